@@ -1,121 +1,563 @@
 #[cfg(feature = "gpio")]
-use rppal::i2c::I2c;
-#[cfg(feature = "gpio")]
+use rppal::gpio::{Gpio, InputPin, Trigger};
+use super::bus::I2cBus;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::sync::{Arc, Mutex, MutexGuard};
 use std::thread;
-#[cfg(feature = "gpio")]
 use std::time::Duration;
 
-/// ADS1115 16-bit ADC I2C address
-#[cfg(feature = "gpio")]
-const ADS1115_ADDRESS: u16 = 0x48;
-
 /// ADS1115 Register addresses
-#[cfg(feature = "gpio")]
 const REG_CONVERSION: u8 = 0x00;
-#[cfg(feature = "gpio")]
 const REG_CONFIG: u8 = 0x01;
+const REG_LO_THRESH: u8 = 0x02;
+const REG_HI_THRESH: u8 = 0x03;
 
-/// Input multiplexer configuration (AINp = A3, AINn = GND)
-#[cfg(feature = "gpio")]
-const MUX_A3_GND: u16 = 0b111 << 12;
+/// Input multiplexer field (bits 14:12)
+const MUX_SHIFT: u16 = 12;
 
-/// Programmable gain amplifier (±4.096V range)
-#[cfg(feature = "gpio")]
-const PGA_4_096V: u16 = 0b001 << 9;
+/// ADS1115 input: one of A0–A3 against GND, or a differential pair (positive first)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Channel {
+    #[serde(rename = "a0")]
+    A0,
+    #[serde(rename = "a1")]
+    A1,
+    #[serde(rename = "a2")]
+    A2,
+    #[serde(rename = "a3")]
+    A3,
+    #[serde(rename = "a0-a1")]
+    A0A1,
+    #[serde(rename = "a0-a3")]
+    A0A3,
+    #[serde(rename = "a1-a3")]
+    A1A3,
+    #[serde(rename = "a2-a3")]
+    A2A3,
+}
 
-/// Operating mode (single-shot)
-#[cfg(feature = "gpio")]
-const MODE_SINGLE: u16 = 0b1 << 8;
+impl Channel {
+    /// MUX[2:0] value from the datasheet
+    pub fn mux(self) -> u16 {
+        match self {
+            Channel::A0A1 => 0b000,
+            Channel::A0A3 => 0b001,
+            Channel::A1A3 => 0b010,
+            Channel::A2A3 => 0b011,
+            Channel::A0 => 0b100,
+            Channel::A1 => 0b101,
+            Channel::A2 => 0b110,
+            Channel::A3 => 0b111,
+        }
+    }
+}
 
-/// Data rate (128 SPS)
-#[cfg(feature = "gpio")]
-const DATA_RATE_128SPS: u16 = 0b100 << 5;
+impl fmt::Display for Channel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Channel::A0 => "A0",
+            Channel::A1 => "A1",
+            Channel::A2 => "A2",
+            Channel::A3 => "A3",
+            Channel::A0A1 => "A0-A1",
+            Channel::A0A3 => "A0-A3",
+            Channel::A1A3 => "A1-A3",
+            Channel::A2A3 => "A2-A3",
+        };
+        write!(f, "{}", s)
+    }
+}
 
-/// Comparator mode (traditional)
-#[cfg(feature = "gpio")]
-const COMP_MODE_TRAD: u16 = 0b0 << 4;
+/// Programmable gain amplifier field (bits 11:9)
+const PGA_SHIFT: u16 = 9;
 
-/// Comparator polarity (active low)
-#[cfg(feature = "gpio")]
-const COMP_POL_LOW: u16 = 0b0 << 3;
+/// Operating mode (single-shot; 0 = continuous)
+const MODE_SINGLE: u16 = 0b1 << 8;
 
-/// Latching comparator (non-latching)
-#[cfg(feature = "gpio")]
-const COMP_LAT_NON: u16 = 0b0 << 2;
+/// Data rate field (bits 7:5)
+const DR_SHIFT: u16 = 5;
+
+/// Comparator mode (traditional; window sets this bit)
+const COMP_MODE_WINDOW: u16 = 0b1 << 4;
+
+/// Latching comparator (cleared by reading the conversion register)
+const COMP_LAT: u16 = 0b1 << 2;
 
 /// Comparator queue (disable)
-#[cfg(feature = "gpio")]
 const COMP_QUE_DIS: u16 = 0b11;
 
 /// Operational status (start single conversion)
-#[cfg(feature = "gpio")]
 const OS_START_SINGLE: u16 = 0b1 << 15;
 
 /// Operational status (conversion ready mask)
-#[cfg(feature = "gpio")]
 const OS_READY: u16 = 0b1 << 15;
 
-pub struct Ads1115 {
+/// Full-scale range of the programmable gain amplifier.
+/// In config: the range in volts, e.g. `gain = 4.096`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(try_from = "f64", into = "f64")]
+pub enum Gain {
+    /// ±6.144 V
+    V6_144,
+    /// ±4.096 V
+    #[default]
+    V4_096,
+    /// ±2.048 V (chip power-on default)
+    V2_048,
+    /// ±1.024 V
+    V1_024,
+    /// ±0.512 V
+    V0_512,
+    /// ±0.256 V
+    V0_256,
+}
+
+impl Gain {
+    const ALL: [Gain; 6] = [Gain::V6_144, Gain::V4_096, Gain::V2_048, Gain::V1_024, Gain::V0_512, Gain::V0_256];
+
+    /// PGA[2:0] value from the datasheet
+    pub fn bits(self) -> u16 {
+        match self {
+            Gain::V6_144 => 0b000,
+            Gain::V4_096 => 0b001,
+            Gain::V2_048 => 0b010,
+            Gain::V1_024 => 0b011,
+            Gain::V0_512 => 0b100,
+            Gain::V0_256 => 0b101,
+        }
+    }
+
+    /// Full-scale voltage (positive side)
+    pub fn full_scale(self) -> f32 {
+        match self {
+            Gain::V6_144 => 6.144,
+            Gain::V4_096 => 4.096,
+            Gain::V2_048 => 2.048,
+            Gain::V1_024 => 1.024,
+            Gain::V0_512 => 0.512,
+            Gain::V0_256 => 0.256,
+        }
+    }
+
+    /// Convert a raw conversion result to volts at this gain
+    pub fn to_volts(self, raw: i16) -> f32 {
+        // One LSB is full scale / 2^15 (0.125 mV at ±4.096 V)
+        raw as f32 * self.full_scale() / 32768.0
+    }
+}
+
+impl TryFrom<f64> for Gain {
+    type Error = String;
+    fn try_from(v: f64) -> Result<Self, Self::Error> {
+        Gain::ALL
+            .into_iter()
+            .find(|g| (g.full_scale() as f64 - v).abs() < 1e-6)
+            .ok_or_else(|| format!("gain {} is not one of 6.144, 4.096, 2.048, 1.024, 0.512, 0.256", v))
+    }
+}
+
+impl From<Gain> for f64 {
+    fn from(g: Gain) -> f64 {
+        // Round-trip through the decimal text so 4.096 stays 4.096 in the file
+        g.full_scale().to_string().parse().unwrap_or_default()
+    }
+}
+
+/// Conversions per second. In config: `data_rate = 128`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(try_from = "u16", into = "u16")]
+pub enum DataRate {
+    Sps8,
+    Sps16,
+    Sps32,
+    Sps64,
+    #[default]
+    Sps128,
+    Sps250,
+    Sps475,
+    Sps860,
+}
+
+impl DataRate {
+    const ALL: [DataRate; 8] = [
+        DataRate::Sps8,
+        DataRate::Sps16,
+        DataRate::Sps32,
+        DataRate::Sps64,
+        DataRate::Sps128,
+        DataRate::Sps250,
+        DataRate::Sps475,
+        DataRate::Sps860,
+    ];
+
+    /// DR[2:0] value from the datasheet
+    pub fn bits(self) -> u16 {
+        self as u16
+    }
+
+    pub fn sps(self) -> u16 {
+        match self {
+            DataRate::Sps8 => 8,
+            DataRate::Sps16 => 16,
+            DataRate::Sps32 => 32,
+            DataRate::Sps64 => 64,
+            DataRate::Sps128 => 128,
+            DataRate::Sps250 => 250,
+            DataRate::Sps475 => 475,
+            DataRate::Sps860 => 860,
+        }
+    }
+
+    /// Nominal time for one conversion
+    pub fn conversion_time(self) -> Duration {
+        Duration::from_micros(1_000_000 / self.sps() as u64)
+    }
+}
+
+impl TryFrom<u16> for DataRate {
+    type Error = String;
+    fn try_from(v: u16) -> Result<Self, Self::Error> {
+        DataRate::ALL
+            .into_iter()
+            .find(|r| r.sps() == v)
+            .ok_or_else(|| format!("data_rate {} is not one of 8, 16, 32, 64, 128, 250, 475, 860", v))
+    }
+}
+
+impl From<DataRate> for u16 {
+    fn from(r: DataRate) -> u16 {
+        r.sps()
+    }
+}
+
+/// I2C address, set by what the ADDR pin is tied to. In config: `address = 0x48`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+#[serde(try_from = "u8", into = "u8")]
+pub enum Address {
+    /// ADDR to GND: 0x48
+    #[default]
+    Gnd,
+    /// ADDR to VDD: 0x49
+    Vdd,
+    /// ADDR to SDA: 0x4A
+    Sda,
+    /// ADDR to SCL: 0x4B
+    Scl,
+}
+
+impl Address {
+    pub fn value(self) -> u8 {
+        0x48 + self as u8
+    }
+}
+
+impl TryFrom<u8> for Address {
+    type Error = String;
+    fn try_from(v: u8) -> Result<Self, Self::Error> {
+        match v {
+            0x48 => Ok(Address::Gnd),
+            0x49 => Ok(Address::Vdd),
+            0x4A => Ok(Address::Sda),
+            0x4B => Ok(Address::Scl),
+            _ => Err(format!("ADS1115 address {:#04x} is not one of 0x48..=0x4B", v)),
+        }
+    }
+}
+
+impl From<Address> for u8 {
+    fn from(a: Address) -> u8 {
+        a.value()
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:#04x}", self.value())
+    }
+}
+
+/// Conversion mode. In config: `mode = "single"` or `mode = "continuous"`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Mode {
+    /// Start a conversion for every read, then power down (lowest chip power)
+    #[default]
+    Single,
+    /// The chip converts on its own; reads just fetch the latest result
+    Continuous,
+}
+
+impl fmt::Display for Mode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Mode::Single => "single-shot",
+            Mode::Continuous => "continuous",
+        })
+    }
+}
+
+/// How many conversions beyond a threshold before ALERT/RDY asserts
+// The firmware itself only uses a window comparator; the rest is driver API
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Queue {
+    One,
+    Two,
+    Four,
+}
+
+/// What the ALERT/RDY pin (open drain, active low) signals.
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Alert {
+    /// Comparator disabled (power-on state)
+    Off,
+    /// Pulse at the end of every conversion
+    ConversionReady,
+    /// Assert once a reading exceeds `high`; release when one falls below `low`
+    Traditional { low: i16, high: i16, latching: bool, queue: Queue },
+    /// Assert while a reading is outside `low..=high`
+    Window { low: i16, high: i16, latching: bool, queue: Queue },
+}
+
+impl Alert {
+    /// COMP_MODE, COMP_LAT and COMP_QUE bits of the config register
+    fn config_bits(self) -> u16 {
+        let queue = |q: Queue| match q {
+            Queue::One => 0b00,
+            Queue::Two => 0b01,
+            Queue::Four => 0b10,
+        };
+        match self {
+            Alert::Off => COMP_QUE_DIS,
+            // Any queue value other than "disable" turns the pin on
+            Alert::ConversionReady => 0b00,
+            Alert::Traditional { latching, queue: q, .. } => (if latching { COMP_LAT } else { 0 }) | queue(q),
+            Alert::Window { latching, queue: q, .. } => COMP_MODE_WINDOW | (if latching { COMP_LAT } else { 0 }) | queue(q),
+        }
+    }
+
+    /// (Lo_thresh, Hi_thresh) register values
+    fn thresholds(self) -> (u16, u16) {
+        match self {
+            // Power-on defaults
+            Alert::Off => (0x8000, 0x7FFF),
+            // MSB of Hi_thresh set and of Lo_thresh clear selects conversion-ready
+            Alert::ConversionReady => (0x0000, 0x8000),
+            Alert::Traditional { low, high, .. } | Alert::Window { low, high, .. } => (low as u16, high as u16),
+        }
+    }
+}
+
+/// Config register value for a conversion of `channel`.
+fn config_word(channel: Channel, gain: Gain, rate: DataRate, mode: Mode, alert: Alert) -> u16 {
+    let mode_bit = match mode {
+        Mode::Single => OS_START_SINGLE | MODE_SINGLE,
+        Mode::Continuous => 0,
+    };
+    mode_bit | (channel.mux() << MUX_SHIFT) | (gain.bits() << PGA_SHIFT) | (rate.bits() << DR_SHIFT) | alert.config_bits()
+}
+
+/// GPIO wired to the ADS1115 ALERT/RDY pin (pulled up; the chip pulls it low).
+/// Shared so a threshold wait doesn't hold the chip's lock.
+pub struct AlertPin {
     #[cfg(feature = "gpio")]
-    i2c: I2c,
-    #[cfg(not(feature = "gpio"))]
-    _phantom: (),
+    input: InputPin,
 }
 
-impl Ads1115 {
-    /// Create a new ADS1115 instance
-    pub fn new() -> Result<Self, Box<dyn std::error::Error>> {
+impl AlertPin {
+    pub fn open(pin: u8) -> Result<Self, Box<dyn std::error::Error>> {
         #[cfg(feature = "gpio")]
         {
-            let mut i2c = I2c::new()?;
-            i2c.set_slave_address(ADS1115_ADDRESS)?;
-            Ok(Self { i2c })
+            let mut input = Gpio::new()?.get(pin)?.into_input_pullup();
+            input.set_interrupt(Trigger::FallingEdge, None)?;
+            Ok(Self { input })
         }
         #[cfg(not(feature = "gpio"))]
         {
-            Err("ADS1115 requires 'gpio' feature to be enabled".into())
+            let _ = pin;
+            Err("ALERT/RDY pin requires 'gpio' feature to be enabled".into())
         }
     }
 
-    /// Read the moisture sensor value from A3 input
-    /// Returns a raw ADC value (0-32767 for positive values)
-    pub fn read_moisture_sensor(&mut self) -> Result<i16, Box<dyn std::error::Error>> {
+    /// Drop edges that happened before now
+    pub fn clear(&mut self) -> Result<(), Box<dyn std::error::Error>> {
         #[cfg(feature = "gpio")]
+        self.input.poll_interrupt(true, Some(Duration::ZERO))?;
+        Ok(())
+    }
+
+    /// Block until the pin asserts; false on timeout
+    pub fn wait(&mut self, timeout: Duration) -> Result<bool, Box<dyn std::error::Error>> {
+        #[cfg(feature = "gpio")]
+        return Ok(self.input.poll_interrupt(false, Some(timeout))?.is_some());
+        #[cfg(not(feature = "gpio"))]
         {
-            self.read_channel_a3()
+            thread::sleep(timeout);
+            Ok(false)
+        }
+    }
+}
+
+/// ADS1115 driver over any `I2cBus`; the Pi's bus by default.
+pub struct Ads1115<B: I2cBus = Box<dyn I2cBus>> {
+    bus: B,
+    address: u8,
+    alert_pin: Option<Arc<Mutex<AlertPin>>>,
+    gain: Gain,
+    data_rate: DataRate,
+    mode: Mode,
+    alert: Alert,
+    // Input the chip is converting in continuous mode
+    active: Option<Channel>,
+}
+
+impl Ads1115 {
+    /// Open the ADS1115 at `address` on the Pi's I2C bus
+    pub fn new(address: Address, gain: Gain, data_rate: DataRate) -> Result<Self, Box<dyn std::error::Error>> {
+        #[cfg(feature = "gpio")]
+        {
+            let bus: Box<dyn I2cBus> = Box::new(rppal::i2c::I2c::new()?);
+            Ok(Self::with_bus(bus, address, gain, data_rate))
         }
         #[cfg(not(feature = "gpio"))]
         {
-            Err("GPIO feature not enabled".into())
+            let _ = (address, gain, data_rate);
+            Err("ADS1115 requires 'gpio' feature to be enabled".into())
         }
     }
 
-    /// Read from A3 channel (single-ended measurement against GND)
-    #[cfg(feature = "gpio")]
-    pub fn read_channel_a3(&mut self) -> Result<i16, Box<dyn std::error::Error>> {
-        // Configure ADC for A3 single-ended measurement
-        let config = OS_START_SINGLE
-            | MUX_A3_GND
-            | PGA_4_096V
-            | MODE_SINGLE
-            | DATA_RATE_128SPS
-            | COMP_MODE_TRAD
-            | COMP_POL_LOW
-            | COMP_LAT_NON
-            | COMP_QUE_DIS;
-
-        // Write configuration register
-        self.write_register(REG_CONFIG, config)?;
-
-        // Wait for conversion to complete (at 128 SPS, ~8ms)
-        thread::sleep(Duration::from_millis(10));
+    /// Convert raw ADC value to moisture percentage estimate
+    /// Note: Calibration values will need to be adjusted based on your specific sensor
+    /// Typical ranges: ~13000-15000 (dry in air) to ~26000-28000 (in water)
+    pub fn raw_to_moisture_percent(raw: i16, dry_value: i16, wet_value: i16) -> f32 {
+        if wet_value == dry_value {
+            return 0.0;
+        }
+        
+        // Widened first: differential readings span the full i16 range
+        let percentage = ((raw as i32 - dry_value as i32) as f32 / (wet_value as i32 - dry_value as i32) as f32) * 100.0;
+        percentage.clamp(0.0, 100.0)
+    }
+}
+
+impl<B: I2cBus> Ads1115<B> {
+    /// Use the ADS1115 at `address` on `bus`
+    pub fn with_bus(bus: B, address: Address, gain: Gain, data_rate: DataRate) -> Self {
+        Self { bus, address: address.value(), alert_pin: None, gain, data_rate, mode: Mode::Single, alert: Alert::Off, active: None }
+    }
+
+    /// Switch between single-shot and continuous conversion.
+    pub fn set_mode(&mut self, mode: Mode) -> Result<(), Box<dyn std::error::Error>> {
+        self.mode = mode;
+        if let (Mode::Single, Some(ch)) = (mode, self.active.take()) {
+            // One last single-shot conversion returns the chip to power-down
+            self.write_register(REG_CONFIG, config_word(ch, self.gain, self.data_rate, mode, self.alert))?;
+        }
+        Ok(())
+    }
+
+    /// Program the comparator / ALERT/RDY function.
+    /// Thresholds are raw values at the current gain.
+    pub fn set_alert(&mut self, alert: Alert) -> Result<(), Box<dyn std::error::Error>> {
+        let (lo, hi) = alert.thresholds();
+        self.write_register(REG_LO_THRESH, lo)?;
+        self.write_register(REG_HI_THRESH, hi)?;
+        self.alert = alert;
+        self.clear_alert()?;
+        if let Some(ch) = self.active {
+            self.write_register(REG_CONFIG, config_word(ch, self.gain, self.data_rate, self.mode, alert))?;
+        }
+        Ok(())
+    }
+
+    /// Use the ALERT/RDY pin to wake up instead of sleeping and polling.
+    /// Enables conversion-ready signalling if no comparator is set.
+    pub fn attach_alert_pin(&mut self, pin: AlertPin) -> Result<(), Box<dyn std::error::Error>> {
+        self.alert_pin = Some(Arc::new(Mutex::new(pin)));
+        if self.alert == Alert::Off {
+            self.set_alert(Alert::ConversionReady)?;
+        }
+        Ok(())
+    }
+
+    /// Start converting `channel` continuously and leave the chip running.
+    pub fn start_continuous(&mut self, channel: Channel) -> Result<(), Box<dyn std::error::Error>> {
+        self.mode = Mode::Continuous;
+        self.write_register(REG_CONFIG, config_word(channel, self.gain, self.data_rate, self.mode, self.alert))?;
+        self.active = Some(channel);
+        Ok(())
+    }
+
+    /// Read one input.
+    /// Single-ended inputs return 0..=32767; differential pairs may be negative.
+    pub fn read_channel(&mut self, channel: Channel) -> Result<i16, Box<dyn std::error::Error>> {
+        match self.mode {
+            Mode::Single => {
+                self.clear_alert()?;
+                // Configure ADC for a single conversion on the requested input
+                self.write_register(REG_CONFIG, config_word(channel, self.gain, self.data_rate, self.mode, self.alert))?;
+                self.wait_conversion(true)?;
+            }
+            Mode::Continuous => {
+                // Already converting this input: the latest result is ready to read
+                if self.active != Some(channel) {
+                    self.clear_alert()?;
+                    self.start_continuous(channel)?;
+                    self.wait_conversion(false)?;
+                }
+            }
+        }
+
+        // Read conversion result
+        let raw = self.read_register(REG_CONVERSION)?;
+        Ok(raw as i16)
+    }
+
+    pub fn alert(&self) -> Alert {
+        self.alert
+    }
+
+    /// The ALERT/RDY pin, for waiting on comparator events without holding the chip.
+    /// Only set in continuous mode: single-shot conversions stop between reads.
+    pub fn alert_pin(&self) -> Option<Arc<Mutex<AlertPin>>> {
+        self.alert_pin.clone().filter(|_| self.mode == Mode::Continuous)
+    }
+
+    fn clear_alert(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        if let Some(pin) = &self.alert_pin {
+            lock(pin).clear()?;
+        }
+        Ok(())
+    }
+
+    // Wait for the conversion just started: on the RDY edge if we have one,
+    // otherwise sleep for one conversion period (and poll OS in single-shot).
+    fn wait_conversion(&mut self, poll_os: bool) -> Result<(), Box<dyn std::error::Error>> {
+        // ~8ms at 128 SPS, 125ms at 8 SPS
+        let period = self.data_rate.conversion_time() + Duration::from_millis(1);
+
+        if self.alert == Alert::ConversionReady {
+            if let Some(pin) = &self.alert_pin {
+                return match lock(pin).wait(period * 2 + Duration::from_millis(10))? {
+                    true => Ok(()),
+                    false => Err("Timeout waiting for conversion".into()),
+                };
+            }
+        }
+
+        thread::sleep(period);
+        if !poll_os {
+            return Ok(());
+        }
 
         // Wait for conversion ready
         let mut attempts = 0;
         loop {
             let config_read = self.read_register(REG_CONFIG)?;
             if (config_read & OS_READY) != 0 {
-                break;
+                return Ok(());
             }
             thread::sleep(Duration::from_millis(1));
             attempts += 1;
@@ -123,61 +565,65 @@ impl Ads1115 {
                 return Err("Timeout waiting for conversion".into());
             }
         }
-
-        // Read conversion result
-        let raw = self.read_register(REG_CONVERSION)?;
-        Ok(raw as i16)
     }
 
-    /// Convert raw ADC value to voltage (±4.096V range)
-    pub fn raw_to_voltage(raw: i16) -> f32 {
-        // For ±4.096V range: LSB = 0.125 mV
-        (raw as f32) * 0.000125
-    }
-
-    /// Convert raw ADC value to moisture percentage estimate
-    /// Note: Calibration values will need to be adjusted based on your specific sensor
-    /// Typical ranges: ~13000-15000 (dry in air) to ~26000-28000 (in water)
-    pub fn raw_to_moisture_percent(raw: i16, dry_value: i16, wet_value: i16) -> f32 {
-        if wet_value == dry_value {
-            return 0.0;
-        }
-        
-        let percentage = ((raw - dry_value) as f32 / (wet_value - dry_value) as f32) * 100.0;
-        percentage.clamp(0.0, 100.0)
+    /// Convert raw ADC value to voltage at the configured gain
+    pub fn raw_to_voltage(&self, raw: i16) -> f32 {
+        self.gain.to_volts(raw)
     }
 
     /// Write to a 16-bit register
-    #[cfg(feature = "gpio")]
     fn write_register(&mut self, register: u8, value: u16) -> Result<(), Box<dyn std::error::Error>> {
         let bytes = [
             register,
             (value >> 8) as u8,  // MSB
             (value & 0xFF) as u8, // LSB
         ];
-        self.i2c.write(&bytes)?;
-        Ok(())
+        self.bus.write(self.address, &bytes)
     }
 
     /// Read from a 16-bit register
-    #[cfg(feature = "gpio")]
     fn read_register(&mut self, register: u8) -> Result<u16, Box<dyn std::error::Error>> {
-        self.i2c.write(&[register])?;
         let mut buffer = [0u8; 2];
-        self.i2c.read(&mut buffer)?;
+        self.bus.write_read(self.address, &[register], &mut buffer)?;
         Ok(((buffer[0] as u16) << 8) | (buffer[1] as u16))
     }
 }
 
+fn lock<T>(m: &Mutex<T>) -> MutexGuard<'_, T> {
+    m.lock().unwrap_or_else(|e| e.into_inner())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::analog::fake::FakeBus;
 
     #[test]
     fn test_raw_to_voltage() {
-        assert_eq!(Ads1115::raw_to_voltage(0), 0.0);
-        assert!((Ads1115::raw_to_voltage(32767) - 4.096).abs() < 0.001);
-        assert!((Ads1115::raw_to_voltage(-32768) - (-4.096)).abs() < 0.001);
+        let g = Gain::V4_096;
+        assert_eq!(g.to_volts(0), 0.0);
+        assert!((g.to_volts(32767) - 4.096).abs() < 0.001);
+        assert!((g.to_volts(-32768) - (-4.096)).abs() < 0.001);
+        // ±0.256 V: 7.8125 µV per LSB
+        assert!((Gain::V0_256.to_volts(16384) - 0.128).abs() < 1e-6);
+    }
+
+    #[test]
+    fn gain_rate_address_from_config_values() {
+        #[derive(Deserialize, Serialize)]
+        struct C {
+            gain: Gain,
+            data_rate: DataRate,
+            address: Address,
+        }
+        let c: C = toml::from_str("gain = 0.256\ndata_rate = 860\naddress = 0x4B").unwrap();
+        assert_eq!((c.gain, c.data_rate, c.address), (Gain::V0_256, DataRate::Sps860, Address::Scl));
+        assert_eq!((c.gain.bits(), c.data_rate.bits(), c.address.value()), (0b101, 0b111, 0x4B));
+        assert!(toml::to_string(&c).unwrap().contains("gain = 0.256"));
+        assert!(toml::from_str::<C>("gain = 3.3\ndata_rate = 128\naddress = 0x48").is_err());
+        assert!(toml::from_str::<C>("gain = 4.096\ndata_rate = 100\naddress = 0x48").is_err());
+        assert!(toml::from_str::<C>("gain = 4.096\ndata_rate = 128\naddress = 0x50").is_err());
     }
 
     #[test]
@@ -190,13 +636,98 @@ mod tests {
         assert!((Ads1115::raw_to_moisture_percent(21000, dry, wet) - 50.0).abs() < 0.1);
     }
 
+    #[test]
+    fn moisture_percent_spans_differential_range() {
+        let (dry, wet) = (-30000, 30000);
+        assert_eq!(Ads1115::raw_to_moisture_percent(30000, dry, wet), 100.0);
+        assert_eq!(Ads1115::raw_to_moisture_percent(0, dry, wet), 50.0);
+        assert_eq!(Ads1115::raw_to_moisture_percent(-30000, wet, dry), 100.0);
+    }
+
+    #[test]
+    fn channel_mux_and_names() {
+        assert_eq!(Channel::A3.mux(), 0b111);
+        assert_eq!(Channel::A0A1.mux(), 0b000);
+        let c: Channel = toml::from_str::<toml::Value>("c = \"a2-a3\"").unwrap()["c"].clone().try_into().unwrap();
+        assert_eq!(c, Channel::A2A3);
+        assert_eq!(c.to_string(), "A2-A3");
+    }
+
+    #[test]
+    fn config_word_and_comparator_registers() {
+        let (g, r) = (Gain::V4_096, DataRate::Sps128);
+        // OS + A3 + ±4.096V + single-shot + 128 SPS + comparator off
+        assert_eq!(config_word(Channel::A3, g, r, Mode::Single, Alert::Off), 0xF383);
+        assert_eq!(config_word(Channel::A0, g, r, Mode::Continuous, Alert::ConversionReady), 0x4280);
+        assert_eq!(Alert::ConversionReady.thresholds(), (0x0000, 0x8000));
+
+        let window = Alert::Window { low: -2, high: 20000, latching: true, queue: Queue::Four };
+        assert_eq!(window.config_bits(), 0b1_0110);
+        assert_eq!(window.thresholds(), (0xFFFE, 20000));
+        let traditional = Alert::Traditional { low: 100, high: 200, latching: false, queue: Queue::One };
+        assert_eq!(traditional.config_bits(), 0);
+        assert_eq!(Alert::Off.thresholds(), (0x8000, 0x7FFF));
+    }
+
+    fn fake(rate: DataRate) -> (FakeBus, Ads1115<FakeBus>) {
+        let bus = FakeBus::new(0x48);
+        (bus.clone(), Ads1115::with_bus(bus, Address::Gnd, Gain::V4_096, rate))
+    }
+
+    #[test]
+    fn single_shot_writes_config_and_polls_os_ready() {
+        let (bus, mut adc) = fake(DataRate::Sps860);
+        bus.set_input(Channel::A3.mux(), 21000);
+        bus.set_busy_reads(3);
+        assert_eq!(adc.read_channel(Channel::A3).unwrap(), 21000);
+        assert_eq!(bus.writes(), vec![(REG_CONFIG, 0xF3E3)]);
+
+        bus.set_input(Channel::A0A1.mux(), -1200);
+        assert_eq!(adc.read_channel(Channel::A0A1).unwrap(), -1200);
+        assert_eq!(bus.writes()[1], (REG_CONFIG, 0x83E3));
+    }
+
+    #[test]
+    fn single_shot_times_out_when_never_ready() {
+        let (bus, mut adc) = fake(DataRate::Sps860);
+        bus.set_stuck(true);
+        let err = adc.read_channel(Channel::A0).unwrap_err();
+        assert!(err.to_string().contains("Timeout"));
+    }
+
+    #[test]
+    fn continuous_mode_configures_once_per_input() {
+        let (bus, mut adc) = fake(DataRate::Sps860);
+        adc.set_mode(Mode::Continuous).unwrap();
+        bus.set_input(Channel::A2.mux(), 100);
+        assert_eq!(adc.read_channel(Channel::A2).unwrap(), 100);
+        bus.set_input(Channel::A2.mux(), 150);
+        assert_eq!(adc.read_channel(Channel::A2).unwrap(), 150);
+        assert_eq!(bus.writes(), vec![(REG_CONFIG, 0x62E3)]);
+
+        let window = Alert::Window { low: 0, high: 500, latching: false, queue: Queue::Two };
+        adc.set_alert(window).unwrap();
+        assert_eq!((bus.register(REG_LO_THRESH), bus.register(REG_HI_THRESH)), (0, 500));
+        assert_eq!(bus.register(REG_CONFIG), 0x62F1);
+        // Back to single-shot powers the chip down after one last conversion
+        adc.set_mode(Mode::Single).unwrap();
+        assert_eq!(bus.writes().last(), Some(&(REG_CONFIG, 0xE3F1)));
+        assert!(adc.alert_pin().is_none());
+    }
+
+    #[test]
+    fn wrong_address_is_an_error() {
+        let mut adc = Ads1115::with_bus(FakeBus::new(0x48), Address::Vdd, Gain::V4_096, DataRate::Sps128);
+        assert!(adc.read_channel(Channel::A0).unwrap_err().to_string().contains("0x49"));
+    }
+
     #[test]
     #[ignore]
     fn test_read_sensor() {
         // This test requires actual hardware
-        let mut ads = Ads1115::new().expect("Failed to create ADS1115");
-        let raw = ads.read_moisture_sensor().expect("Failed to read sensor");
+        let mut ads = Ads1115::new(Address::Gnd, Gain::V4_096, DataRate::Sps128).expect("Failed to create ADS1115");
+        let raw = ads.read_channel(Channel::A3).expect("Failed to read sensor");
         println!("Raw value: {}", raw);
-        println!("Voltage: {:.3}V", Ads1115::raw_to_voltage(raw));
+        println!("Voltage: {:.3}V", ads.raw_to_voltage(raw));
     }
 }
@@ -0,0 +1,35 @@
+// I2C access for the ADC driver. The Pi bus comes from rppal; see `fake` for an
+// in-memory ADS1115.
+
+use std::error::Error;
+
+/// Minimal I2C master: what the ADS1115 driver needs, addressed per transfer
+/// like the `embedded-hal` I2C trait so other boards can plug in their own bus.
+pub trait I2cBus: Send {
+    fn write(&mut self, address: u8, bytes: &[u8]) -> Result<(), Box<dyn Error>>;
+    /// Write `bytes`, then read `buffer.len()` bytes back in the same transaction
+    fn write_read(&mut self, address: u8, bytes: &[u8], buffer: &mut [u8]) -> Result<(), Box<dyn Error>>;
+}
+
+impl I2cBus for Box<dyn I2cBus> {
+    fn write(&mut self, address: u8, bytes: &[u8]) -> Result<(), Box<dyn Error>> {
+        (**self).write(address, bytes)
+    }
+    fn write_read(&mut self, address: u8, bytes: &[u8], buffer: &mut [u8]) -> Result<(), Box<dyn Error>> {
+        (**self).write_read(address, bytes, buffer)
+    }
+}
+
+#[cfg(feature = "gpio")]
+impl I2cBus for rppal::i2c::I2c {
+    fn write(&mut self, address: u8, bytes: &[u8]) -> Result<(), Box<dyn Error>> {
+        self.set_slave_address(address as u16)?;
+        rppal::i2c::I2c::write(self, bytes)?;
+        Ok(())
+    }
+    fn write_read(&mut self, address: u8, bytes: &[u8], buffer: &mut [u8]) -> Result<(), Box<dyn Error>> {
+        self.set_slave_address(address as u16)?;
+        rppal::i2c::I2c::write_read(self, bytes, buffer)?;
+        Ok(())
+    }
+}
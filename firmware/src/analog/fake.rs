@@ -0,0 +1,157 @@
+// In-memory ADS1115 on a fake I2C bus, so the driver's register logic runs
+// (and is tested) without hardware.
+
+use super::bus::I2cBus;
+use std::error::Error;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+const REG_CONVERSION: usize = 0;
+const REG_CONFIG: usize = 1;
+const OS: u16 = 1 << 15;
+const MODE_SINGLE: u16 = 1 << 8;
+
+/// In-memory ADS1115. Clones share the same chip, so a test can keep one handle
+/// to set inputs and inspect writes after giving another to the driver.
+#[derive(Clone)]
+pub struct FakeBus(Arc<Mutex<FakeChip>>);
+
+struct FakeChip {
+    address: u8,
+    // Conversion, Config, Lo_thresh, Hi_thresh
+    regs: [u16; 4],
+    pointer: usize,
+    // Raw result per mux setting (config bits 14:12)
+    inputs: [i16; 8],
+    // Config reads that still report a conversion in progress
+    busy_reads: u32,
+    busy_left: u32,
+    // Never finish a conversion
+    stuck: bool,
+    writes: Vec<(u8, u16)>,
+}
+
+impl FakeBus {
+    /// A chip at `address` with its power-on register values
+    pub fn new(address: u8) -> Self {
+        Self(Arc::new(Mutex::new(FakeChip {
+            address,
+            regs: [0x0000, 0x8583, 0x8000, 0x7FFF],
+            pointer: 0,
+            inputs: [0; 8],
+            busy_reads: 0,
+            busy_left: 0,
+            stuck: false,
+            writes: Vec::new(),
+        })))
+    }
+
+    /// Value the next conversion with this mux setting returns
+    pub fn set_input(&self, mux: u16, raw: i16) {
+        let mut chip = self.lock();
+        chip.inputs[(mux & 0b111) as usize] = raw;
+        // A running continuous conversion picks the new value up
+        if chip.regs[REG_CONFIG] & MODE_SINGLE == 0 && ((chip.regs[REG_CONFIG] >> 12) & 0b111) == mux & 0b111 {
+            chip.regs[REG_CONVERSION] = raw as u16;
+        }
+    }
+
+    /// Report "busy" on this many config reads after each single-shot start
+    #[cfg(test)]
+    pub fn set_busy_reads(&self, n: u32) {
+        self.lock().busy_reads = n;
+    }
+
+    /// Make single-shot conversions never complete
+    #[cfg(test)]
+    pub fn set_stuck(&self, stuck: bool) {
+        self.lock().stuck = stuck;
+    }
+
+    #[cfg(test)]
+    pub fn register(&self, reg: u8) -> u16 {
+        self.lock().regs[reg as usize & 3]
+    }
+
+    /// Every register write so far as (register, value)
+    #[cfg(test)]
+    pub fn writes(&self) -> Vec<(u8, u16)> {
+        self.lock().writes.clone()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, FakeChip> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl FakeChip {
+    fn check(&self, address: u8) -> Result<(), Box<dyn Error>> {
+        if address != self.address {
+            return Err(format!("no ACK from 0x{:02X}", address).into());
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> Result<(), Box<dyn Error>> {
+        let (&pointer, data) = bytes.split_first().ok_or("empty I2C write")?;
+        if pointer > 3 {
+            return Err(format!("invalid register 0x{:02X}", pointer).into());
+        }
+        self.pointer = pointer as usize;
+        match data {
+            [] => Ok(()),
+            [msb, lsb] => {
+                let value = u16::from_be_bytes([*msb, *lsb]);
+                self.writes.push((pointer, value));
+                match self.pointer {
+                    REG_CONVERSION => {} // read-only
+                    REG_CONFIG => self.configure(value),
+                    p => self.regs[p] = value,
+                }
+                Ok(())
+            }
+            _ => Err("register writes take two bytes".into()),
+        }
+    }
+
+    fn configure(&mut self, value: u16) {
+        let single = value & MODE_SINGLE != 0;
+        let start = !single || value & OS != 0;
+        // OS reads 1 when idle; writing 1 in single-shot starts a conversion
+        self.regs[REG_CONFIG] = (value & !OS) | if start { 0 } else { OS };
+        if start {
+            self.regs[REG_CONVERSION] = self.inputs[((value >> 12) & 0b111) as usize] as u16;
+            self.busy_left = self.busy_reads;
+        }
+    }
+
+    fn read(&mut self, buffer: &mut [u8]) -> Result<(), Box<dyn Error>> {
+        let mut value = self.regs[self.pointer];
+        if self.pointer == REG_CONFIG && value & MODE_SINGLE != 0 && value & OS == 0 {
+            if self.busy_left == 0 && !self.stuck {
+                self.regs[REG_CONFIG] |= OS;
+                value |= OS;
+            } else {
+                self.busy_left = self.busy_left.saturating_sub(1);
+            }
+        }
+        let bytes = value.to_be_bytes();
+        for (b, v) in buffer.iter_mut().zip(bytes) {
+            *b = v;
+        }
+        Ok(())
+    }
+}
+
+impl I2cBus for FakeBus {
+    fn write(&mut self, address: u8, bytes: &[u8]) -> Result<(), Box<dyn Error>> {
+        let mut chip = self.lock();
+        chip.check(address)?;
+        chip.write(bytes)
+    }
+    fn write_read(&mut self, address: u8, bytes: &[u8], buffer: &mut [u8]) -> Result<(), Box<dyn Error>> {
+        let mut chip = self.lock();
+        chip.check(address)?;
+        chip.write(bytes)?;
+        chip.read(buffer)
+    }
+}
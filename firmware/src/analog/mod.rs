@@ -1,3 +1,7 @@
 pub mod ads1115;
+pub mod bus;
+pub mod fake;
+pub mod probe;
 
-pub use ads1115::Ads1115;
+pub use ads1115::{Ads1115, Channel};
+pub use probe::MoistureProbe;
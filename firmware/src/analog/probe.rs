@@ -0,0 +1,177 @@
+use super::ads1115::{Address, Alert, AlertPin, Queue};
+use super::bus::I2cBus;
+use super::{Ads1115, Channel};
+use crate::config::{AdcConfig, SensorConfig};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+/// One moisture sample in every unit we display or log.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Reading {
+    pub raw: i16,
+    pub voltage: f32,
+    /// `None` until the probe is calibrated
+    pub percent: Option<f32>,
+}
+
+/// One named moisture sensor: an ADS1115 input together with its dry/wet calibration.
+/// Shared by the UI, the watering controller and the data logger; sensors on the
+/// same chip share one ADC handle so conversions never interleave.
+pub struct MoistureProbe {
+    name: String,
+    channel: Channel,
+    adc: Arc<Mutex<Ads1115>>,
+    calibration: Mutex<Option<(i16, i16)>>,
+    // Only sensor on its chip, so the comparator can watch it
+    exclusive: bool,
+}
+
+impl MoistureProbe {
+    pub fn new(name: &str, channel: Channel, adc: Arc<Mutex<Ads1115>>, calibration: Option<(i16, i16)>) -> Self {
+        Self { name: name.to_string(), channel, adc, calibration: Mutex::new(calibration), exclusive: false }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn channel(&self) -> Channel {
+        self.channel
+    }
+
+    /// Blocking read; waits if another thread is mid-conversion.
+    pub fn read(&self) -> Result<Reading, Box<dyn Error>> {
+        let mut adc = lock(&self.adc);
+        let raw = adc.read_channel(self.channel)?;
+        Ok(self.reading(raw, adc.raw_to_voltage(raw)))
+    }
+
+    /// Non-blocking read for the UI; `None` if the ADC is busy.
+    pub fn try_read(&self) -> Option<Result<Reading, Box<dyn Error>>> {
+        let mut adc = self.adc.try_lock().ok()?;
+        Some(adc.read_channel(self.channel).map(|raw| self.reading(raw, adc.raw_to_voltage(raw))))
+    }
+
+    /// Calibration as (dry, wet) raw values.
+    pub fn calibration(&self) -> Option<(i16, i16)> {
+        *lock(&self.calibration)
+    }
+
+    pub fn set_calibration(&self, calibration: Option<(i16, i16)>) {
+        *lock(&self.calibration) = calibration;
+    }
+
+    /// Sleep until the soil is drier than `percent` or `timeout` passes, woken by
+    /// the ADS1115 comparator instead of polling. Returns `None` when the hardware
+    /// can't: no ALERT/RDY pin, single-shot mode, a shared chip or no calibration.
+    pub fn wait_dry(&self, percent: f32, timeout: Duration) -> Option<Result<bool, Box<dyn Error>>> {
+        let (dry, wet) = self.calibration().filter(|(d, w)| d != w)?;
+        if !self.exclusive {
+            return None;
+        }
+        let pin = lock(&self.adc).alert_pin()?;
+        let armed = (|| -> Result<bool, Box<dyn Error>> {
+            let mut adc = lock(&self.adc);
+            let alert = dry_alert(percent, dry, wet);
+            if adc.alert() != alert {
+                adc.read_channel(self.channel)?;
+                adc.set_alert(alert)?;
+            }
+            // Already dry: the pin only signals the crossing
+            let raw = adc.read_channel(self.channel)?;
+            Ok(Ads1115::raw_to_moisture_percent(raw, dry, wet) < percent)
+        })();
+        Some(match armed {
+            Ok(false) => lock(&pin).wait(timeout),
+            other => other,
+        })
+    }
+
+    fn reading(&self, raw: i16, voltage: f32) -> Reading {
+        let percent = match self.calibration() {
+            Some((dry, wet)) if dry != wet => Some(Ads1115::raw_to_moisture_percent(raw, dry, wet)),
+            _ => None,
+        };
+        Reading { raw, voltage, percent }
+    }
+}
+
+/// Open each ADS1115 the sensors use and one probe per sensor; sensors on the
+/// same chip share its handle.
+pub fn open_all(adc: &AdcConfig, sensors: &[SensorConfig]) -> Result<Vec<Arc<MoistureProbe>>, Box<dyn Error>> {
+    let mut chips: HashMap<Address, Arc<Mutex<Ads1115>>> = HashMap::new();
+    let mut out = Vec::new();
+    for s in sensors {
+        let address = s.address.unwrap_or(adc.address);
+        let chip = match chips.get(&address) {
+            Some(c) => Arc::clone(c),
+            None => {
+                let c = open_chip(adc, address).map_err(|e| format!("ADS1115 at {}: {}", address, e))?;
+                let c = Arc::new(Mutex::new(c));
+                chips.insert(address, Arc::clone(&c));
+                c
+            }
+        };
+        // The sim reports percentages even before anyone calibrates
+        let cal = s.calibration().or(crate::sim::global().map(|_| crate::sim::DEFAULT_CALIBRATION));
+        out.push(MoistureProbe::new(&s.name, s.channel, chip, cal));
+    }
+    // One reference from `chips`, one from the probe
+    for p in &mut out {
+        p.exclusive = Arc::strong_count(&p.adc) == 2;
+    }
+    Ok(out.into_iter().map(Arc::new).collect())
+}
+
+fn open_chip(adc: &AdcConfig, address: Address) -> Result<Ads1115, Box<dyn Error>> {
+    if let Some(sim) = crate::sim::global() {
+        let mut chip = Ads1115::with_bus(Box::new(sim.bus(address)) as Box<dyn I2cBus>, address, adc.gain, adc.data_rate);
+        chip.set_mode(adc.mode)?;
+        return Ok(chip);
+    }
+    let mut chip = Ads1115::new(address, adc.gain, adc.data_rate)?;
+    chip.set_mode(adc.mode)?;
+    // ALERT/RDY is wired from the default chip only
+    if let Some(pin) = adc.alert_pin.filter(|_| address == adc.address) {
+        chip.attach_alert_pin(AlertPin::open(pin)?)?;
+    }
+    Ok(chip)
+}
+
+// Comparator window that asserts once moisture drops below `percent`. Raw values
+// grow towards whichever end `dry` is at, so the window is open on the wet side.
+fn dry_alert(percent: f32, dry: i16, wet: i16) -> Alert {
+    let edge = (dry as f32 + (wet as f32 - dry as f32) * percent.clamp(0.0, 100.0) / 100.0).round() as i16;
+    let (low, high) = if dry > wet { (i16::MIN, edge) } else { (edge, i16::MAX) };
+    // Two conversions past the edge, so one noisy sample doesn't wake us
+    Alert::Window { low, high, latching: false, queue: Queue::Two }
+}
+
+/// Look a sensor up by name.
+pub fn find<'a>(sensors: &'a [Arc<MoistureProbe>], name: &str) -> Option<&'a Arc<MoistureProbe>> {
+    sensors.iter().find(|p| p.name() == name)
+}
+
+fn lock<T>(m: &Mutex<T>) -> MutexGuard<'_, T> {
+    m.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dry_alert_opens_on_the_wet_side() {
+        // Capacitive probes read higher when dry
+        let Alert::Window { low, high, .. } = dry_alert(30.0, 26000, 12000) else { panic!() };
+        assert_eq!((low, high), (i16::MIN, 21800));
+        let Alert::Window { low, high, .. } = dry_alert(30.0, 12000, 26000) else { panic!() };
+        assert_eq!((low, high), (16200, i16::MAX));
+        // A differential channel can span more than half the i16 range
+        let Alert::Window { low, high, .. } = dry_alert(50.0, -30000, 30000) else { panic!() };
+        assert_eq!((low, high), (0, i16::MAX));
+    }
+}
@@ -0,0 +1,348 @@
+// Local HTTP/JSON API (feature `http`): read status from a phone on the LAN and
+// make the same changes the TUI can. Every POST needs `Authorization: Bearer <token>`.
+//
+//   GET  /api/status                         sensors, controllers, schedule (active rule set), zones, faults
+//   GET  /api/config                         current config (token redacted)
+//   POST /api/blink        {"on": true}
+//   POST /api/interval     {"ms": 500}
+//   POST /api/water        {"secs": 10, "zone": "basil"}
+//                          timed manual watering; zone defaults to the first one
+//   POST /api/calibration  {"sensor": "basil", "dry": 26000, "wet": 12000}
+//                          sensor defaults to the first one; dry/wet either or both
+
+use crate::analog::probe::{self, MoistureProbe};
+use crate::config::{self, AppConfig};
+use crate::gpio::manual;
+use crate::gpio::shutdown::{self, WorkerHandle};
+use crate::gpio::{safety, GpioController, ScheduleRppalGpioController};
+use crate::zone::{self, Zone};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::error::Error;
+use std::io::Read;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+use tiny_http::{Header, Request, Response, Server};
+
+pub struct Api {
+    ctl: Arc<dyn GpioController + Send + Sync>,
+    sensors: Vec<Arc<MoistureProbe>>,
+    zones: Vec<Arc<Zone>>,
+    schedule: Option<Arc<ScheduleRppalGpioController>>,
+    cfg: Mutex<AppConfig>,
+}
+
+pub struct Reply {
+    pub status: u16,
+    pub body: Value,
+}
+
+impl Reply {
+    fn ok(body: Value) -> Self {
+        Self { status: 200, body }
+    }
+
+    fn error(status: u16, msg: impl ToString) -> Self {
+        Self { status, body: json!({ "error": msg.to_string() }) }
+    }
+}
+
+#[derive(Deserialize)]
+struct BlinkReq {
+    on: bool,
+}
+
+#[derive(Deserialize)]
+struct IntervalReq {
+    ms: u64,
+}
+
+#[derive(Deserialize)]
+struct WaterReq {
+    secs: u64,
+    zone: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct CalibrationReq {
+    sensor: Option<String>,
+    dry: Option<i16>,
+    wet: Option<i16>,
+}
+
+impl Api {
+    pub fn new(
+        ctl: Arc<dyn GpioController + Send + Sync>,
+        cfg: AppConfig,
+        sensors: Vec<Arc<MoistureProbe>>,
+        zones: Vec<Arc<Zone>>,
+        schedule: Option<Arc<ScheduleRppalGpioController>>,
+    ) -> Self {
+        Self { ctl, sensors, zones, schedule, cfg: Mutex::new(cfg) }
+    }
+
+    /// Route one request. `auth` is the raw `Authorization` header, if any.
+    pub fn handle(&self, method: &str, path: &str, auth: Option<&str>, body: &str) -> Reply {
+        let path = path.split('?').next().unwrap_or(path);
+        match (method, path) {
+            ("GET", "/api/status") => Reply::ok(self.status()),
+            ("GET", "/api/config") => {
+                // Public route: mask the secrets
+                let mut cfg = lock(&self.cfg).clone();
+                for secret in [&mut cfg.http.token, &mut cfg.mqtt.password] {
+                    if secret.is_some() {
+                        *secret = Some("********".to_string());
+                    }
+                }
+                match serde_json::to_value(&cfg) {
+                    Ok(v) => Reply::ok(v),
+                    Err(e) => Reply::error(500, e),
+                }
+            }
+            ("POST", _) => {
+                if let Err(r) = self.authorize(auth) {
+                    return r;
+                }
+                let result = match path {
+                    "/api/blink" => parse(body).and_then(|r: BlinkReq| self.set_blink(r.on)),
+                    "/api/interval" => parse(body).and_then(|r: IntervalReq| self.set_interval(r.ms)),
+                    "/api/water" => parse(body).and_then(|r: WaterReq| self.water(r.secs, r.zone.as_deref())),
+                    "/api/calibration" => parse(body).and_then(|r: CalibrationReq| self.calibrate(r)),
+                    _ => Err(Reply::error(404, "not found")),
+                };
+                result.unwrap_or_else(|r| r)
+            }
+            ("GET", _) => Reply::error(404, "not found"),
+            _ => Reply::error(405, "method not allowed"),
+        }
+    }
+
+    fn authorize(&self, auth: Option<&str>) -> Result<(), Reply> {
+        let cfg = lock(&self.cfg);
+        let Some(token) = cfg.http.token.as_deref().filter(|t| !t.is_empty()) else {
+            return Err(Reply::error(403, "no [http] token configured; the API is read-only"));
+        };
+        match auth.and_then(|a| a.strip_prefix("Bearer ")) {
+            Some(given) if token_eq(given.trim(), token) => Ok(()),
+            _ => Err(Reply::error(401, "missing or wrong bearer token")),
+        }
+    }
+
+    fn status(&self) -> Value {
+        let sensors: Vec<Value> = self
+            .sensors
+            .iter()
+            .map(|p| {
+                let mut v = match p.read() {
+                    Ok(r) => json!(r),
+                    Err(e) => json!({ "error": e.to_string() }),
+                };
+                v["name"] = json!(p.name());
+                v["channel"] = json!(p.channel());
+                v
+            })
+            .collect();
+        let zones: Vec<Value> = self
+            .zones
+            .iter()
+            .map(|z| {
+                json!({
+                    "name": z.name,
+                    "sensor": z.sensor,
+                    "watering": z.watering.as_ref().map(|w| {
+                        json!({ "pin": w.pin(), "enabled": w.is_blink(), "state": w.state().to_string() })
+                    }),
+                    "light": z.light.as_ref().map(|l| {
+                        json!({ "pin": z.light_pin, "enabled": l.is_blink(), "on": z.light_on(), "rule_set": l.rule_set() })
+                    }),
+                })
+            })
+            .collect();
+        let faults: Vec<Value> = safety::global()
+            .faults()
+            .into_iter()
+            .map(|(pin, f)| json!({ "pin": pin, "fault": f.to_string() }))
+            .collect();
+        let cfg = lock(&self.cfg);
+        json!({
+            "sensors": sensors,
+            "controller": {
+                "pin": cfg.gpio_pin,
+                "is_blink": self.ctl.is_blink(),
+                "interval_ms": self.ctl.interval_ms(),
+            },
+            "schedule": {
+                "pin": cfg.schedule_pin,
+                "enabled": self.schedule.as_ref().map(|s| s.is_blink()),
+                "rule_set": self.schedule.as_ref().map(|s| s.rule_set()),
+                "days": cfg.schedule,
+                "rules": cfg.schedule_rules,
+                "overrides": cfg.schedule_overrides,
+            },
+            "zones": zones,
+            "faults": faults,
+        })
+    }
+
+    fn set_blink(&self, on: bool) -> Result<Reply, Reply> {
+        self.ctl.set_blink(on);
+        self.persist(|c| c.blink_on = on)?;
+        Ok(Reply::ok(json!({ "is_blink": on })))
+    }
+
+    fn set_interval(&self, ms: u64) -> Result<Reply, Reply> {
+        // Same bounds as the TUI's +/- keys
+        if !(50..=10_000).contains(&ms) {
+            return Err(Reply::error(400, "ms must be between 50 and 10000"));
+        }
+        self.ctl.set_interval_ms(ms);
+        self.persist(|c| c.interval_ms = ms)?;
+        Ok(Reply::ok(json!({ "interval_ms": ms })))
+    }
+
+    fn water(&self, secs: u64, zone: Option<&str>) -> Result<Reply, Reply> {
+        let z = zone::find(&self.zones, zone).ok_or_else(|| match zone {
+            Some(_) => Reply::error(404, "no such zone"),
+            None => Reply::error(409, "watering is not configured"),
+        })?;
+        let dose = z.dose.as_ref().ok_or_else(|| Reply::error(409, format!("zone '{}' has no moisture sensor", z.name)))?;
+        if !(1..=manual::MAX_SECS).contains(&secs) {
+            return Err(Reply::error(400, format!("secs must be between 1 and {}", manual::MAX_SECS)));
+        }
+        dose.start(secs, "api").map_err(|e| Reply::error(409, e))?;
+        Ok(Reply::ok(json!({ "zone": z.name, "pin": z.pump_pin, "secs": secs })))
+    }
+
+    fn calibrate(&self, req: CalibrationReq) -> Result<Reply, Reply> {
+        if req.dry.is_none() && req.wet.is_none() {
+            return Err(Reply::error(400, "give dry, wet or both"));
+        }
+        let current = {
+            let list = lock(&self.cfg).sensor_list();
+            match &req.sensor {
+                Some(name) => list.into_iter().find(|s| &s.name == name),
+                None => list.into_iter().next(),
+            }
+        };
+        let Some(current) = current else {
+            return Err(Reply::error(404, "no such sensor"));
+        };
+        let (dry, wet) = (req.dry.or(current.dry), req.wet.or(current.wet));
+        if dry.is_some() && dry == wet {
+            return Err(Reply::error(400, "dry and wet must differ"));
+        }
+        if let Some(p) = probe::find(&self.sensors, &current.name) {
+            p.set_calibration(dry.zip(wet));
+        }
+        self.persist(|c| {
+            c.set_calibration(&current.name, req.dry, req.wet);
+        })?;
+        Ok(Reply::ok(json!({ "sensor": current.name, "dry": dry, "wet": wet })))
+    }
+
+    fn persist(&self, f: impl Fn(&mut AppConfig)) -> Result<(), Reply> {
+        f(&mut lock(&self.cfg));
+        config::update_config(f).map(|_| ()).map_err(|e| Reply::error(500, format!("config not saved: {}", e)))
+    }
+}
+
+fn parse<T: for<'de> Deserialize<'de>>(body: &str) -> Result<T, Reply> {
+    serde_json::from_str(body).map_err(|e| Reply::error(400, e))
+}
+
+// Compare without bailing out at the first differing byte
+fn token_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len() && a.bytes().zip(b.bytes()).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+fn lock<T>(m: &Mutex<T>) -> MutexGuard<'_, T> {
+    m.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Serve the API on `cfg.http.bind` until shutdown.
+pub fn start(api: Api, bind: &str) -> Result<WorkerHandle, Box<dyn Error>> {
+    let server = Server::http(bind).map_err(|e| format!("cannot listen on {}: {}", bind, e))?;
+    println!("[api] listening on http://{}", bind);
+    Ok(shutdown::global().spawn("http-api", move |stop| {
+        while !stop.is_set() {
+            match server.recv_timeout(Duration::from_millis(250)) {
+                Ok(Some(req)) => serve(&api, req),
+                Ok(None) => {}
+                Err(e) => {
+                    println!("[api] accept error: {}", e);
+                    if !stop.wait(Duration::from_secs(1)) {
+                        break;
+                    }
+                }
+            }
+        }
+    }))
+}
+
+fn serve(api: &Api, mut req: Request) {
+    let auth = req
+        .headers()
+        .iter()
+        .find(|h| h.field.equiv("Authorization"))
+        .map(|h| h.value.as_str().to_string());
+    let mut body = String::new();
+    // Bodies are tiny JSON objects; anything larger is not for us
+    let _ = req.as_reader().take(4096).read_to_string(&mut body);
+    let reply = api.handle(req.method().as_str(), req.url(), auth.as_deref(), &body);
+    let mut resp = Response::from_string(reply.body.to_string()).with_status_code(reply.status);
+    if let Ok(h) = Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..]) {
+        resp = resp.with_header(h);
+    }
+    let _ = req.respond(resp);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gpio::fake::FakeCtl;
+
+    fn new_api(token: Option<&str>) -> (Api, Arc<FakeCtl>) {
+        let ctl = Arc::new(FakeCtl::default());
+        let mut cfg = AppConfig::default();
+        cfg.http.token = token.map(str::to_string);
+        cfg.mqtt.password = Some("broker-secret".to_string());
+        (Api::new(ctl.clone(), cfg, Vec::new(), Vec::new(), None), ctl)
+    }
+
+    #[test]
+    fn status_is_public_and_token_is_redacted() {
+        let (api, _) = new_api(Some("secret"));
+        let r = api.handle("GET", "/api/status", None, "");
+        assert_eq!(r.status, 200);
+        assert_eq!(r.body["sensors"], json!([]));
+        assert_eq!(r.body["controller"]["interval_ms"], 0);
+        let r = api.handle("GET", "/api/config", None, "");
+        assert_eq!(r.body["http"]["token"], "********");
+        assert_eq!(r.body["mqtt"]["password"], "********");
+    }
+
+    #[test]
+    fn posts_need_the_right_token() {
+        let (api, ctl) = new_api(Some("secret"));
+        assert_eq!(api.handle("POST", "/api/interval", None, r#"{"ms":500}"#).status, 401);
+        assert_eq!(api.handle("POST", "/api/interval", Some("Bearer nope"), r#"{"ms":500}"#).status, 401);
+        assert_eq!(api.handle("POST", "/api/interval", Some("Bearer secret"), r#"{"ms":5}"#).status, 400);
+        assert_eq!(api.handle("POST", "/api/interval", Some("Bearer secret"), "oops").status, 400);
+        assert_eq!(ctl.interval_ms(), 0);
+
+        let (api, _) = new_api(None);
+        assert_eq!(api.handle("POST", "/api/blink", Some("Bearer "), r#"{"on":true}"#).status, 403);
+    }
+
+    #[test]
+    fn water_and_calibration_reject_bad_requests() {
+        let (api, _) = new_api(Some("secret"));
+        let auth = Some("Bearer secret");
+        assert_eq!(api.handle("POST", "/api/water", auth, r#"{"secs":10}"#).status, 409);
+        assert_eq!(api.handle("POST", "/api/water", auth, r#"{"secs":10,"zone":"basil"}"#).status, 404);
+        assert_eq!(api.handle("POST", "/api/calibration", auth, "{}").status, 400);
+        assert_eq!(api.handle("POST", "/api/calibration", auth, r#"{"dry":5,"wet":5}"#).status, 400);
+        assert_eq!(api.handle("POST", "/api/calibration", auth, r#"{"sensor":"nope","dry":5}"#).status, 404);
+        assert_eq!(api.handle("DELETE", "/api/status", auth, "").status, 405);
+    }
+}
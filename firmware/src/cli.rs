@@ -0,0 +1,285 @@
+// Command-line interface. With no subcommand the firmware runs the TUI, as before.
+
+use crate::analog::probe::{self, MoistureProbe};
+use crate::config::{self, AppConfig};
+use crate::migrate;
+use crate::gpio::registry::{self, Priority};
+use crate::gpio::{safety, shutdown};
+use clap::{Parser, Subcommand, ValueEnum};
+use std::error::Error;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Parser)]
+#[command(version, about = "Plant watering firmware for the Raspberry Pi")]
+pub struct Cli {
+    /// Use this config file instead of searching for one
+    ///
+    /// Without it: $PLANTS_CONFIG, then the first that exists of
+    /// ~/.config/plants-love-rust/config.toml, /etc/plants-love-rust/config.toml
+    /// and config/config.toml next to the executable.
+    #[arg(long, global = true, value_name = "PATH")]
+    pub config: Option<PathBuf>,
+
+    /// Simulated plants and virtual pins instead of the hardware (same as [sim] enabled)
+    #[arg(long, global = true)]
+    pub sim: bool,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Run the controllers with the terminal UI (default)
+    Run,
+    /// Run the controllers headless, e.g. as a systemd service
+    Daemon {
+        /// Write the PID here while running
+        #[arg(long, value_name = "PATH")]
+        pid_file: Option<PathBuf>,
+    },
+    /// Read the moisture sensors once and print the result
+    ReadSensor {
+        /// Only this sensor (default: all)
+        #[arg(long, value_name = "NAME")]
+        sensor: Option<String>,
+        /// Print JSON instead of text
+        #[arg(long)]
+        json: bool,
+    },
+    /// Drive one output; `on` is held until --hold expires or Ctrl-C, then made safe
+    SetPin {
+        pin: u8,
+        state: PinState,
+        /// Seconds to hold the pin on (default: until interrupted)
+        #[arg(long, value_name = "SECS")]
+        hold: Option<u64>,
+    },
+    /// Show, check or edit the config file
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction,
+    },
+    /// Store the current sensor reading as the dry or wet calibration point
+    Calibrate {
+        point: CalPoint,
+        /// Sensor to calibrate (default: the first one)
+        #[arg(long, value_name = "NAME")]
+        sensor: Option<String>,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ConfigAction {
+    /// Print the effective config as TOML
+    Show,
+    /// Parse and check the config file without changing it
+    Validate,
+    /// Change one value, e.g. `interval_ms=250` or `safety.max_on_secs=600`
+    Set {
+        #[arg(value_name = "KEY=VALUE")]
+        assignment: String,
+    },
+    /// List the saved previous versions of the config file
+    Backups,
+    /// Replace the config file with a previous version (1 = most recent)
+    Restore {
+        #[arg(default_value_t = 1)]
+        backup: u32,
+    },
+    /// Upgrade an older config file to the current schema (also done at startup)
+    Migrate {
+        /// Show the changes without writing the file
+        #[arg(long)]
+        dry_run: bool,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum PinState {
+    On,
+    Off,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum CalPoint {
+    Dry,
+    Wet,
+}
+
+pub fn read_sensor(cfg: &AppConfig, sensor: Option<&str>, json: bool) -> Result<(), Box<dyn Error>> {
+    let probes = open_probes(cfg, sensor)?;
+    let mut readings = Vec::new();
+    for p in &probes {
+        let r = p.read()?;
+        if json {
+            let mut v = serde_json::to_value(r)?;
+            v["name"] = p.name().into();
+            readings.push(v);
+        } else {
+            let pct = r.percent.map(|p| format!("{:.1}%", p)).unwrap_or_else(|| "uncalibrated".to_string());
+            println!("{} ({}): raw={} v={:.3} {}", p.name(), p.channel(), r.raw, r.voltage, pct);
+        }
+    }
+    if json {
+        println!("{}", serde_json::Value::Array(readings));
+    }
+    Ok(())
+}
+
+pub fn set_pin(cfg: &AppConfig, pin: u8, state: PinState, hold: Option<u64>) -> Result<(), Box<dyn Error>> {
+    // The usual safety limits still apply while we hold a pump pin
+    safety::global().configure(safety::Limits::from(&cfg.safety), cfg.pump_pins());
+    let _watchdog = safety::start_watchdog(false);
+    let claim = registry::global().claim(pin, cfg.invert, "cli", Priority::Manual)?;
+
+    if state == PinState::Off {
+        claim.set(false);
+        println!("GPIO {} off", pin);
+        return Ok(());
+    }
+
+    claim.set(true);
+    let until = hold.map(|s| Instant::now() + Duration::from_secs(s));
+    match hold {
+        Some(s) => println!("GPIO {} on for {}s (Ctrl-C to stop early)", pin, s),
+        None => println!("GPIO {} on until Ctrl-C", pin),
+    }
+    while !shutdown::global().is_requested() && until.is_none_or(|t| Instant::now() < t) {
+        if let Some((_, fault)) = safety::global().faults().into_iter().find(|(p, _)| *p == pin) {
+            return Err(format!("GPIO {} forced off: {}", pin, fault).into());
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    }
+    println!("GPIO {} off", pin);
+    Ok(())
+}
+
+pub fn config(cfg: AppConfig, action: ConfigAction) -> Result<(), Box<dyn Error>> {
+    match action {
+        ConfigAction::Show => {
+            if let Some((p, source)) = config::config_location() {
+                println!("# {} ({})", p.display(), source);
+            }
+            for name in config::env_overrides() {
+                println!("# {} overrides the file", name);
+            }
+            print!("{}", toml::to_string_pretty(&cfg)?);
+        }
+        ConfigAction::Validate => {
+            let path = config::config_path().ok_or("no config path")?;
+            let problems = match config::check_file(&path) {
+                Ok(parsed) => config::with_env(parsed).err().into_iter().collect(),
+                Err(problems) => problems,
+            };
+            if !problems.is_empty() {
+                for p in &problems {
+                    eprintln!("{}", p);
+                }
+                return Err(format!("{} problem(s) found", problems.len()).into());
+            }
+            println!("{}: ok", path.display());
+            if let Ok(Some((m, _))) = config::migrate_file(&path, false) {
+                println!("version {} will be migrated to {} at the next start (or run `config migrate`)", m.from, migrate::CURRENT_VERSION);
+            }
+        }
+        ConfigAction::Set { assignment } => {
+            // Edit what the file says, not `cfg`, so PLANTS_* overrides aren't saved
+            let path = config::config_path().ok_or("no config path")?;
+            let file = if path.exists() { config::read_config(&path)? } else { AppConfig::default() };
+            let updated = config::set_value(&file, &assignment)?;
+            let problems = config::validate(&updated);
+            if !problems.is_empty() {
+                return Err(problems.iter().map(|p| p.to_string()).collect::<Vec<_>>().join("; ").into());
+            }
+            config::save_config(&updated)?;
+            println!("saved {}", assignment.trim());
+        }
+        ConfigAction::Backups => {
+            let path = config::config_path().ok_or("no config path")?;
+            let list = config::backups(&path);
+            if list.is_empty() {
+                println!("no backups of {}", path.display());
+            }
+            for (n, p) in list {
+                let modified = fs::metadata(&p)?.modified()?;
+                println!("{}  {}  {}", n, chrono::DateTime::<chrono::Local>::from(modified).format("%Y-%m-%d %H:%M:%S"), p.display());
+            }
+        }
+        ConfigAction::Restore { backup } => {
+            let path = config::config_path().ok_or("no config path")?;
+            config::restore_backup(&path, backup)?;
+            println!("restored {} from backup {} (the replaced file is now backup 1)", path.display(), backup);
+        }
+        ConfigAction::Migrate { dry_run } => {
+            let path = config::config_path().ok_or("no config path")?;
+            let Some((m, diff)) = config::migrate_file(&path, !dry_run)? else {
+                println!("{}: already version {}", path.display(), migrate::CURRENT_VERSION);
+                return Ok(());
+            };
+            for line in m.notes.iter().chain(&diff) {
+                println!("{}", line);
+            }
+            if dry_run {
+                println!("{}: would migrate from version {} to {}", path.display(), m.from, migrate::CURRENT_VERSION);
+            } else {
+                println!("{}: migrated from version {} to {} (the old file is backup 1)", path.display(), m.from, migrate::CURRENT_VERSION);
+            }
+        }
+    }
+    Ok(())
+}
+
+pub fn calibrate(cfg: AppConfig, point: CalPoint, sensor: Option<&str>) -> Result<(), Box<dyn Error>> {
+    let probes = open_probes(&cfg, sensor)?;
+    let p = probes.first().ok_or("no sensors configured")?;
+    let raw = p.read()?.raw;
+    let (dry, wet) = if point == CalPoint::Dry { (Some(raw), None) } else { (None, Some(raw)) };
+    config::update_config(|c| {
+        c.set_calibration(p.name(), dry, wet);
+    })?;
+    println!(
+        "{}: {} calibration set to raw={}",
+        p.name(),
+        if point == CalPoint::Dry { "dry" } else { "wet" },
+        raw
+    );
+    Ok(())
+}
+
+// All configured sensors, or just the named one
+fn open_probes(cfg: &AppConfig, only: Option<&str>) -> Result<Vec<Arc<MoistureProbe>>, Box<dyn Error>> {
+    let mut list = cfg.sensor_list();
+    if let Some(name) = only {
+        list.retain(|s| s.name == name);
+        if list.is_empty() {
+            return Err(format!("no sensor named '{}'", name).into());
+        }
+    }
+    probe::open_all(&cfg.adc, &list)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+
+    #[test]
+    fn cli_definition_is_consistent() {
+        Cli::command().debug_assert();
+    }
+
+    #[test]
+    fn parses_subcommands() {
+        let cli = Cli::try_parse_from(["fw", "--config", "/tmp/c.toml", "set-pin", "22", "on", "--hold", "5"]).unwrap();
+        assert_eq!(cli.config, Some(PathBuf::from("/tmp/c.toml")));
+        assert!(matches!(cli.command, Some(Command::SetPin { pin: 22, state: PinState::On, hold: Some(5) })));
+        let cli = Cli::try_parse_from(["fw", "config", "set", "interval_ms=250"]).unwrap();
+        assert!(matches!(cli.command, Some(Command::Config { action: ConfigAction::Set { .. } })));
+        assert!(Cli::try_parse_from(["fw", "calibrate", "soggy"]).is_err());
+        assert!(Cli::try_parse_from(["fw"]).unwrap().command.is_none());
+    }
+}
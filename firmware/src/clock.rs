@@ -0,0 +1,137 @@
+// Time source for everything that runs on a timetable or measures durations.
+// The real clock in production, an accelerated one in `sim` mode, and a fixed
+// one that tests move by hand (midnight, day changes, DST jumps).
+
+use chrono::{DateTime, Local, NaiveDateTime, Utc};
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock};
+use std::time::{Duration, Instant};
+
+pub trait Clock: Send + Sync {
+    /// Local wall-clock time, as schedules see it.
+    fn now(&self) -> NaiveDateTime;
+    /// Monotonic time for measuring durations (doses, limits, intervals).
+    fn instant(&self) -> Instant;
+    /// Real time to sleep for `d` to pass on this clock.
+    fn wall(&self, d: Duration) -> Duration {
+        d
+    }
+}
+
+/// The system clock.
+pub struct RealClock;
+
+impl Clock for RealClock {
+    fn now(&self) -> NaiveDateTime {
+        Local::now().naive_local()
+    }
+    fn instant(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// Starts at the real time and runs `speed` times faster; DST follows the local zone.
+pub struct AcceleratedClock {
+    speed: f64,
+    start: Instant,
+    start_utc: DateTime<Utc>,
+}
+
+impl AcceleratedClock {
+    pub fn new(speed: f64) -> Self {
+        Self { speed, start: Instant::now(), start_utc: Utc::now() }
+    }
+
+    fn elapsed(&self) -> Duration {
+        self.start.elapsed().mul_f64(self.speed)
+    }
+}
+
+impl Clock for AcceleratedClock {
+    fn now(&self) -> NaiveDateTime {
+        let elapsed = chrono::Duration::from_std(self.elapsed()).unwrap_or(chrono::Duration::MAX);
+        (self.start_utc + elapsed).with_timezone(&Local).naive_local()
+    }
+    fn instant(&self) -> Instant {
+        self.start + self.elapsed()
+    }
+    fn wall(&self, d: Duration) -> Duration {
+        d.div_f64(self.speed)
+    }
+}
+
+/// Stands still until moved. `advance` moves both readings; `set` only the wall
+/// clock, like a DST change or an NTP correction.
+pub struct FixedClock {
+    state: Mutex<(NaiveDateTime, Instant)>,
+}
+
+#[cfg_attr(not(test), allow(dead_code))]
+impl FixedClock {
+    pub fn new(now: NaiveDateTime) -> Self {
+        Self { state: Mutex::new((now, Instant::now())) }
+    }
+
+    pub fn set(&self, now: NaiveDateTime) {
+        lock(&self.state).0 = now;
+    }
+
+    pub fn advance(&self, d: Duration) {
+        let mut s = lock(&self.state);
+        s.0 += chrono::Duration::from_std(d).unwrap_or(chrono::Duration::MAX);
+        s.1 += d;
+    }
+}
+
+impl Clock for FixedClock {
+    fn now(&self) -> NaiveDateTime {
+        lock(&self.state).0
+    }
+    fn instant(&self) -> Instant {
+        lock(&self.state).1
+    }
+}
+
+static GLOBAL: OnceLock<Arc<dyn Clock>> = OnceLock::new();
+
+/// Use `clock` process-wide. Call before any controller starts; later calls are ignored.
+pub fn init(clock: Arc<dyn Clock>) {
+    let _ = GLOBAL.set(clock);
+}
+
+/// The process-wide clock; the system clock unless `init` chose another.
+pub fn global() -> Arc<dyn Clock> {
+    Arc::clone(GLOBAL.get_or_init(|| Arc::new(RealClock)))
+}
+
+fn lock<T>(m: &Mutex<T>) -> MutexGuard<'_, T> {
+    m.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    #[test]
+    fn fixed_clock_moves_only_when_told() {
+        let t0 = NaiveDate::from_ymd_opt(2024, 3, 31).unwrap().and_hms_opt(1, 59, 0).unwrap();
+        let c = FixedClock::new(t0);
+        let i0 = c.instant();
+        c.advance(Duration::from_secs(60));
+        assert_eq!(c.now(), t0 + chrono::Duration::minutes(1));
+        // Spring forward: the wall clock jumps, monotonic time doesn't
+        c.set(t0 + chrono::Duration::minutes(61));
+        assert_eq!(c.instant() - i0, Duration::from_secs(60));
+        assert_eq!(c.wall(Duration::from_secs(5)), Duration::from_secs(5));
+    }
+
+    #[test]
+    fn accelerated_clock_runs_fast() {
+        let c = AcceleratedClock::new(3600.0);
+        assert_eq!(c.wall(Duration::from_secs(3600)), Duration::from_secs(1));
+        let (i0, t0) = (c.instant(), c.now());
+        std::thread::sleep(Duration::from_millis(20));
+        assert!(c.instant() - i0 >= Duration::from_secs(72));
+        assert!(c.now() - t0 >= chrono::Duration::seconds(72));
+    }
+}
@@ -1,84 +1,1304 @@
+use crate::analog::ads1115::{Address, Channel, DataRate, Gain, Mode};
+use crate::gpio::MIN_SAMPLE_MS;
+use crate::gpio::rules::{self, Rule, TimeOfDay};
+use crate::migrate::{self, Migrated, CURRENT_VERSION};
 use serde::{Deserialize, Serialize};
 use std::fs;
-use std::io;
+use std::fmt;
+use std::io::{self, Write};
 use std::path::{Path, PathBuf};
 use std::env;
 use std::collections::HashMap;
+use std::sync::OnceLock;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(default)]
 pub struct AppConfig {
+    // Schema version; older files are upgraded on load (see `migrate`)
+    pub version: u32,
     pub blink_on: bool,
     pub interval_ms: u64,
     pub gpio_pin: u8,
     pub invert: bool,
     // Optional schedule loaded from config: map of day name -> list of (start,end) HHMM
+    // ranges and timed runs
     // Example (TOML):
     // [schedule]
     // Monday = [[900,1700]]
     // Sat = [[800,1200],[1300,1500]]
-    pub schedule: Option<HashMap<String, Vec<(u16, u16)>>>,
+    // Sun = [{ at = "07:30:00", secs = 45 }]
+    // Fri = [{ from = "sunrise-30m", to = "sunset+2h" }]
+    pub schedule: Option<HashMap<String, Vec<ScheduleEntry>>>,
+    // Where the plants are, for sunrise/sunset entries (degrees, north and east positive)
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+    // Cron expressions and "every N hours from T" on the schedule pin, on top of
+    // the weekday table; each fires a run of `secs` seconds
+    // Example (TOML):
+    // [[schedule_rules]]
+    // cron = "0 7,19 * * *"
+    // secs = 45
+    // [[schedule_rules]]
+    // every_hours = 6
+    // start = "06:00"
+    // secs = 30
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub schedule_rules: Vec<ScheduleRule>,
+    // Date ranges that switch the schedule pin to another rule set, applied in
+    // order on the days they cover: `schedule`/`rules` replace the set, `scale`
+    // stretches every range and run, `skip` turns it off.
+    // Example (TOML):
+    // [[schedule_overrides]]
+    // name = "vacation"
+    // from = "2026-12-20"
+    // to = "2027-01-03"
+    // skip = true
+    // [[schedule_overrides]]
+    // name = "summer"     # every year
+    // from = "06-01"
+    // to = "08-31"
+    // schedule = { Monday = [[600, 700]] }
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub schedule_overrides: Vec<ScheduleOverride>,
     // Pin to use for schedule controller (if schedule is provided)
     pub schedule_pin: u8,
-    // Moisture sensor calibration values
-    pub moisture_dry_value: Option<i16>,
-    pub moisture_wet_value: Option<i16>,
+    // Named sensors, one per ADS1115 input
+    // Example (TOML):
+    // [[sensors]]
+    // name = "basil"
+    // channel = "a0"      # a0..a3, or a0-a1 / a0-a3 / a1-a3 / a2-a3 differential
+    // dry = 26000
+    // wet = 12000
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub sensors: Vec<SensorConfig>,
+    // ADS1115 settings shared by every sensor
+    // Example (TOML):
+    // [adc]
+    // address = 0x48      # 0x48..0x4B, set by the ADDR pin
+    // gain = 4.096        # full-scale volts: 6.144, 4.096, 2.048, 1.024, 0.512, 0.256
+    // data_rate = 128     # samples/s: 8, 16, 32, 64, 128, 250, 475, 860
+    // mode = "continuous" # or "single" (default)
+    // alert_pin = 17      # GPIO wired to ALERT/RDY
+    pub adc: AdcConfig,
+    // Run-time limits enforced on every pump output (see gpio::safety)
+    // Example (TOML):
+    // [safety]
+    // max_on_secs = 600
+    // min_off_secs = 60
+    // daily_on_secs = 3600
+    pub safety: SafetyConfig,
+    // Closed-loop watering from the moisture sensor; absent = disabled
+    // Example (TOML):
+    // [watering]
+    // pin = 22
+    // low_percent = 30.0
+    // high_percent = 60.0
+    pub watering: Option<WateringConfig>,
+    // Named plants or beds, each with its own sensor, pump and optional grow
+    // light; use these instead of [watering] to look after more than one plant
+    // Example (TOML):
+    // [[zones]]
+    // name = "basil"
+    // sensor = "basil"    # a [[sensors]] name, one zone each
+    // pump_pin = 22
+    // light_pin = 5       # on during `schedule`/`schedule_rules`
+    // low_percent = 30.0
+    // high_percent = 60.0
+    // schedule = { Mon = [[600, 2000]] }
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub zones: Vec<ZoneConfig>,
+    // On-disk history of moisture samples and pin changes (see datalog)
+    pub logging: LoggingConfig,
+    // Local HTTP/JSON API; only used when built with the `http` feature
+    // Example (TOML):
+    // [http]
+    // enabled = true
+    // bind = "0.0.0.0:8080"
+    // token = "change-me"
+    pub http: HttpConfig,
+    // MQTT publishing and commands; only used when built with the `mqtt` feature
+    // Example (TOML):
+    // [mqtt]
+    // enabled = true
+    // host = "homeassistant.local"
+    pub mqtt: MqttConfig,
+    // Simulated plants instead of the ADS1115 and GPIO (also `--sim`)
+    // Example (TOML):
+    // [sim]
+    // enabled = true
+    // speed = 60.0        # simulated seconds per real second
+    pub sim: SimConfig,
+}
+
+/// Sensor name used for the legacy single-probe setup.
+pub const DEFAULT_SENSOR: &str = "moisture";
+
+impl AppConfig {
+    /// Configured sensors; without `[[sensors]]`, the original single probe on
+    /// A3, not yet calibrated.
+    pub fn sensor_list(&self) -> Vec<SensorConfig> {
+        if !self.sensors.is_empty() {
+            return self.sensors.clone();
+        }
+        vec![SensorConfig { name: DEFAULT_SENSOR.to_string(), channel: Channel::A3, address: None, dry: None, wet: None }]
+    }
+
+    /// Configured zones; without `[[zones]]`, `[watering]` as one zone named
+    /// after its sensor. The schedule pin is not part of any zone.
+    pub fn zone_list(&self) -> Vec<ZoneConfig> {
+        if !self.zones.is_empty() {
+            return self.zones.clone();
+        }
+        let Some(w) = &self.watering else {
+            return Vec::new();
+        };
+        let sensor = w.sensor.clone().or_else(|| self.sensor_list().into_iter().next().map(|s| s.name)).unwrap_or_default();
+        vec![ZoneConfig {
+            name: sensor.clone(),
+            sensor,
+            pump_pin: w.pin,
+            light_pin: None,
+            low_percent: w.low_percent,
+            high_percent: w.high_percent,
+            max_dose_secs: w.max_dose_secs,
+            soak_secs: w.soak_secs,
+            sample_ms: w.sample_ms,
+            schedule: None,
+            schedule_rules: Vec::new(),
+        }]
+    }
+
+    /// Outputs that water a plant: each zone's pump. `[safety]` limits apply to these.
+    pub fn pump_pins(&self) -> Vec<u8> {
+        self.zone_list().iter().map(|z| z.pump_pin).collect()
+    }
+
+    /// Whether the schedule pin has anything to do.
+    pub fn has_schedule(&self) -> bool {
+        self.schedule.is_some() || !self.schedule_rules.is_empty() || !self.schedule_overrides.is_empty()
+    }
+
+    /// Store calibration for sensor `name`; `None` leaves that point unchanged.
+    /// Returns false if there is no such sensor.
+    pub fn set_calibration(&mut self, name: &str, dry: Option<i16>, wet: Option<i16>) -> bool {
+        // Calibrating the implicit probe writes it out as a [[sensors]] entry
+        if self.sensors.is_empty() && name == DEFAULT_SENSOR {
+            self.sensors = self.sensor_list();
+        }
+        match self.sensors.iter_mut().find(|s| s.name == name) {
+            Some(s) => {
+                s.dry = dry.or(s.dry);
+                s.wet = wet.or(s.wet);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SensorConfig {
+    pub name: String,
+    pub channel: Channel,
+    // Second ADS1115 on the same bus; unset = [adc] address
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub address: Option<Address>,
+    // Raw readings in dry soil and in water
+    #[serde(default)]
+    pub dry: Option<i16>,
+    #[serde(default)]
+    pub wet: Option<i16>,
+}
+
+impl SensorConfig {
+    pub fn calibration(&self) -> Option<(i16, i16)> {
+        self.dry.zip(self.wet)
+    }
+}
+
+/// One `[schedule]` entry.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ScheduleEntry {
+    // On from start to end, HHMM
+    Range(u16, u16),
+    // On at `at` ("HH:MM:SS" or "HH:MM") for `secs` seconds
+    Run { at: String, secs: u32 },
+    // On from `from` to `to`, each "sunrise"/"sunset" with an optional offset
+    // ("sunrise-30m", "sunset+2h") or "HH:MM"; needs latitude/longitude
+    Sun { from: String, to: String },
+}
+
+/// One `[[schedule_rules]]` entry; see `gpio::rules` for what is accepted.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ScheduleRule {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cron: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub every_hours: Option<u32>,
+    // "HH:MM" or "HH:MM:SS" for `every_hours`; default midnight
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub start: Option<String>,
+    pub secs: u32,
+}
+
+/// One `[[schedule_overrides]]` entry.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ScheduleOverride {
+    // Shown as the active rule set
+    pub name: String,
+    // "YYYY-MM-DD", or "MM-DD" for every year; both days included
+    pub from: String,
+    pub to: String,
+    // Nothing runs (vacation)
+    pub skip: bool,
+    // Multiplies the length of every range and run, e.g. 2.0 during a heatwave
+    pub scale: f64,
+    // Replace [schedule] and [[schedule_rules]] while active
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub schedule: Option<HashMap<String, Vec<ScheduleEntry>>>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub rules: Vec<ScheduleRule>,
+}
+
+impl Default for ScheduleOverride {
+    fn default() -> Self {
+        Self { name: String::new(), from: String::new(), to: String::new(), skip: false, scale: 1.0, schedule: None, rules: Vec::new() }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AdcConfig {
+    pub address: Address,
+    pub gain: Gain,
+    pub data_rate: DataRate,
+    // "single" starts a conversion per read; "continuous" lets the chip sample on its own
+    pub mode: Mode,
+    // GPIO wired to ALERT/RDY: wake on conversion-ready and moisture thresholds
+    pub alert_pin: Option<u8>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SafetyConfig {
+    // Longest continuous ON time per output; unset = no limit
+    pub max_on_secs: Option<u64>,
+    // Minimum OFF time before an output may switch ON again
+    pub min_off_secs: Option<u64>,
+    // Total ON time allowed per output per calendar day
+    pub daily_on_secs: Option<u64>,
+    // Feed /dev/watchdog so the Pi reboots if the firmware hangs or is killed
+    pub hardware_watchdog: bool,
 }
 
 impl Default for AppConfig {
     fn default() -> Self {
         Self {
+            version: CURRENT_VERSION,
             blink_on: true,
             interval_ms: 1000,
             gpio_pin: 17,
             invert: false,
             schedule: None,
+            latitude: None,
+            longitude: None,
+            schedule_rules: Vec::new(),
+            schedule_overrides: Vec::new(),
             schedule_pin: 27,
-            moisture_dry_value: None,
-            moisture_wet_value: None,
+            sensors: Vec::new(),
+            adc: AdcConfig::default(),
+            safety: SafetyConfig::default(),
+            watering: None,
+            zones: Vec::new(),
+            logging: LoggingConfig::default(),
+            http: HttpConfig::default(),
+            mqtt: MqttConfig::default(),
+            sim: SimConfig::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LoggingConfig {
+    pub enabled: bool,
+    // How often the moisture sensor is sampled into the log
+    pub sample_secs: u64,
+    // Rotate plants.log once it would exceed this size, keeping `keep_files` old logs
+    pub max_bytes: u64,
+    pub keep_files: u32,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        Self { enabled: true, sample_secs: 60, max_bytes: 1024 * 1024, keep_files: 3 }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+#[cfg_attr(not(feature = "http"), allow(dead_code))]
+pub struct HttpConfig {
+    pub enabled: bool,
+    // Address to listen on; the default only accepts connections from the Pi itself
+    pub bind: String,
+    // Bearer token required for POSTs; unset = the API is read-only
+    pub token: Option<String>,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        Self { enabled: false, bind: "127.0.0.1:8080".to_string(), token: None }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+#[cfg_attr(not(feature = "mqtt"), allow(dead_code))]
+pub struct MqttConfig {
+    pub enabled: bool,
+    pub host: String,
+    pub port: u16,
+    // Also the Home Assistant node id, so keep it unique per Pi
+    pub client_id: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    // State and command topics live under <base_topic>/<client_id>/
+    pub base_topic: String,
+    pub discovery_prefix: String,
+    // How often the moisture reading is published; pin changes go out immediately
+    pub publish_secs: u64,
+}
+
+impl Default for MqttConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            host: "localhost".to_string(),
+            port: 1883,
+            client_id: "plants-love-rust".to_string(),
+            username: None,
+            password: None,
+            base_topic: "plants".to_string(),
+            discovery_prefix: "homeassistant".to_string(),
+            publish_secs: 30,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SimConfig {
+    pub enabled: bool,
+    // Simulated seconds per real second for the soil model
+    pub speed: f64,
+    // Moisture of every pot at start-up
+    pub start_percent: f32,
+    // Moisture lost to evaporation per simulated hour
+    pub dry_per_hour: f32,
+    // Moisture gained per simulated minute while a pump output is on
+    pub wet_per_minute: f32,
+    // Outputs that water every pot; empty = each zone's pump waters its own pot
+    pub pump_pins: Vec<u8>,
+}
+
+impl Default for SimConfig {
+    fn default() -> Self {
+        Self { enabled: false, speed: 1.0, start_percent: 50.0, dry_per_hour: 2.0, wet_per_minute: 10.0, pump_pins: Vec::new() }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WateringConfig {
+    // Pump/valve output
+    pub pin: u8,
+    // Sensor that drives it (by name); unset = the first sensor
+    pub sensor: Option<String>,
+    // Start dosing below this moisture %, stop at or above `high_percent`
+    pub low_percent: f32,
+    pub high_percent: f32,
+    // Longest single dose, then wait `soak_secs` before measuring again
+    pub max_dose_secs: u64,
+    pub soak_secs: u64,
+    // Sensor sample period
+    pub sample_ms: u64,
+}
+
+impl Default for WateringConfig {
+    fn default() -> Self {
+        Self {
+            pin: 22,
+            sensor: None,
+            low_percent: 30.0,
+            high_percent: 60.0,
+            max_dose_secs: 10,
+            soak_secs: 300,
+            sample_ms: 1000,
+        }
+    }
+}
+
+/// One `[[zones]]` entry: a plant or bed with its own sensor, pump and light.
+/// `name`, `sensor` and `pump_pin` are required; the thresholds default to
+/// those of `[watering]`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ZoneConfig {
+    pub name: String,
+    // Moisture sensor (by name) that drives the pump
+    pub sensor: String,
+    // Pump/valve output
+    pub pump_pin: u8,
+    // Grow light, on during `schedule`/`schedule_rules`; unset = none
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub light_pin: Option<u8>,
+    #[serde(default = "zone_defaults::low_percent")]
+    pub low_percent: f32,
+    #[serde(default = "zone_defaults::high_percent")]
+    pub high_percent: f32,
+    #[serde(default = "zone_defaults::max_dose_secs")]
+    pub max_dose_secs: u64,
+    #[serde(default = "zone_defaults::soak_secs")]
+    pub soak_secs: u64,
+    #[serde(default = "zone_defaults::sample_ms")]
+    pub sample_ms: u64,
+    // Same form as the top-level [schedule] and [[schedule_rules]]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub schedule: Option<HashMap<String, Vec<ScheduleEntry>>>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub schedule_rules: Vec<ScheduleRule>,
+}
+
+impl ZoneConfig {
+    /// Settings for the zone's closed-loop watering controller.
+    pub fn watering(&self) -> WateringConfig {
+        WateringConfig {
+            pin: self.pump_pin,
+            sensor: Some(self.sensor.clone()),
+            low_percent: self.low_percent,
+            high_percent: self.high_percent,
+            max_dose_secs: self.max_dose_secs,
+            soak_secs: self.soak_secs,
+            sample_ms: self.sample_ms,
+        }
+    }
+
+    /// Whether the light has anything to do.
+    pub fn has_schedule(&self) -> bool {
+        self.schedule.is_some() || !self.schedule_rules.is_empty()
+    }
+}
+
+mod zone_defaults {
+    use super::WateringConfig;
+
+    pub fn low_percent() -> f32 {
+        WateringConfig::default().low_percent
+    }
+    pub fn high_percent() -> f32 {
+        WateringConfig::default().high_percent
+    }
+    pub fn max_dose_secs() -> u64 {
+        WateringConfig::default().max_dose_secs
+    }
+    pub fn soak_secs() -> u64 {
+        WateringConfig::default().soak_secs
+    }
+    pub fn sample_ms() -> u64 {
+        WateringConfig::default().sample_ms
+    }
+}
+
+static PATH_OVERRIDE: OnceLock<PathBuf> = OnceLock::new();
+static LOCATION: OnceLock<Option<(PathBuf, ConfigSource)>> = OnceLock::new();
+
+// Names under the user's and the system's config directories
+const APP_DIR: &str = "plants-love-rust";
+const FILE_NAME: &str = "config.toml";
+// Prefix of the per-key environment overrides; PLANTS_CONFIG names the file
+const ENV_PREFIX: &str = "PLANTS_";
+const ENV_CONFIG: &str = "PLANTS_CONFIG";
+
+/// How the config file was found.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigSource {
+    Flag,
+    Env,
+    User,
+    System,
+    Binary,
+}
+
+impl fmt::Display for ConfigSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ConfigSource::Flag => "--config",
+            ConfigSource::Env => ENV_CONFIG,
+            ConfigSource::User => "user config directory",
+            ConfigSource::System => "/etc",
+            ConfigSource::Binary => "next to the binary",
+        })
+    }
+}
+
+/// Use `path` instead of searching for the config file (from `--config`). Call before loading.
+pub fn set_config_path(path: PathBuf) {
+    let _ = PATH_OVERRIDE.set(path);
+}
+
+pub fn config_path() -> Option<PathBuf> {
+    config_location().map(|(path, _)| path)
+}
+
+/// The config file and how it was chosen, decided once per run: `--config`,
+/// then `PLANTS_CONFIG`, then the first of these that exists:
+/// `~/.config/plants-love-rust/config.toml`, `/etc/plants-love-rust/config.toml`
+/// and `config/config.toml` next to the binary. Without any, a new file goes in
+/// the user's config directory, away from the binary that deploys replace.
+pub fn config_location() -> Option<(PathBuf, ConfigSource)> {
+    LOCATION
+        .get_or_init(|| {
+            let user = directories::ProjectDirs::from("", "", APP_DIR).map(|d| d.config_dir().join(FILE_NAME));
+            let system = Path::new("/etc").join(APP_DIR).join(FILE_NAME);
+            let binary = env::current_exe().ok().and_then(|exe| Some(exe.parent()?.join("config").join(FILE_NAME)));
+            let candidates: Vec<(PathBuf, ConfigSource)> = [
+                user.map(|p| (p, ConfigSource::User)),
+                Some((system, ConfigSource::System)),
+                binary.map(|p| (p, ConfigSource::Binary)),
+            ]
+            .into_iter()
+            .flatten()
+            .collect();
+            let from_env = env::var_os(ENV_CONFIG).filter(|v| !v.is_empty()).map(PathBuf::from);
+            choose(PATH_OVERRIDE.get().cloned(), from_env, &candidates, |p| p.exists())
+        })
+        .clone()
+}
+
+// The explicit choices win; otherwise the first existing candidate, else the first one
+fn choose(
+    flag: Option<PathBuf>,
+    from_env: Option<PathBuf>,
+    candidates: &[(PathBuf, ConfigSource)],
+    exists: impl Fn(&Path) -> bool,
+) -> Option<(PathBuf, ConfigSource)> {
+    if let Some(p) = flag {
+        return Some((p, ConfigSource::Flag));
+    }
+    if let Some(p) = from_env {
+        return Some((p, ConfigSource::Env));
+    }
+    candidates.iter().find(|(p, _)| exists(p)).or(candidates.first()).cloned()
+}
+
+/// Directory for the data log: a `data` folder beside the `config/` directory
+/// next to the binary, the user's data directory for a config under ~/.config
+/// or /etc, and the file's own directory for `--config` or `PLANTS_CONFIG`.
+pub fn data_dir() -> Option<PathBuf> {
+    let (path, source) = config_location()?;
+    data_dir_for(&path, source)
+}
+
+fn data_dir_for(path: &Path, source: ConfigSource) -> Option<PathBuf> {
+    match source {
+        ConfigSource::User | ConfigSource::System => {
+            directories::ProjectDirs::from("", "", APP_DIR).map(|d| d.data_dir().to_path_buf())
+        }
+        ConfigSource::Binary => Some(path.parent()?.parent()?.join("data")),
+        // Absolute first, so a bare `--config plants.toml` still has a directory
+        ConfigSource::Flag | ConfigSource::Env => Some(std::path::absolute(path).ok()?.parent()?.to_path_buf()),
+    }
+}
+
+/// Load the config file, creating it with defaults only if it doesn't exist,
+/// and apply `PLANTS_*` overrides. A file that doesn't parse or validate is an
+/// error listing every problem with its line and column; it is never overwritten.
+pub fn load_config() -> Result<AppConfig, String> {
+    let Some(path) = config_path() else {
+        return with_env(AppConfig::default());
+    };
+    if path.exists() {
+        if let Some((m, diff)) = migrate_file(&path, true)? {
+            println!(
+                "[config] migrated {} from version {} to {}; the old file is {}",
+                path.display(),
+                m.from,
+                CURRENT_VERSION,
+                backup_path(&path, 1).display()
+            );
+            for line in m.notes.iter().chain(&diff) {
+                println!("[config]   {}", line);
+            }
+        }
+        return with_env(read_config(&path)?);
+    }
+    let default_cfg = AppConfig::default();
+    if let Some(dir) = path.parent() {
+        let _ = fs::create_dir_all(dir);
+    }
+    if let Ok(data) = toml::to_string_pretty(&default_cfg) {
+        // create_new: never clobber a file that appeared in the meantime
+        if let Ok(mut f) = fs::OpenOptions::new().write(true).create_new(true).open(&path) {
+            let _ = f.write_all(data.as_bytes());
+        }
+    }
+    with_env(default_cfg)
+}
+
+/// Upgrade the config file at `path` to the current schema if it's older,
+/// returning the migration and a line diff. The result must pass `check`, or
+/// the file is left alone; with `write`, the old file is kept as backup 1.
+pub fn migrate_file(path: &Path, write: bool) -> Result<Option<(Migrated, Vec<String>)>, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    // A bad version is reported by `check` with its line
+    let Ok(Some(m)) = migrate::migrate(&text) else {
+        return Ok(None);
+    };
+    check(&m.text).map_err(|problems| {
+        problems.iter().map(|p| format!("{}:{} (after migrating to version {})", path.display(), p, CURRENT_VERSION)).collect::<Vec<_>>().join("\n")
+    })?;
+    if write {
+        write_config_file(path, &m.text, true).map_err(|e| format!("{}: {}", path.display(), e))?;
+    }
+    let diff = migrate::diff(&text, &m.text);
+    Ok(Some((m, diff)))
+}
+
+/// Names of the `PLANTS_*` variables that override config keys.
+pub fn env_overrides() -> Vec<String> {
+    overrides(env::vars()).into_iter().map(|(name, _)| name).collect()
+}
+
+/// `cfg` with each `PLANTS_<KEY>=<value>` variable applied, e.g.
+/// `PLANTS_GPIO_PIN=22`; `__` reaches into tables (`PLANTS_SAFETY__MAX_ON_SECS=600`).
+/// Overrides are never written back to the file.
+pub fn with_env(cfg: AppConfig) -> Result<AppConfig, String> {
+    apply_overrides(cfg, overrides(env::vars()))
+}
+
+fn overrides(vars: impl Iterator<Item = (String, String)>) -> Vec<(String, String)> {
+    let mut list: Vec<(String, String)> =
+        vars.filter(|(name, _)| name.starts_with(ENV_PREFIX) && name != ENV_CONFIG).collect();
+    list.sort();
+    list
+}
+
+fn apply_overrides(mut cfg: AppConfig, vars: Vec<(String, String)>) -> Result<AppConfig, String> {
+    if vars.is_empty() {
+        return Ok(cfg);
+    }
+    let mut problems = Vec::new();
+    for (name, value) in &vars {
+        let key = name[ENV_PREFIX.len()..].to_lowercase().replace("__", ".");
+        match set_value(&cfg, &format!("{}={}", key, value)) {
+            Ok(updated) => cfg = updated,
+            Err(e) => problems.push(format!("{}: {}", name, e)),
+        }
+    }
+    if problems.is_empty() {
+        problems.extend(validate(&cfg).iter().map(|p| format!("with {}* overrides: {}", ENV_PREFIX, p)));
+    }
+    if problems.is_empty() {
+        Ok(cfg)
+    } else {
+        Err(problems.join("\n"))
+    }
+}
+
+/// Parse and validate a config file without falling back to defaults; the
+/// error has one `path:line:col: problem` line per problem.
+pub fn read_config(path: &Path) -> Result<AppConfig, String> {
+    check_file(path).map_err(|problems| problems.join("\n"))
+}
+
+/// Like `read_config`, with the problems as a list.
+pub fn check_file(path: &Path) -> Result<AppConfig, Vec<String>> {
+    let text = fs::read_to_string(path).map_err(|e| vec![format!("{}: {}", path.display(), e)])?;
+    check(&text).map_err(|problems| problems.into_iter().map(|p| format!("{}:{}", path.display(), p)).collect())
+}
+
+/// Parse config text and run `validate`, reporting unknown keys too. Each
+/// problem reads `line:col: message`, or just `message` for a key the text doesn't contain.
+pub fn check(text: &str) -> Result<AppConfig, Vec<String>> {
+    match migrate::migrate(text) {
+        Ok(None) => check_current(text, text),
+        // An older file is checked as what it will become, with positions from
+        // the file as it is where the key is still there
+        Ok(Some(m)) => check_current(&m.text, text).map_err(|problems| {
+            problems.into_iter().map(|p| format!("{} (after migrating to version {})", p, CURRENT_VERSION)).collect()
+        }),
+        Err(e) => {
+            let doc = toml_edit::ImDocument::parse(text).ok();
+            Err(vec![located(text, doc.as_ref().and_then(|d| locate(d, "version")), &e)])
+        }
+    }
+}
+
+// Check `text`, reporting positions in `file`
+fn check_current(text: &str, file: &str) -> Result<AppConfig, Vec<String>> {
+    let mut unknown = Vec::new();
+    let parsed: Result<AppConfig, toml::de::Error> =
+        serde_ignored::deserialize(toml::Deserializer::new(text), |path| unknown.push(key_of(&path)));
+    let cfg = parsed.map_err(|e| {
+        if text == file {
+            return vec![located(file, e.span().map(|r| r.start), e.message())];
+        }
+        // Most type errors are in keys migration doesn't touch: find them in the file
+        match toml::from_str::<AppConfig>(file) {
+            Err(e) => vec![located(file, e.span().map(|r| r.start), e.message())],
+            Ok(_) => vec![e.message().to_string()],
+        }
+    })?;
+    let mut problems: Vec<Problem> =
+        unknown.into_iter().map(|key| Problem { message: format!("unknown key '{}'", key), key }).collect();
+    problems.extend(validate(&cfg));
+    if problems.is_empty() {
+        return Ok(cfg);
+    }
+    let doc = toml_edit::ImDocument::parse(file).ok();
+    Err(problems
+        .iter()
+        .map(|p| located(file, doc.as_ref().and_then(|d| locate(d, &p.key)), &p.message))
+        .collect())
+}
+
+/// Something wrong with a config value, at a dotted key such as `sensors[1].dry`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Problem {
+    pub key: String,
+    pub message: String,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+fn problem(key: impl Into<String>, message: String) -> Problem {
+    Problem { key: key.into(), message }
+}
+
+// Highest BCM GPIO number on the Pi's 40-pin header
+const MAX_PIN: u8 = 27;
+
+/// Problems that parse fine but would misbehave at run time.
+pub fn validate(cfg: &AppConfig) -> Vec<Problem> {
+    let mut problems = Vec::new();
+    if !(50..=10_000).contains(&cfg.interval_ms) {
+        problems.push(problem("interval_ms", format!("interval_ms = {} is outside 50..=10000", cfg.interval_ms)));
+    }
+    let mut pins = vec![("gpio_pin".to_string(), cfg.gpio_pin), ("schedule_pin".to_string(), cfg.schedule_pin)];
+    pins.extend(cfg.watering.as_ref().map(|w| ("watering.pin".to_string(), w.pin)));
+    for (i, z) in cfg.zones.iter().enumerate() {
+        pins.push((format!("zones[{}].pump_pin", i), z.pump_pin));
+        pins.extend(z.light_pin.map(|p| (format!("zones[{}].light_pin", i), p)));
+    }
+    pins.extend(cfg.adc.alert_pin.map(|p| ("adc.alert_pin".to_string(), p)));
+    pins.extend(cfg.sim.pump_pins.iter().enumerate().map(|(i, p)| (format!("sim.pump_pins[{}]", i), *p)));
+    for (key, pin) in pins {
+        if pin > MAX_PIN {
+            problems.push(problem(&key, format!("{} = {} is not a GPIO pin (0..={})", key, pin, MAX_PIN)));
+        }
+    }
+    if cfg.has_schedule() && cfg.schedule_pin == cfg.gpio_pin {
+        problems.push(problem("schedule_pin", format!("schedule_pin and gpio_pin are both {}", cfg.gpio_pin)));
+    }
+    if let Some(lat) = cfg.latitude.filter(|l| !(-90.0..=90.0).contains(l)) {
+        problems.push(problem("latitude", format!("latitude = {} is outside -90..=90", lat)));
+    }
+    if let Some(lon) = cfg.longitude.filter(|l| !(-180.0..=180.0).contains(l)) {
+        problems.push(problem("longitude", format!("longitude = {} is outside -180..=180", lon)));
+    }
+    let located = cfg.latitude.is_some() && cfg.longitude.is_some();
+    if let Some(table) = &cfg.schedule {
+        check_schedule_table(&mut problems, "schedule", table, located);
+    }
+    for (i, r) in cfg.schedule_rules.iter().enumerate() {
+        if let Err(e) = Rule::from_config(r) {
+            problems.push(problem(format!("schedule_rules[{}]", i), format!("schedule_rules[{}]: {}", i, e)));
+        }
+    }
+    for (i, o) in cfg.schedule_overrides.iter().enumerate() {
+        let key = format!("schedule_overrides[{}]", i);
+        if let Err(e) = rules::check_override(o) {
+            problems.push(problem(&key, format!("{}: {}", key, e)));
+        }
+        if let Some(table) = &o.schedule {
+            check_schedule_table(&mut problems, &format!("{}.schedule", key), table, located);
+        }
+    }
+    if let Some(w) = &cfg.watering {
+        if w.pin == cfg.gpio_pin || (cfg.has_schedule() && w.pin == cfg.schedule_pin) {
+            problems.push(problem("watering.pin", format!("watering.pin {} is already used by another controller", w.pin)));
+        }
+        if w.low_percent >= w.high_percent {
+            problems.push(problem(
+                "watering.low_percent",
+                format!("watering.low_percent ({}) must be below high_percent ({})", w.low_percent, w.high_percent),
+            ));
+        }
+        check_sample_ms(&mut problems, "watering", w.sample_ms, w.max_dose_secs);
+    }
+    for (i, s) in cfg.sensors.iter().enumerate() {
+        if s.name.trim().is_empty() {
+            problems.push(problem(format!("sensors[{}]", i), format!("sensors[{}] has no name", i)));
+        } else if cfg.sensors[..i].iter().any(|o| o.name == s.name) {
+            problems.push(problem(format!("sensors[{}].name", i), format!("sensor name '{}' is used twice", s.name)));
+        }
+        if s.dry.is_some() && s.dry == s.wet {
+            problems.push(problem(
+                format!("sensors[{}].wet", i),
+                format!("sensor '{}': dry and wet are both {}", s.name, s.dry.unwrap_or_default()),
+            ));
         }
     }
+    // Outputs claimed so far, in start-up order, to find zone pins used twice
+    let mut outputs: Vec<u8> =
+        [Some(cfg.gpio_pin), cfg.has_schedule().then_some(cfg.schedule_pin), cfg.watering.as_ref().map(|w| w.pin)]
+            .into_iter()
+            .flatten()
+            .collect();
+    if cfg.watering.is_some() && !cfg.zones.is_empty() {
+        problems.push(problem("watering", "[watering] and [[zones]] are both set; give the pump its own zone".to_string()));
+    }
+    let sensors = cfg.sensor_list();
+    for (i, z) in cfg.zones.iter().enumerate() {
+        let key = format!("zones[{}]", i);
+        if z.name.trim().is_empty() {
+            problems.push(problem(&key, format!("{} has no name", key)));
+        } else if cfg.zones[..i].iter().any(|o| o.name == z.name) {
+            problems.push(problem(format!("{}.name", key), format!("zone name '{}' is used twice", z.name)));
+        }
+        if !sensors.iter().any(|s| s.name == z.sensor) {
+            problems.push(problem(
+                format!("{}.sensor", key),
+                format!("zone '{}': sensor '{}' is not a configured sensor", z.name, z.sensor),
+            ));
+        } else if let Some(other) = cfg.zones[..i].iter().find(|o| o.sensor == z.sensor) {
+            problems.push(problem(
+                format!("{}.sensor", key),
+                format!("zone '{}': sensor '{}' already belongs to zone '{}'", z.name, z.sensor, other.name),
+            ));
+        }
+        for (field, pin) in [("pump_pin", Some(z.pump_pin)), ("light_pin", z.light_pin)] {
+            let Some(pin) = pin else { continue };
+            if outputs.contains(&pin) {
+                problems.push(problem(
+                    format!("{}.{}", key, field),
+                    format!("zone '{}': {} {} is already used by another controller", z.name, field, pin),
+                ));
+            }
+            outputs.push(pin);
+        }
+        if z.low_percent >= z.high_percent {
+            problems.push(problem(
+                format!("{}.low_percent", key),
+                format!("zone '{}': low_percent ({}) must be below high_percent ({})", z.name, z.low_percent, z.high_percent),
+            ));
+        }
+        check_sample_ms(&mut problems, &key, z.sample_ms, z.max_dose_secs);
+        if z.has_schedule() && z.light_pin.is_none() {
+            problems.push(problem(format!("{}.light_pin", key), format!("zone '{}' has a schedule but no light_pin", z.name)));
+        }
+        if let Some(table) = &z.schedule {
+            check_schedule_table(&mut problems, &format!("{}.schedule", key), table, located);
+        }
+        for (j, r) in z.schedule_rules.iter().enumerate() {
+            if let Err(e) = Rule::from_config(r) {
+                let rkey = format!("{}.schedule_rules[{}]", key, j);
+                problems.push(problem(&rkey, format!("{}: {}", rkey, e)));
+            }
+        }
+    }
+    if let Some(pin) = cfg.adc.alert_pin {
+        if outputs.contains(&pin) {
+            problems.push(problem("adc.alert_pin", format!("adc.alert_pin {} is also used as an output", pin)));
+        }
+    }
+    if !(cfg.sim.speed > 0.0 && cfg.sim.speed <= 100_000.0) {
+        problems.push(problem("sim.speed", format!("sim.speed = {} is outside 0..=100000", cfg.sim.speed)));
+    }
+    if !(0.0..=100.0).contains(&cfg.sim.start_percent) {
+        problems.push(problem("sim.start_percent", format!("sim.start_percent = {} is outside 0..=100", cfg.sim.start_percent)));
+    }
+    if let Some(name) = cfg.watering.as_ref().and_then(|w| w.sensor.as_ref()) {
+        if !cfg.sensor_list().iter().any(|s| &s.name == name) {
+            problems.push(problem("watering.sensor", format!("watering.sensor '{}' is not a configured sensor", name)));
+        }
+    }
+    problems
 }
 
-fn config_path() -> Option<PathBuf> {
-    // Place config next to the built binary in a local ./config directory
-    let exe = env::current_exe().ok()?;
-    let base: &Path = exe.parent()?;
-    let dir = base.join("config");
-    let _ = fs::create_dir_all(&dir);
-    Some(dir.join("config.toml"))
+// Day names and sun-relative entries of one weekday table
+// At least MIN_SAMPLE_MS, and no longer than a dose so the pump is checked while it runs
+fn check_sample_ms(problems: &mut Vec<Problem>, key: &str, sample_ms: u64, max_dose_secs: u64) {
+    let max = max_dose_secs.saturating_mul(1000).max(MIN_SAMPLE_MS);
+    if !(MIN_SAMPLE_MS..=max).contains(&sample_ms) {
+        problems.push(problem(
+            format!("{}.sample_ms", key),
+            format!("{}.sample_ms = {} is outside {}..={} (max_dose_secs)", key, sample_ms, MIN_SAMPLE_MS, max),
+        ));
+    }
 }
 
-pub fn load_config() -> AppConfig {
-    if let Some(path) = config_path() {
-        if path.exists() {
-            if let Ok(text) = fs::read_to_string(&path) {
-                if let Ok(cfg) = toml::from_str::<AppConfig>(&text) {
-                    return cfg;
+fn check_schedule_table(problems: &mut Vec<Problem>, key: &str, table: &HashMap<String, Vec<ScheduleEntry>>, located: bool) {
+    for (day, entries) in table {
+        let day_key = format!("{}.{}", key, day);
+        if rules::parse_weekday(day).is_none() {
+            problems.push(problem(&day_key, format!("{}: '{}' is not a weekday", key, day)));
+        }
+        for (i, entry) in entries.iter().enumerate() {
+            let entry_key = format!("{}[{}]", day_key, i);
+            let (from, to) = match entry {
+                ScheduleEntry::Range(s, e) => {
+                    if crate::split_range(*s, *e).is_none() {
+                        problems.push(problem(&entry_key, format!("{}: [{}, {}] is not a valid HHMM range", day_key, s, e)));
+                    }
+                    continue;
+                }
+                ScheduleEntry::Run { at, secs } => {
+                    if rules::parse_time_of_day(at).is_none() {
+                        problems.push(problem(format!("{}.at", entry_key), format!("{}: '{}' is not HH:MM or HH:MM:SS", day_key, at)));
+                    }
+                    if !(1..=86400).contains(secs) {
+                        problems.push(problem(format!("{}.secs", entry_key), format!("{}: a run lasts 1 to 86400 seconds, not {}", day_key, secs)));
+                    }
+                    continue;
                 }
+                ScheduleEntry::Sun { from, to } => (from, to),
+            };
+            let times: Vec<TimeOfDay> = [("from", from), ("to", to)]
+                .into_iter()
+                .filter_map(|(field, t)| match TimeOfDay::parse(t) {
+                    Ok(t) => Some(t),
+                    Err(e) => {
+                        problems.push(problem(format!("{}.{}", entry_key, field), format!("{}: {}", day_key, e)));
+                        None
+                    }
+                })
+                .collect();
+            if !located && times.iter().any(|t| !t.is_fixed()) {
+                problems.push(problem(&entry_key, format!("{}: sunrise/sunset needs latitude and longitude", day_key)));
             }
         }
+    }
+}
 
-        // Create a default config file if missing or unreadable
-        let default_cfg = AppConfig::default();
-        if let Ok(data) = toml::to_string_pretty(&default_cfg) {
-            let _ = fs::write(&path, data);
+// serde_ignored's path as a dotted key
+fn key_of(path: &serde_ignored::Path) -> String {
+    use serde_ignored::Path;
+    match path {
+        Path::Root => String::new(),
+        Path::Seq { parent, index } => format!("{}[{}]", key_of(parent), index),
+        Path::Map { parent, key } => match key_of(parent) {
+            p if p.is_empty() => key.clone(),
+            p => format!("{}.{}", p, key),
+        },
+        Path::Some { parent } | Path::NewtypeStruct { parent } | Path::NewtypeVariant { parent } => key_of(parent),
+    }
+}
+
+// Byte offset of `key` (or of the deepest part of it the document has)
+fn locate(doc: &toml_edit::ImDocument<&str>, key: &str) -> Option<usize> {
+    use toml_edit::{Item, Value};
+    enum Node<'a> {
+        Table(&'a toml_edit::Table),
+        Inline(&'a toml_edit::InlineTable),
+        Array(&'a toml_edit::Array),
+        Tables(&'a toml_edit::ArrayOfTables),
+        Leaf,
+    }
+    fn of_value(v: &Value) -> Node<'_> {
+        match v {
+            Value::InlineTable(t) => Node::Inline(t),
+            Value::Array(a) => Node::Array(a),
+            _ => Node::Leaf,
+        }
+    }
+    fn of_item(i: &Item) -> Node<'_> {
+        match i {
+            Item::Table(t) => Node::Table(t),
+            Item::ArrayOfTables(a) => Node::Tables(a),
+            Item::Value(v) => of_value(v),
+            Item::None => Node::Leaf,
         }
-        return default_cfg;
     }
 
-    AppConfig::default()
+    let mut node = Node::Table(doc.as_table());
+    let mut found = None;
+    for part in key.split('.') {
+        let (name, indexes) = match part.find('[') {
+            Some(i) => (&part[..i], &part[i..]),
+            None => (part, ""),
+        };
+        let mut steps: Vec<Result<&str, usize>> = vec![Ok(name)];
+        steps.extend(indexes.split(['[', ']']).filter(|s| !s.is_empty()).map(|s| Err(s.parse().unwrap_or(usize::MAX))));
+        for step in steps {
+            let next = match (&node, step) {
+                (Node::Table(t), Ok(k)) => t.get_key_value(k).map(|(k, i)| (k.span().or(i.span()), of_item(i))),
+                (Node::Inline(t), Ok(k)) => t.get_key_value(k).map(|(k, i)| (k.span().or(i.span()), of_item(i))),
+                (Node::Array(a), Err(i)) => a.get(i).map(|v| (v.span(), of_value(v))),
+                (Node::Tables(a), Err(i)) => a.get(i).map(|t| (t.span(), Node::Table(t))),
+                _ => None,
+            };
+            let Some((span, next)) = next else { return found };
+            found = span.map(|s| s.start).or(found);
+            node = next;
+        }
+    }
+    found
+}
+
+// "line:col: message" for a byte offset into `text`
+fn located(text: &str, offset: Option<usize>, message: &str) -> String {
+    let Some(offset) = offset.filter(|o| *o <= text.len() && text.is_char_boundary(*o)) else {
+        return message.to_string();
+    };
+    let before = &text[..offset];
+    let line = before.matches('\n').count() + 1;
+    let col = before.rsplit('\n').next().unwrap_or_default().chars().count() + 1;
+    format!("{}:{}: {}", line, col, message)
+}
+
+/// Apply a `key=value` assignment (dotted keys reach into tables, e.g.
+/// `safety.max_on_secs=600`). The value is parsed as TOML, falling back to a string.
+pub fn set_value(cfg: &AppConfig, assignment: &str) -> Result<AppConfig, String> {
+    let (key, raw) = assignment
+        .split_once('=')
+        .ok_or_else(|| format!("expected key=value, got '{}'", assignment))?;
+    let (key, raw) = (key.trim(), raw.trim());
+    let value = toml::from_str::<toml::Table>(&format!("v = {}", raw))
+        .ok()
+        .and_then(|mut t| t.remove("v"))
+        .unwrap_or_else(|| toml::Value::String(raw.to_string()));
+
+    let mut root = toml::Value::try_from(cfg).map_err(|e| e.to_string())?;
+    let parts: Vec<&str> = key.split('.').collect();
+    let (last, parents) = parts.split_last().ok_or("empty key")?;
+    let mut table = root.as_table_mut().ok_or("config is not a table")?;
+    for p in parents {
+        table = table
+            .entry(p.to_string())
+            .or_insert_with(|| toml::Value::Table(toml::Table::new()))
+            .as_table_mut()
+            .ok_or_else(|| format!("'{}' is not a table", p))?;
+    }
+    table.insert(last.to_string(), value.clone());
+
+    let updated: AppConfig = root.try_into().map_err(|e: toml::de::Error| format!("{}: {}", key, e.message()))?;
+    // Unknown keys are silently dropped by serde; make sure ours survived the round trip.
+    let check = toml::Value::try_from(&updated).map_err(|e| e.to_string())?;
+    let found = parts.iter().try_fold(&check, |v, p| v.get(p));
+    if found != Some(&value) {
+        return Err(format!("unknown key '{}'", key));
+    }
+    Ok(updated)
 }
 
+/// Re-read the file, apply `f` and save it, so writers that only own a few
+/// fields (e.g. the HTTP API) don't clobber changes made elsewhere.
+pub fn update_config(f: impl FnOnce(&mut AppConfig)) -> io::Result<AppConfig> {
+    let path = config_path().ok_or_else(|| io::Error::other("no config path"))?;
+    let mut cfg = if path.exists() { read_config(&path).map_err(io::Error::other)? } else { AppConfig::default() };
+    f(&mut cfg);
+    save_config(&cfg)?;
+    Ok(cfg)
+}
+
+/// Save `cfg` over the config file. Comments, key order and layout the user
+/// wrote are kept for every value that didn't change; see `write_config_file`.
 pub fn save_config(cfg: &AppConfig) -> io::Result<()> {
-    if let Some(path) = config_path() {
-        let data = toml::to_string_pretty(cfg)
-            .map_err(io::Error::other)?;
-        fs::write(path, data)?;
+    match config_path() {
+        Some(path) => save_to(&path, cfg),
+        None => Ok(()),
+    }
+}
+
+fn save_to(path: &Path, cfg: &AppConfig) -> io::Result<()> {
+    let old = fs::read_to_string(path).ok();
+    let data = render(old.as_deref(), cfg).map_err(io::Error::other)?;
+    if old.as_deref() == Some(data.as_str()) {
+        return Ok(());
+    }
+    write_config_file(path, &data, false)
+}
+
+// Previous versions are kept as config.toml.1 (newest) .. config.toml.N
+const KEEP_BACKUPS: u32 = 5;
+// A file younger than this is part of a burst of edits (e.g. holding `+` in
+// the TUI) and isn't backed up again, so the burst doesn't push out older versions
+const BACKUP_AFTER_SECS: u64 = 60;
+
+fn backup_path(path: &Path, n: u32) -> PathBuf {
+    PathBuf::from(format!("{}.{}", path.display(), n))
+}
+
+/// Replace `path` with `data` so a power cut leaves either the old or the new
+/// file, never a truncated one: write a temp file beside it, fsync, rename it
+/// over and fsync the directory. The old file becomes `path.1`, shifting older
+/// backups up to `path.N`; `force_backup` backs it up even if it's brand new.
+pub fn write_config_file(path: &Path, data: &str, force_backup: bool) -> io::Result<()> {
+    let tmp = PathBuf::from(format!("{}.tmp", path.display()));
+    {
+        let mut f = fs::File::create(&tmp)?;
+        f.write_all(data.as_bytes())?;
+        f.sync_all()?;
+    }
+    // Keep a `chmod 600`: the file may hold http.token and mqtt.password
+    if let Ok(meta) = fs::metadata(path) {
+        fs::set_permissions(&tmp, meta.permissions())?;
+    }
+    let modified = fs::metadata(path).and_then(|m| m.modified());
+    let recent = modified.as_ref().is_ok_and(|t| t.elapsed().is_ok_and(|a| a.as_secs() < BACKUP_AFTER_SECS));
+    if modified.is_ok() && (force_backup || !recent) {
+        let _ = fs::remove_file(backup_path(path, KEEP_BACKUPS));
+        for n in (1..KEEP_BACKUPS).rev() {
+            let _ = fs::rename(backup_path(path, n), backup_path(path, n + 1));
+        }
+        // A hard link keeps the old contents without copying; FAT has none
+        if fs::hard_link(path, backup_path(path, 1)).is_err() {
+            fs::copy(path, backup_path(path, 1))?;
+        }
+    }
+    fs::rename(&tmp, path)?;
+    if let Some(dir) = path.parent().and_then(|d| fs::File::open(d).ok()) {
+        let _ = dir.sync_all();
     }
     Ok(())
 }
 
+/// Backups of the config file that exist, newest first.
+pub fn backups(path: &Path) -> Vec<(u32, PathBuf)> {
+    (1..=KEEP_BACKUPS).map(|n| (n, backup_path(path, n))).filter(|(_, p)| p.exists()).collect()
+}
+
+/// Put backup `n` back in place of the config file. The backup must still
+/// pass `check`; the file it replaces becomes backup 1, so a restore can be undone.
+pub fn restore_backup(path: &Path, n: u32) -> Result<(), String> {
+    let from = backup_path(path, n);
+    let text = fs::read_to_string(&from).map_err(|e| format!("{}: {}", from.display(), e))?;
+    check(&text).map_err(|problems| {
+        problems.iter().map(|p| format!("{}:{}", from.display(), p)).collect::<Vec<_>>().join("\n")
+    })?;
+    write_config_file(path, &text, true).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+// `cfg` as TOML, edited into `old` so what the user wrote survives
+fn render(old: Option<&str>, cfg: &AppConfig) -> Result<String, String> {
+    let fresh = toml::to_string_pretty(cfg).map_err(|e| e.to_string())?;
+    let Some(mut doc) = old.and_then(|o| o.parse::<toml_edit::DocumentMut>().ok()) else {
+        return Ok(fresh);
+    };
+    let new: toml_edit::DocumentMut = fresh.parse().map_err(|e: toml_edit::TomlError| e.to_string())?;
+    // Tables the file doesn't have yet go after the ones it does
+    let mut next = last_position(doc.as_table()) + 1;
+    merge_table(doc.as_table_mut(), new.as_table(), &mut next);
+    Ok(doc.to_string())
+}
+
+fn last_position(t: &toml_edit::Table) -> usize {
+    let nested = t.iter().flat_map(|(_, i)| match i {
+        toml_edit::Item::Table(t) => vec![last_position(t)],
+        toml_edit::Item::ArrayOfTables(a) => a.iter().map(last_position).collect(),
+        _ => vec![],
+    });
+    nested.chain(t.position()).max().unwrap_or(0)
+}
+
+// Give a new table (and the tables inside it) a place at the end of the file
+fn place(item: &mut toml_edit::Item, next: &mut usize) {
+    let tables: Vec<&mut toml_edit::Table> = match item {
+        toml_edit::Item::Table(t) => vec![t],
+        toml_edit::Item::ArrayOfTables(a) => a.iter_mut().collect(),
+        _ => return,
+    };
+    for t in tables {
+        t.set_position(*next);
+        *next += 1;
+        for (_, i) in t.iter_mut() {
+            place(i, next);
+        }
+    }
+}
+
+// Make `old` hold the values of `new`, touching only what differs
+fn merge_table(old: &mut dyn toml_edit::TableLike, new: &dyn toml_edit::TableLike, next: &mut usize) {
+    let stale: Vec<String> = old.iter().map(|(k, _)| k.to_string()).filter(|k| !new.contains_key(k)).collect();
+    for k in stale {
+        old.remove(&k);
+    }
+    for (k, item) in new.iter() {
+        match old.get_mut(k) {
+            Some(o) => merge_item(o, item, next),
+            None => {
+                let mut item = item.clone();
+                place(&mut item, next);
+                old.insert(k, item);
+            }
+        }
+    }
+}
+
+fn merge_item(old: &mut toml_edit::Item, new: &toml_edit::Item, next: &mut usize) {
+    use toml_edit::Item;
+    if let (Some(o), Some(n)) = (old.as_table_like_mut(), new.as_table_like()) {
+        return merge_table(o, n, next);
+    }
+    match (old, new) {
+        (Item::ArrayOfTables(o), Item::ArrayOfTables(n)) if o.len() == n.len() => {
+            for (o, n) in o.iter_mut().zip(n.iter()) {
+                merge_table(o, n, next);
+            }
+        }
+        // Also covers `sensors = [{ .. }]` written inline where we'd write [[sensors]]
+        (Item::Value(o), n) => {
+            if let Ok(n) = n.clone().into_value() {
+                merge_value(o, &n);
+            }
+        }
+        (old, new) => {
+            let mut new = new.clone();
+            place(&mut new, next);
+            *old = new;
+        }
+    }
+}
+
+fn merge_value(old: &mut toml_edit::Value, new: &toml_edit::Value) {
+    use toml_edit::Value;
+    match (old, new) {
+        (Value::Array(o), Value::Array(n)) if o.len() == n.len() => {
+            for (o, n) in o.iter_mut().zip(n.iter()) {
+                merge_value(o, n);
+            }
+        }
+        (Value::InlineTable(o), Value::InlineTable(n)) => merge_table(o, n, &mut 0),
+        (old, new) if same_value(old, new) => {}
+        (old, new) => {
+            let decor = old.decor().clone();
+            *old = new.clone();
+            *old.decor_mut() = decor;
+        }
+    }
+}
+
+// Equal scalars, however they're spelled (`1` and `1.0`, 'a' and "a")
+fn same_value(a: &toml_edit::Value, b: &toml_edit::Value) -> bool {
+    use toml_edit::Value;
+    match (a, b) {
+        (Value::String(a), Value::String(b)) => a.value() == b.value(),
+        (Value::Integer(a), Value::Integer(b)) => a.value() == b.value(),
+        (Value::Float(a), Value::Float(b)) => a.value() == b.value(),
+        (Value::Integer(a), Value::Float(b)) | (Value::Float(b), Value::Integer(a)) => *a.value() as f64 == *b.value(),
+        (Value::Boolean(a), Value::Boolean(b)) => a.value() == b.value(),
+        (Value::Datetime(a), Value::Datetime(b)) => a.value() == b.value(),
+        _ => false,
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -92,5 +1312,310 @@ mod tests {
         assert!(!d.invert);
         assert!(d.schedule.is_none());
         assert_eq!(d.schedule_pin, 27);
+        assert!(d.safety.max_on_secs.is_none());
+        assert!(!d.safety.hardware_watchdog);
+        assert!(d.watering.is_none());
+    }
+
+    #[test]
+    fn set_value_updates_nested_keys_and_rejects_unknown() {
+        let d = AppConfig::default();
+        let c = set_value(&d, "interval_ms=250").unwrap();
+        assert_eq!(c.interval_ms, 250);
+        let c = set_value(&c, "safety.max_on_secs = 600").unwrap();
+        assert_eq!(c.safety.max_on_secs, Some(600));
+        assert!(set_value(&c, "no_such_key=1").is_err());
+        assert!(set_value(&c, "gpio_pin=banana").is_err());
+        assert!(set_value(&c, "gpio_pin").is_err());
+    }
+
+    #[test]
+    fn validate_flags_pin_clash_and_bad_calibration() {
+        let mut c = AppConfig::default();
+        assert!(validate(&c).is_empty());
+        c.schedule = Some(HashMap::new());
+        c.schedule_pin = c.gpio_pin;
+        c.set_calibration(DEFAULT_SENSOR, Some(100), Some(100));
+        assert_eq!(validate(&c).len(), 2);
+        c.adc.alert_pin = Some(c.gpio_pin);
+        assert_eq!(validate(&c).len(), 3);
+    }
+
+    #[test]
+    fn sample_ms_must_fit_inside_a_dose() {
+        let mut c = AppConfig { watering: Some(WateringConfig { sample_ms: 0, ..Default::default() }), ..Default::default() };
+        assert_eq!(validate(&c)[0].message, "watering.sample_ms = 0 is outside 100..=10000 (max_dose_secs)");
+        c.watering = Some(WateringConfig { sample_ms: 60_000, max_dose_secs: 10, ..Default::default() });
+        assert_eq!(validate(&c).len(), 1);
+        c.watering = Some(WateringConfig { sample_ms: 60_000, max_dose_secs: 60, ..Default::default() });
+        assert!(validate(&c).is_empty());
+
+        let z: AppConfig = toml::from_str("[[zones]]\nname = \"basil\"\nsensor = \"moisture\"\npump_pin = 22\nsample_ms = 20000\n").unwrap();
+        assert_eq!(validate(&z)[0].key, "zones[0].sample_ms");
+    }
+
+    #[test]
+    fn check_reports_every_problem_with_line_and_column() {
+        let text = "version = 2\ninterval_ms = 20\ngpio_pin = 40\ncolour = \"red\"\n\n[schedule]\nMonday = [[800, 900]]\nFunday = [[1000, 1100]]\n\n[sim]\nspeeed = 2.0\n\n[[sensors]]\nname = \"basil\"\nchannel = \"a0\"\ndry = 100\nwet = 100\n";
+        let problems = check(text).unwrap_err();
+        let want = [
+            "4:1: unknown key 'colour'",
+            "11:1: unknown key 'sim.speeed'",
+            "2:1: interval_ms = 20 is outside 50..=10000",
+            "3:1: gpio_pin = 40 is not a GPIO pin (0..=27)",
+            "8:1: schedule: 'Funday' is not a weekday",
+            "17:1: sensor 'basil': dry and wet are both 100",
+        ];
+        assert_eq!(problems, want);
+
+        // Older files are checked as they'll be after migrating
+        let err = check("moisture_dry_value = 100\nmoisture_wet_value = 100\n").unwrap_err();
+        assert_eq!(err, ["sensor 'moisture': dry and wet are both 100 (after migrating to version 2)"]);
+        let err = check("interval_ms = 5\n").unwrap_err();
+        assert_eq!(err, ["1:1: interval_ms = 5 is outside 50..=10000 (after migrating to version 2)"]);
+        assert_eq!(check("version = 3\n").unwrap_err(), ["1:1: version = 3 is from a newer firmware; this one reads up to version 2"]);
+
+        let err = check("gpio_pin = \"seventeen\"\n").unwrap_err();
+        assert_eq!(err.len(), 1);
+        assert!(err[0].starts_with("1:12: "), "{}", err[0]);
+        // Defaults pass, as written to a fresh file
+        assert!(check(&toml::to_string_pretty(&AppConfig::default()).unwrap()).is_ok());
+    }
+
+    #[test]
+    fn sun_entries_need_a_location() {
+        let text = "version = 2\n[schedule]\nMonday = [{ from = \"sunrise-30m\", to = \"sunset\" }, { from = \"noon\", to = \"13:00\" }]\n";
+        let problems = check(text).unwrap_err();
+        assert_eq!(problems.len(), 2);
+        assert_eq!(problems[0], "3:11: schedule.Monday: sunrise/sunset needs latitude and longitude");
+        assert_eq!(problems[1], "3:54: schedule.Monday: 'noon' is not sunrise, sunset or a time of day (HH:MM)");
+        let located = format!("latitude = 51.5\nlongitude = -0.1\n{}", text.replace("noon", "12:00"));
+        assert!(check(&located).is_ok());
+    }
+
+    #[test]
+    fn bad_ranges_and_runs_are_rejected() {
+        // One day only: days come out of a HashMap in no fixed order
+        let text = "version = 2\n[schedule]\nMonday = [[800, 900], [2400, 100], { at = \"noon\", secs = 30 }, { at = \"25:00\", secs = 5 }, { at = \"07:30\", secs = 0 }]\n";
+        let problems = check(text).unwrap_err();
+        let want = [
+            "3:23: schedule.Monday: [2400, 100] is not a valid HHMM range",
+            "3:38: schedule.Monday: 'noon' is not HH:MM or HH:MM:SS",
+            "3:66: schedule.Monday: '25:00' is not HH:MM or HH:MM:SS",
+            "3:108: schedule.Monday: a run lasts 1 to 86400 seconds, not 0",
+        ];
+        assert_eq!(problems, want);
+    }
+
+    #[test]
+    fn broken_file_is_reported_and_left_alone() {
+        let path = std::env::temp_dir().join(format!("plants-config-{}.toml", std::process::id()));
+        let text = "interval_ms = 5\nsensors = [\n";
+        fs::write(&path, text).unwrap();
+        let err = read_config(&path).unwrap_err();
+        assert!(err.starts_with(&format!("{}:", path.display())), "{}", err);
+        assert_eq!(fs::read_to_string(&path).unwrap(), text);
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn saving_keeps_comments_and_layout() {
+        let text = "# Pot on the kitchen window\ninterval_ms = 1500 # blink speed\ngpio_pin = 17\nspeed_of_light = 1\n\n[safety]\n# never more than ten minutes\nmax_on_secs = 600\n\n[sim]\nspeed = 1\n";
+        let mut cfg: AppConfig = toml::from_str(text).unwrap();
+        cfg.interval_ms = 250;
+        cfg.watering = Some(WateringConfig::default());
+        let out = render(Some(text), &cfg).unwrap();
+        assert!(out.starts_with("# Pot on the kitchen window\ninterval_ms = 250 # blink speed\ngpio_pin = 17\n"), "{}", out);
+        assert!(out.contains("[safety]\n# never more than ten minutes\nmax_on_secs = 600\n"), "{}", out);
+        // `1` for a float isn't rewritten as `1.0`; keys serde doesn't know are dropped
+        assert!(out.contains("[sim]\nspeed = 1\n"), "{}", out);
+        assert!(!out.contains("speed_of_light"));
+        // New tables go at the end
+        assert!(out.find("[watering]") > out.find("[sim]"), "{}", out);
+        let back: AppConfig = toml::from_str(&out).unwrap();
+        assert_eq!(toml::to_string(&back).unwrap(), toml::to_string(&cfg).unwrap());
+        // Nothing changed, nothing rewritten
+        assert_eq!(render(Some(&out), &back).unwrap(), out);
+    }
+
+    #[test]
+    fn writes_keep_backups_that_can_be_restored() {
+        let dir = std::env::temp_dir().join(format!("plants-backups-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("config.toml");
+        for ms in 1..=KEEP_BACKUPS + 2 {
+            write_config_file(&path, &format!("interval_ms = {}\n", ms * 100), true).unwrap();
+        }
+        let read = |p: &Path| fs::read_to_string(p).unwrap();
+        assert_eq!(read(&path), format!("interval_ms = {}\n", (KEEP_BACKUPS + 2) * 100));
+        let list = backups(&path);
+        assert_eq!(list.len(), KEEP_BACKUPS as usize);
+        assert_eq!(read(&list[0].1), format!("interval_ms = {}\n", (KEEP_BACKUPS + 1) * 100));
+        assert!(!dir.join("config.toml.tmp").exists());
+
+        // A fresh file isn't backed up again by an ordinary save
+        save_to(&path, &AppConfig { interval_ms: 900, ..Default::default() }).unwrap();
+        assert_eq!(read(&backup_path(&path, 1)), format!("interval_ms = {}\n", (KEEP_BACKUPS + 1) * 100));
+
+        restore_backup(&path, 2).unwrap();
+        assert_eq!(read(&path), format!("interval_ms = {}\n", KEEP_BACKUPS * 100));
+        assert!(read(&backup_path(&path, 1)).starts_with("interval_ms = 900\n"));
+        fs::write(backup_path(&path, 3), "interval_ms = 1\n").unwrap();
+        assert!(restore_backup(&path, 3).unwrap_err().contains("outside 50..=10000"));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn saves_keep_the_file_mode() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = std::env::temp_dir().join(format!("plants-mode-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("config.toml");
+        fs::write(&path, "interval_ms = 100\n").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o600)).unwrap();
+        save_to(&path, &AppConfig { interval_ms: 900, ..Default::default() }).unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn data_dir_follows_the_config_source() {
+        let user = directories::ProjectDirs::from("", "", APP_DIR).map(|d| d.data_dir().to_path_buf());
+        let etc = Path::new("/etc/plants-love-rust/config.toml");
+        assert_eq!(data_dir_for(etc, ConfigSource::System), user);
+        assert_eq!(data_dir_for(Path::new("/home/pi/.config/plants-love-rust/config.toml"), ConfigSource::User), user);
+        let binary = Path::new("/opt/plants/config/config.toml");
+        assert_eq!(data_dir_for(binary, ConfigSource::Binary), Some(PathBuf::from("/opt/plants/data")));
+        assert_eq!(data_dir_for(Path::new("/etc/plants.toml"), ConfigSource::Flag), Some(PathBuf::from("/etc")));
+        assert_eq!(data_dir_for(Path::new("/home/pi/plants.toml"), ConfigSource::Env), Some(PathBuf::from("/home/pi")));
+        assert_eq!(data_dir_for(Path::new("plants.toml"), ConfigSource::Flag), Some(env::current_dir().unwrap()));
+    }
+
+    #[test]
+    fn config_file_is_searched_in_order() {
+        let candidates = [
+            (PathBuf::from("/home/pi/.config/plants-love-rust/config.toml"), ConfigSource::User),
+            (PathBuf::from("/etc/plants-love-rust/config.toml"), ConfigSource::System),
+            (PathBuf::from("/opt/plants/config/config.toml"), ConfigSource::Binary),
+        ];
+        let found = |p: &Path| p.starts_with("/etc") || p.starts_with("/opt");
+        let pick = |flag: Option<&str>, from_env: Option<&str>| {
+            choose(flag.map(PathBuf::from), from_env.map(PathBuf::from), &candidates, found).map(|(_, s)| s)
+        };
+        assert_eq!(pick(Some("/tmp/a.toml"), Some("/tmp/b.toml")), Some(ConfigSource::Flag));
+        assert_eq!(pick(None, Some("/tmp/b.toml")), Some(ConfigSource::Env));
+        assert_eq!(pick(None, None), Some(ConfigSource::System));
+        // Nothing exists yet: the new file goes in the user's directory
+        assert_eq!(choose(None, None, &candidates, |_| false).map(|(_, s)| s), Some(ConfigSource::User));
+    }
+
+    #[test]
+    fn env_overrides_set_keys_and_are_checked() {
+        let vars = |list: &[(&str, &str)]| {
+            overrides(list.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect::<Vec<_>>().into_iter())
+        };
+        let list = vars(&[("PLANTS_GPIO_PIN", "22"), ("PLANTS_SAFETY__MAX_ON_SECS", "600"), ("PLANTS_CONFIG", "/x"), ("HOME", "/root")]);
+        assert_eq!(list.len(), 2);
+        let cfg = apply_overrides(AppConfig::default(), list).unwrap();
+        assert_eq!(cfg.gpio_pin, 22);
+        assert_eq!(cfg.safety.max_on_secs, Some(600));
+
+        let err = apply_overrides(AppConfig::default(), vars(&[("PLANTS_GPIO_PINN", "22")])).unwrap_err();
+        assert_eq!(err, "PLANTS_GPIO_PINN: unknown key 'gpio_pinn'");
+        let err = apply_overrides(AppConfig::default(), vars(&[("PLANTS_INTERVAL_MS", "5")])).unwrap_err();
+        assert!(err.contains("interval_ms = 5 is outside"), "{}", err);
+    }
+
+    #[test]
+    fn sensors_default_to_legacy_probe_and_take_calibration() {
+        let mut c = AppConfig::default();
+        let list = c.sensor_list();
+        assert_eq!(list.len(), 1);
+        assert_eq!(list[0].name, DEFAULT_SENSOR);
+        assert_eq!(list[0].channel, Channel::A3);
+        assert!(c.set_calibration(DEFAULT_SENSOR, Some(26000), None));
+        assert!(c.set_calibration(DEFAULT_SENSOR, None, Some(12000)));
+        assert_eq!(c.sensors.len(), 1);
+        assert_eq!(c.sensor_list()[0].calibration(), Some((26000, 12000)));
+
+        let c: AppConfig = toml::from_str(
+            "[[sensors]]\nname = \"basil\"\nchannel = \"a0\"\n\n[[sensors]]\nname = \"mint\"\nchannel = \"a1-a3\"\ndry = 5\n",
+        )
+        .unwrap();
+        let mut c2 = c.clone();
+        assert!(c2.set_calibration("mint", None, Some(9)));
+        assert!(!c2.set_calibration(DEFAULT_SENSOR, Some(1), None));
+        assert_eq!(c2.sensor_list()[1].calibration(), Some((5, 9)));
+        // Round-trips through the file format
+        let back: AppConfig = toml::from_str(&toml::to_string_pretty(&c2).unwrap()).unwrap();
+        assert_eq!(back.sensors.len(), 2);
+        assert_eq!(back.sensors[1].channel, Channel::A1A3);
+    }
+
+    #[test]
+    fn partial_watering_table_uses_defaults() {
+        let cfg: AppConfig = toml::from_str("[watering]\nlow_percent = 25.0\n").unwrap();
+        let w = cfg.watering.unwrap();
+        assert_eq!(w.low_percent, 25.0);
+        assert_eq!(w.pin, 22);
+        assert!(w.low_percent < w.high_percent);
+    }
+
+    #[test]
+    fn watering_is_the_only_zone_without_zones() {
+        assert!(AppConfig::default().zone_list().is_empty());
+        let cfg: AppConfig = toml::from_str("[watering]\npin = 5\nsoak_secs = 60\n").unwrap();
+        let zones = cfg.zone_list();
+        assert_eq!(zones.len(), 1);
+        assert_eq!((zones[0].name.as_str(), zones[0].sensor.as_str()), (DEFAULT_SENSOR, DEFAULT_SENSOR));
+        assert_eq!(zones[0].watering().soak_secs, 60);
+        assert_eq!(zones[0].watering().pin, 5);
+    }
+
+    #[test]
+    fn zones_need_their_own_sensor_and_pins() {
+        let text = "version = 2\n\
+                    [[sensors]]\nname = \"basil\"\nchannel = \"a0\"\n\
+                    [[zones]]\nname = \"basil\"\nsensor = \"basil\"\npump_pin = 22\nlight_pin = 5\nschedule = { Mon = [[600, 2000]] }\n\
+                    [[zones]]\nname = \"mint\"\nsensor = \"basil\"\npump_pin = 5\nschedule_rules = [{ every_hours = 6, secs = 30 }]\n\
+                    [[zones]]\nname = \"mint\"\nsensor = \"sage\"\npump_pin = 17\nlow_percent = 70.0\n";
+        let cfg: AppConfig = toml::from_str(text).unwrap();
+        assert_eq!(cfg.zones[0].max_dose_secs, 10);
+        let keys: Vec<String> = validate(&cfg).into_iter().map(|p| p.key).collect();
+        assert_eq!(
+            keys,
+            [
+                "zones[1].sensor",
+                "zones[1].pump_pin",
+                "zones[1].light_pin",
+                "zones[2].name",
+                "zones[2].sensor",
+                "zones[2].pump_pin",
+                "zones[2].low_percent",
+            ]
+        );
+        let errors = check(text).unwrap_err();
+        assert_eq!(errors[0], "13:1: zone 'mint': sensor 'basil' already belongs to zone 'basil'");
+
+        // A zone config round-trips without the defaulted keys' absence mattering
+        let back: AppConfig = toml::from_str(&toml::to_string_pretty(&cfg).unwrap()).unwrap();
+        assert_eq!(back.zones, cfg.zones);
+        let mut both = back;
+        both.zones.truncate(1);
+        both.watering = Some(WateringConfig { pin: 23, ..Default::default() });
+        assert_eq!(validate(&both)[0].key, "watering");
+    }
+
+    #[test]
+    fn schedule_mixes_ranges_and_timed_runs() {
+        let cfg: AppConfig =
+            toml::from_str("[schedule]\nMonday = [[700, 900], { at = \"07:30:00\", secs = 45 }]\n").unwrap();
+        let expected = vec![ScheduleEntry::Range(700, 900), ScheduleEntry::Run { at: "07:30:00".to_string(), secs: 45 }];
+        assert_eq!(cfg.schedule.as_ref().unwrap()["Monday"], expected);
+        let back: AppConfig = toml::from_str(&toml::to_string_pretty(&cfg).unwrap()).unwrap();
+        assert_eq!(back.schedule.unwrap()["Monday"], expected);
     }
 }
@@ -0,0 +1,151 @@
+// Headless mode: same controllers as the TUI, but plain line-oriented logging to
+// stdout (journald captures it) and systemd readiness/watchdog notifications.
+
+use crate::analog::MoistureProbe;
+use crate::config::AppConfig;
+use crate::gpio::{safety, shutdown, GpioController};
+use crate::zone::Zone;
+use std::error::Error;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Default)]
+pub struct DaemonOptions {
+    /// Write our PID here while running; removed on exit
+    pub pid_file: Option<PathBuf>,
+}
+
+pub fn run(
+    ctl: Arc<dyn GpioController + Send + Sync>,
+    cfg: AppConfig,
+    sensors: Vec<Arc<MoistureProbe>>,
+    zones: Vec<Arc<Zone>>,
+    opts: DaemonOptions,
+) -> Result<(), Box<dyn Error>> {
+    if let Some(path) = &opts.pid_file {
+        fs::write(path, format!("{}\n", std::process::id()))?;
+    }
+
+    let watchdog = notify::watchdog_interval();
+    notify::ready();
+    println!(
+        "[daemon] running pin={} blink={} iv={}ms",
+        cfg.gpio_pin,
+        ctl.is_blink(),
+        ctl.interval_ms()
+    );
+
+    let status_every = Duration::from_secs(cfg.logging.sample_secs.max(1));
+    let mut last_status: Option<Instant> = None;
+    let mut last_ping = Instant::now();
+    let mut last_faults = Vec::new();
+
+    while !shutdown::global().is_requested() {
+        if last_status.is_none_or(|t| t.elapsed() >= status_every) {
+            let line = status_line(&sensors, &zones);
+            println!("[daemon] {}", line);
+            notify::status(&line);
+            last_status = Some(Instant::now());
+        }
+
+        // Report each latched fault once, when it appears
+        let faults = safety::global().faults();
+        for (pin, fault) in &faults {
+            if !last_faults.contains(&(*pin, *fault)) {
+                println!("[daemon] FAULT GPIO {}: {}", pin, fault);
+            }
+        }
+        last_faults = faults;
+
+        if let Some(iv) = watchdog {
+            if last_ping.elapsed() >= iv {
+                notify::watchdog();
+                last_ping = Instant::now();
+            }
+        }
+
+        thread::sleep(Duration::from_millis(250));
+    }
+
+    notify::stopping();
+    println!("[daemon] stopping");
+    if let Some(path) = &opts.pid_file {
+        let _ = fs::remove_file(path);
+    }
+    Ok(())
+}
+
+fn status_line(sensors: &[Arc<MoistureProbe>], zones: &[Arc<Zone>]) -> String {
+    let mut parts = Vec::new();
+    for probe in sensors {
+        match probe.read() {
+            Ok(r) => {
+                let pct = r.percent.map(|p| format!("{:.1}%", p)).unwrap_or_else(|| "uncalibrated".to_string());
+                parts.push(format!("{} raw={} v={:.3} {}", probe.name(), r.raw, r.voltage, pct));
+            }
+            Err(e) => parts.push(format!("{} err: {}", probe.name(), e)),
+        }
+    }
+    if sensors.is_empty() {
+        parts.push("sensor N/A".to_string());
+    }
+    for z in zones {
+        if let Some(w) = &z.watering {
+            parts.push(format!("{} water GPIO {}: {}", z.name, w.pin(), w.state()));
+        }
+        if let (Some(l), Some(on)) = (&z.light, z.light_on()) {
+            let paused = if l.is_blink() { "" } else { " (schedule off)" };
+            parts.push(format!("{} light GPIO {}: {}{}", z.name, z.light_pin.unwrap_or_default(), if on { "on" } else { "off" }, paused));
+        }
+    }
+    parts.join(", ")
+}
+
+// sd_notify(3) messages. Outside systemd (no NOTIFY_SOCKET) these are no-ops.
+#[cfg(unix)]
+mod notify {
+    use sd_notify::NotifyState;
+    use std::time::Duration;
+
+    pub fn ready() {
+        let _ = sd_notify::notify(false, &[NotifyState::Ready]);
+    }
+
+    pub fn stopping() {
+        let _ = sd_notify::notify(false, &[NotifyState::Stopping]);
+    }
+
+    pub fn status(s: &str) {
+        let _ = sd_notify::notify(false, &[NotifyState::Status(s)]);
+    }
+
+    pub fn watchdog() {
+        let _ = sd_notify::notify(false, &[NotifyState::Watchdog]);
+    }
+
+    /// How often to ping: half of `WatchdogSec=`, if systemd asked for pings.
+    pub fn watchdog_interval() -> Option<Duration> {
+        let mut usec = 0;
+        if sd_notify::watchdog_enabled(false, &mut usec) {
+            Some(Duration::from_micros(usec / 2))
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(not(unix))]
+mod notify {
+    use std::time::Duration;
+
+    pub fn ready() {}
+    pub fn stopping() {}
+    pub fn status(_s: &str) {}
+    pub fn watchdog() {}
+    pub fn watchdog_interval() -> Option<Duration> {
+        None
+    }
+}
@@ -0,0 +1,161 @@
+// Append-only CSV log of moisture samples and pin changes, rotated by size
+// so it cannot fill the Pi's SD card.
+//
+// Line formats:
+//   <rfc3339>,moisture,<sensor>,<raw>,<voltage>,<percent or empty>
+//   <rfc3339>,pin,<gpio>,<on|off>
+
+use crate::analog::MoistureProbe;
+use crate::clock;
+use crate::config::LoggingConfig;
+use crate::gpio::shutdown::{self, WorkerHandle};
+use chrono::Local;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+
+pub const LOG_FILE: &str = "plants.log";
+
+pub struct DataLog {
+    path: PathBuf,
+    max_bytes: u64,
+    keep: u32,
+    file: Mutex<Option<(File, u64)>>,
+}
+
+impl DataLog {
+    pub fn open(dir: &Path, max_bytes: u64, keep: u32) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        let log = Self { path: dir.join(LOG_FILE), max_bytes, keep, file: Mutex::new(None) };
+        *log.file.lock().unwrap_or_else(|e| e.into_inner()) = Some(log.open_current()?);
+        Ok(log)
+    }
+
+    pub fn moisture(&self, sensor: &str, raw: i16, voltage: f32, percent: Option<f32>) {
+        let pct = percent.map(|p| format!("{:.1}", p)).unwrap_or_default();
+        self.append(&format!("{},moisture,{},{},{:.4},{}", timestamp(), sensor, raw, voltage, pct));
+    }
+
+    pub fn pin(&self, pin: u8, on: bool) {
+        self.append(&format!("{},pin,{},{}", timestamp(), pin, if on { "on" } else { "off" }));
+    }
+
+    // Logging must never take the controllers down, so write errors are dropped.
+    fn append(&self, line: &str) {
+        let mut guard = self.file.lock().unwrap_or_else(|e| e.into_inner());
+        let len = line.len() as u64 + 1;
+        if let Some((_, size)) = guard.as_ref() {
+            if *size > 0 && size + len > self.max_bytes {
+                *guard = None; // close before renaming
+                self.rotate();
+            }
+        }
+        if guard.is_none() {
+            *guard = self.open_current().ok();
+        }
+        if let Some((file, size)) = guard.as_mut() {
+            if writeln!(file, "{}", line).is_ok() {
+                *size += len;
+            }
+        }
+    }
+
+    // plants.log -> plants.log.1 -> ... -> plants.log.<keep>; the oldest is dropped.
+    fn rotate(&self) {
+        let rotated = |n: u32| PathBuf::from(format!("{}.{}", self.path.display(), n));
+        if self.keep == 0 {
+            let _ = fs::remove_file(&self.path);
+            return;
+        }
+        let _ = fs::remove_file(rotated(self.keep));
+        for n in (1..self.keep).rev() {
+            let _ = fs::rename(rotated(n), rotated(n + 1));
+        }
+        let _ = fs::rename(&self.path, rotated(1));
+    }
+
+    fn open_current(&self) -> io::Result<(File, u64)> {
+        let file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        let size = file.metadata()?.len();
+        Ok((file, size))
+    }
+}
+
+// From the process clock, so a sim run logs simulated time
+fn timestamp() -> String {
+    let now = clock::global().now();
+    match now.and_local_timezone(Local).earliest() {
+        Some(t) => t.to_rfc3339_opts(chrono::SecondsFormat::Secs, false),
+        None => now.format("%Y-%m-%dT%H:%M:%S").to_string(),
+    }
+}
+
+static GLOBAL: OnceLock<DataLog> = OnceLock::new();
+
+/// Open the process-wide log in `dir`. Later calls are ignored.
+pub fn init(dir: &Path, cfg: &LoggingConfig) -> io::Result<()> {
+    let log = DataLog::open(dir, cfg.max_bytes, cfg.keep_files)?;
+    let _ = GLOBAL.set(log);
+    Ok(())
+}
+
+/// The process-wide log, if logging is enabled.
+pub fn global() -> Option<&'static DataLog> {
+    GLOBAL.get()
+}
+
+/// Sample every sensor each `period` and log it, independently of the UI.
+pub fn start_sampler(sensors: Vec<Arc<MoistureProbe>>, period: Duration) -> WorkerHandle {
+    shutdown::global().spawn("datalog-sampler", move |stop| loop {
+        for probe in &sensors {
+            if let (Some(log), Ok(r)) = (global(), probe.read()) {
+                log.moisture(probe.name(), r.raw, r.voltage, r.percent);
+            }
+        }
+        if !stop.wait(period) {
+            break;
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("plants-datalog-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn writes_csv_lines() {
+        let dir = temp_dir("lines");
+        let log = DataLog::open(&dir, 1 << 20, 2).unwrap();
+        log.moisture("basil", 21000, 2.625, Some(50.0));
+        log.moisture("mint", 21000, 2.625, None);
+        log.pin(17, true);
+        let text = fs::read_to_string(dir.join(LOG_FILE)).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert!(lines[0].ends_with(",moisture,basil,21000,2.6250,50.0"));
+        assert!(lines[1].ends_with(",moisture,mint,21000,2.6250,"));
+        assert!(lines[2].ends_with(",pin,17,on"));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn rotates_by_size_and_keeps_n_files() {
+        let dir = temp_dir("rotate");
+        let log = DataLog::open(&dir, 100, 2).unwrap();
+        for _ in 0..20 {
+            log.pin(17, true);
+        }
+        assert!(fs::metadata(dir.join(LOG_FILE)).unwrap().len() <= 100);
+        assert!(dir.join("plants.log.1").exists());
+        assert!(dir.join("plants.log.2").exists());
+        assert!(!dir.join("plants.log.3").exists());
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
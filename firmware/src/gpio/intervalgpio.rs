@@ -1,56 +1,47 @@
+use super::registry::{self, PinError, Priority};
+use super::shutdown::{self, WorkerHandle};
 use super::GpioController;
-use rppal::gpio::Gpio;
+use crate::clock::Clock;
 use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::Arc;
-use std::thread;
-use std::time::{Duration, Instant};
+use std::time::Duration;
 
 pub struct IntervalRppalGpioController {
     blink_on: Arc<AtomicBool>,
     interval_ms: Arc<AtomicU64>,
-    _thread: thread::JoinHandle<()>,
+    _worker: WorkerHandle,
 }
 
 impl IntervalRppalGpioController {
-    pub fn new(gpio_pin: u8, invert: bool) -> Self {
+    pub fn new(gpio_pin: u8, invert: bool, clock: Arc<dyn Clock>) -> Result<Self, PinError> {
+        let claim = registry::global().claim(gpio_pin, invert, "interval", Priority::Interval)?;
         let blink_on = Arc::new(AtomicBool::new(true));
         let interval_ms = Arc::new(AtomicU64::new(1000));
 
         let blink_on_t = Arc::clone(&blink_on);
         let interval_t = Arc::clone(&interval_ms);
 
-        let handle = thread::spawn(move || {
+        let worker = shutdown::global().spawn("gpio-interval", move |stop| {
             println!("[gpio] thread start pin={} invert={}", gpio_pin, invert);
-            let gpio = match Gpio::new() {
-                Ok(g) => g,
-                Err(_) => return,
-            };
-            let mut pin = match gpio.get(gpio_pin).and_then(|p| Ok(p.into_output())) {
-                Ok(p) => p,
-                Err(_) => return,
-            };
-
-            let mut last = Instant::now();
+            let mut last = clock.instant();
             let mut state = false;
-            loop {
+            while stop.wait(Duration::from_millis(10)) {
                 let iv = Duration::from_millis(interval_t.load(Ordering::Relaxed));
                 if blink_on_t.load(Ordering::Relaxed) {
-                    if last.elapsed() >= iv {
+                    if clock.instant().saturating_duration_since(last) >= iv {
                         state = !state;
-                        let high = if invert { !state } else { state };
-                        if high { pin.set_high(); } else { pin.set_low(); }
-                        last = Instant::now();
+                        claim.set(state);
+                        last = clock.instant();
                     }
                 } else if state {
-                    let high = if invert { true } else { false };
-                    if high { pin.set_high(); } else { pin.set_low(); }
+                    claim.set(false);
                     state = false;
                 }
-                thread::sleep(Duration::from_millis(10));
             }
+            // Dropping the claim releases the pin back to its safe level
         });
 
-        Self { blink_on, interval_ms, _thread: handle }
+        Ok(Self { blink_on, interval_ms, _worker: worker })
     }
 }
 
@@ -78,7 +69,7 @@ mod tests {
     #[test]
     #[ignore]
     fn construct_interval_controller() {
-        let ctl = IntervalRppalGpioController::new(17, false);
+        let ctl = IntervalRppalGpioController::new(17, false, crate::clock::global()).unwrap();
         // compile-only: do not call methods that interact with hardware
         let _ = ctl.interval_ms();
     }
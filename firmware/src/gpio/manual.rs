@@ -0,0 +1,40 @@
+// Timed manual watering for remote front ends (HTTP API, MQTT): a Manual layer
+// over the pump pin that removes itself after the requested time. The safety
+// interlock still applies underneath.
+
+use super::registry::{self, PinError, Priority};
+use super::shutdown::{self, WorkerHandle};
+use crate::clock::Clock;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Longest manual dose we accept; the safety limits may cut it shorter.
+pub const MAX_SECS: u64 = 600;
+
+pub struct ManualDose {
+    pin: u8,
+    clock: Arc<dyn Clock>,
+    worker: Mutex<Option<WorkerHandle>>,
+}
+
+impl ManualDose {
+    pub fn new(pin: u8, clock: Arc<dyn Clock>) -> Self {
+        Self { pin, clock, worker: Mutex::new(None) }
+    }
+
+    /// Run the pump for `secs` (1..=MAX_SECS), replacing any dose in progress.
+    pub fn start(&self, secs: u64, source: &str) -> Result<(), PinError> {
+        let mut worker = self.worker.lock().unwrap_or_else(|e| e.into_inner());
+        // Stop the running dose first so its layer is gone before we add ours
+        worker.take();
+        let claim = registry::global().layer(self.pin, "manual", Priority::Manual)?;
+        claim.set(true);
+        println!("[{}] manual watering GPIO {} for {}s", source, self.pin, secs);
+        let hold = self.clock.wall(Duration::from_secs(secs));
+        *worker = Some(shutdown::global().spawn("manual-dose", move |stop| {
+            stop.wait(hold);
+            drop(claim);
+        }));
+        Ok(())
+    }
+}
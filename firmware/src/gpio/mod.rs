@@ -1,4 +1,6 @@
 use std::sync::Arc;
+use registry::PinError;
+use crate::clock::Clock;
 
 pub trait GpioController: Send + Sync {
     fn set_blink(&self, on: bool);
@@ -7,38 +9,77 @@ pub trait GpioController: Send + Sync {
     fn interval_ms(&self) -> u64;
 }
 
-#[cfg(feature = "gpio")]
+pub mod output;
+pub mod registry;
+pub mod rules;
+pub mod safety;
+pub mod shutdown;
+// Only the remote front ends (`http`, `mqtt`) start manual doses
+#[cfg_attr(not(any(feature = "http", feature = "mqtt")), allow(dead_code))]
+pub mod manual;
+
+mod moisturegpio;
+// Also drive the virtual lines in `sim` mode
 mod intervalgpio;
-#[cfg(feature = "gpio")]
 mod schedulegpio;
 #[cfg(not(feature = "gpio"))]
 mod stub;
 
-#[cfg(feature = "gpio")]
+pub use moisturegpio::{MoistureGpioController, MIN_SAMPLE_MS};
 pub use intervalgpio::IntervalRppalGpioController;
-#[cfg(feature = "gpio")]
 pub use schedulegpio::ScheduleRppalGpioController;
-#[cfg(feature = "gpio")]
-pub use schedulegpio::GpioSchedule;
-#[cfg(not(feature = "gpio"))]
-#[derive(Debug, Clone, Default)]
-pub struct GpioSchedule;
+pub use schedulegpio::{GpioSchedule, Override};
 
 #[cfg(not(feature = "gpio"))]
 pub use stub::NoopGpioController;
 
-pub fn new_controller(_gpio_pin: u8, _invert: bool, _sched: Option<GpioSchedule>) -> Arc<dyn GpioController + Send + Sync> {
-    #[cfg(feature = "gpio")]
-    {
-        if let Some(s) = _sched {
-            Arc::new(ScheduleRppalGpioController::new(_gpio_pin, _invert, s))
-        } else {
-            Arc::new(IntervalRppalGpioController::new(_gpio_pin, _invert))
-        }
-    }
+/// Whether outputs can be driven: real GPIO, or virtual lines in `sim` mode.
+pub fn available() -> bool {
+    cfg!(feature = "gpio") || crate::sim::global().is_some()
+}
+
+pub fn new_controller(
+    gpio_pin: u8,
+    invert: bool,
+    sched: Option<GpioSchedule>,
+    clock: Arc<dyn Clock>,
+) -> Result<Arc<dyn GpioController + Send + Sync>, PinError> {
     #[cfg(not(feature = "gpio"))]
-    {
-        Arc::new(NoopGpioController::new())
+    if !available() {
+        return Ok(Arc::new(NoopGpioController::new()));
+    }
+    if let Some(s) = sched {
+        Ok(Arc::new(ScheduleRppalGpioController::new(gpio_pin, invert, s, clock)?))
+    } else {
+        Ok(Arc::new(IntervalRppalGpioController::new(gpio_pin, invert, clock)?))
+    }
+}
+
+/// A controller that only remembers what it was told, for the front-end tests.
+#[cfg(all(test, any(feature = "http", feature = "mqtt")))]
+pub(crate) mod fake {
+    use super::GpioController;
+    use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+    #[derive(Default)]
+    pub(crate) struct FakeCtl {
+        blink: AtomicBool,
+        interval: AtomicU64,
+    }
+
+    impl GpioController for FakeCtl {
+        fn set_blink(&self, on: bool) {
+            self.blink.store(on, Ordering::Relaxed);
+        }
+        fn is_blink(&self) -> bool {
+            self.blink.load(Ordering::Relaxed)
+        }
+        fn set_interval_ms(&self, ms: u64) {
+            self.interval.store(ms, Ordering::Relaxed);
+        }
+        fn interval_ms(&self) -> u64 {
+            self.interval.load(Ordering::Relaxed)
+        }
     }
 }
 
@@ -48,7 +89,7 @@ mod tests {
 
     #[test]
     fn controller_roundtrip() {
-        let ctl = new_controller(17, false, None);
+        let ctl = new_controller(17, false, None, crate::clock::global()).unwrap();
         ctl.set_blink(true);
         assert!(ctl.is_blink());
         ctl.set_blink(false);
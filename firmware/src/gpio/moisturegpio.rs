@@ -0,0 +1,282 @@
+use super::registry::{self, PinError, Priority};
+use super::shutdown::{self, WorkerHandle};
+use super::{safety, GpioController};
+use crate::analog::MoistureProbe;
+use crate::clock::Clock;
+use crate::config::WateringConfig;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Shortest sensor sample period; anything lower just keeps the ADC busy.
+pub const MIN_SAMPLE_MS: u64 = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WateringState {
+    Idle,
+    Dosing,
+    Soaking,
+    Fault,
+}
+
+impl fmt::Display for WateringState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            WateringState::Idle => "idle",
+            WateringState::Dosing => "dosing",
+            WateringState::Soaking => "soaking",
+            WateringState::Fault => "fault",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Thresholds {
+    /// Start a dose below this moisture %
+    pub low: f32,
+    /// Stop dosing at or above this moisture %
+    pub high: f32,
+    /// Longest single dose before soaking
+    pub max_dose: Duration,
+    /// Pause after a dose so water can spread before re-measuring
+    pub soak: Duration,
+}
+
+impl From<&WateringConfig> for Thresholds {
+    fn from(c: &WateringConfig) -> Self {
+        Self {
+            low: c.low_percent,
+            high: c.high_percent,
+            max_dose: Duration::from_secs(c.max_dose_secs),
+            soak: Duration::from_secs(c.soak_secs),
+        }
+    }
+}
+
+/// Hysteresis dosing state machine; kept apart from the thread so it can be tested.
+pub struct Doser {
+    th: Thresholds,
+    state: WateringState,
+    since: Instant,
+}
+
+impl Doser {
+    pub fn new(th: Thresholds, now: Instant) -> Self {
+        Self { th, state: WateringState::Idle, since: now }
+    }
+
+    pub fn state(&self) -> WateringState {
+        self.state
+    }
+
+    /// Advance with the latest moisture reading (`None` = no usable reading).
+    /// Returns whether the pump should run.
+    pub fn step(&mut self, now: Instant, moisture: Option<f32>) -> bool {
+        let Some(pct) = moisture else {
+            self.enter(WateringState::Fault, now);
+            return false;
+        };
+        let elapsed = now.saturating_duration_since(self.since);
+        match self.state {
+            WateringState::Idle | WateringState::Fault => {
+                let next = if pct < self.th.low { WateringState::Dosing } else { WateringState::Idle };
+                self.enter(next, now);
+            }
+            WateringState::Dosing => {
+                if pct >= self.th.high {
+                    self.enter(WateringState::Idle, now);
+                } else if elapsed >= self.th.max_dose {
+                    self.enter(WateringState::Soaking, now);
+                }
+            }
+            WateringState::Soaking => {
+                if pct >= self.th.high {
+                    self.enter(WateringState::Idle, now);
+                } else if elapsed >= self.th.soak {
+                    self.enter(WateringState::Dosing, now);
+                }
+            }
+        }
+        self.state == WateringState::Dosing
+    }
+
+    /// Time left in the current dose; None unless dosing.
+    pub fn dose_left(&self, now: Instant) -> Option<Duration> {
+        (self.state == WateringState::Dosing).then(|| self.th.max_dose.saturating_sub(now.saturating_duration_since(self.since)))
+    }
+
+    /// Force the pump off in `state` (disabled or faulted from outside).
+    pub fn halt(&mut self, state: WateringState, now: Instant) {
+        self.enter(state, now);
+    }
+
+    fn enter(&mut self, state: WateringState, now: Instant) {
+        if self.state != state {
+            self.state = state;
+            self.since = now;
+        }
+    }
+}
+
+/// Closed-loop watering: reads the ADS1115 and doses the pump with hysteresis.
+pub struct MoistureGpioController {
+    enabled: Arc<AtomicBool>,
+    sample_ms: Arc<AtomicU64>,
+    state: Arc<Mutex<WateringState>>,
+    pin: u8,
+    _worker: WorkerHandle,
+}
+
+impl MoistureGpioController {
+    pub fn new(invert: bool, cfg: &WateringConfig, probe: Arc<MoistureProbe>, clock: Arc<dyn Clock>) -> Result<Self, PinError> {
+        let pin = cfg.pin;
+        let claim = registry::global().claim(pin, invert, "watering", Priority::Moisture)?;
+        let enabled = Arc::new(AtomicBool::new(true));
+        let sample_ms = Arc::new(AtomicU64::new(cfg.sample_ms));
+        let state = Arc::new(Mutex::new(WateringState::Idle));
+
+        let enabled_t = Arc::clone(&enabled);
+        let sample_t = Arc::clone(&sample_ms);
+        let state_t = Arc::clone(&state);
+        let th = Thresholds::from(cfg);
+
+        let worker = shutdown::global().spawn("gpio-moisture", move |stop| {
+            println!("[gpio-moisture] pin={} low={}% high={}%", pin, th.low, th.high);
+            let mut doser = Doser::new(th, clock.instant());
+            loop {
+                let now = clock.instant();
+                let on = if !enabled_t.load(Ordering::Relaxed) {
+                    doser.halt(WateringState::Idle, now);
+                    false
+                } else if safety::global().faults().iter().any(|(p, _)| *p == pin) {
+                    doser.halt(WateringState::Fault, now);
+                    false
+                } else {
+                    // Uncalibrated or unreadable sensors give `None`, which faults the doser
+                    doser.step(now, probe.read().ok().and_then(|r| r.percent))
+                };
+                claim.set(on);
+                *state_t.lock().unwrap_or_else(|e| e.into_inner()) = doser.state();
+
+                let mut period = Duration::from_millis(sample_t.load(Ordering::Relaxed).max(MIN_SAMPLE_MS));
+                // A long sample period must not stretch a dose past max_dose
+                if let Some(left) = doser.dose_left(now) {
+                    period = period.min(left);
+                }
+                let period = clock.wall(period);
+                let rest = if doser.state() == WateringState::Idle && wait_dry(&probe, th.low, period, &stop) {
+                    Duration::ZERO
+                } else {
+                    period
+                };
+                if !stop.wait(rest) {
+                    break;
+                }
+            }
+        });
+
+        Ok(Self { enabled, sample_ms, state, pin, _worker: worker })
+    }
+
+    pub fn pin(&self) -> u8 {
+        self.pin
+    }
+
+    pub fn state(&self) -> WateringState {
+        *self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+// While idle, let the ADS1115 comparator wake us as soon as the soil dries out
+// instead of sleeping blind. Waits in short slices to notice shutdown; false if
+// the hardware can't do it (see `MoistureProbe::wait_dry`).
+fn wait_dry(probe: &MoistureProbe, low: f32, period: Duration, stop: &shutdown::StopFlag) -> bool {
+    let deadline = Instant::now() + period;
+    while let Some(left) = deadline.checked_duration_since(Instant::now()).filter(|d| !d.is_zero()) {
+        match probe.wait_dry(low, left.min(Duration::from_millis(250))) {
+            Some(Ok(true)) => return true,
+            Some(Ok(false)) => {}
+            Some(Err(_)) | None => return false,
+        }
+        if !stop.wait(Duration::ZERO) {
+            break;
+        }
+    }
+    true
+}
+
+impl GpioController for MoistureGpioController {
+    fn set_blink(&self, on: bool) { // enables/disables automatic watering
+        self.enabled.store(on, Ordering::Relaxed);
+    }
+    fn is_blink(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+    fn set_interval_ms(&self, ms: u64) { // sensor sample period
+        self.sample_ms.store(ms.max(MIN_SAMPLE_MS), Ordering::Relaxed);
+    }
+    fn interval_ms(&self) -> u64 {
+        self.sample_ms.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn th() -> Thresholds {
+        Thresholds { low: 30.0, high: 60.0, max_dose: Duration::from_secs(10), soak: Duration::from_secs(60) }
+    }
+
+    fn at(t0: Instant, s: u64) -> Instant {
+        t0 + Duration::from_secs(s)
+    }
+
+    #[test]
+    fn hysteresis_between_thresholds() {
+        let t0 = Instant::now();
+        let mut d = Doser::new(th(), t0);
+        assert!(!d.step(t0, Some(45.0)));
+        assert_eq!(d.state(), WateringState::Idle);
+        assert!(d.step(at(t0, 1), Some(25.0)));
+        // Still below high: keep dosing even though above low
+        assert!(d.step(at(t0, 2), Some(45.0)));
+        assert!(!d.step(at(t0, 3), Some(61.0)));
+        assert_eq!(d.state(), WateringState::Idle);
+    }
+
+    #[test]
+    fn max_dose_then_soak_then_dose_again() {
+        let t0 = Instant::now();
+        let mut d = Doser::new(th(), t0);
+        assert!(d.step(t0, Some(20.0)));
+        assert!(!d.step(at(t0, 10), Some(25.0)));
+        assert_eq!(d.state(), WateringState::Soaking);
+        assert!(!d.step(at(t0, 40), Some(40.0)));
+        assert!(d.step(at(t0, 70), Some(40.0)));
+        assert_eq!(d.state(), WateringState::Dosing);
+    }
+
+    #[test]
+    fn dose_left_counts_down_while_dosing() {
+        let t0 = Instant::now();
+        let mut d = Doser::new(th(), t0);
+        assert_eq!(d.dose_left(t0), None);
+        d.step(t0, Some(20.0));
+        assert_eq!(d.dose_left(at(t0, 4)), Some(Duration::from_secs(6)));
+        d.step(at(t0, 10), Some(25.0));
+        assert_eq!(d.dose_left(at(t0, 10)), None);
+    }
+
+    #[test]
+    fn missing_reading_faults_and_recovers() {
+        let t0 = Instant::now();
+        let mut d = Doser::new(th(), t0);
+        assert!(d.step(t0, Some(20.0)));
+        assert!(!d.step(at(t0, 1), None));
+        assert_eq!(d.state(), WateringState::Fault);
+        assert!(d.step(at(t0, 2), Some(20.0)));
+    }
+}
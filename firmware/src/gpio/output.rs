@@ -0,0 +1,260 @@
+use super::safety::{self, Fault, Interlock};
+use super::shutdown;
+use crate::{clock, datalog};
+use std::error::Error;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// A physical output line. `write(true)` drives the line high.
+pub trait OutputPin: Send {
+    fn write(&mut self, high: bool);
+}
+
+#[cfg(feature = "gpio")]
+impl OutputPin for rppal::gpio::OutputPin {
+    fn write(&mut self, high: bool) {
+        if high { self.set_high(); } else { self.set_low(); }
+    }
+}
+
+/// A line that exists only in memory (`sim` mode); `Output` keeps its state.
+pub struct VirtualPin;
+
+impl OutputPin for VirtualPin {
+    fn write(&mut self, _high: bool) {}
+}
+
+/// Physical level that switches the load off: low normally, high when `invert` is set
+/// (active-low relay boards).
+pub fn safe_level(invert: bool) -> bool {
+    invert
+}
+
+/// A GPIO output with a known safe state.
+/// Callers switch it logically on/off; `invert` is applied here and every request
+/// passes through the optional safety `Interlock`. Dropping the output (including
+/// during a panic unwind) drives it to the safe level.
+pub struct Output {
+    pin: u8,
+    invert: bool,
+    on: AtomicBool,
+    // What the controllers last asked for; differs from `on` while the interlock holds it back
+    want: AtomicBool,
+    interlock: Option<Interlock>,
+    line: Mutex<Box<dyn OutputPin>>,
+}
+
+impl Output {
+    pub fn new(pin: u8, invert: bool, line: Box<dyn OutputPin>) -> Self {
+        let out = Self { pin, invert, on: AtomicBool::new(false), want: AtomicBool::new(false), interlock: None, line: Mutex::new(line) };
+        out.set_safe();
+        out
+    }
+
+    pub fn with_interlock(mut self, interlock: Interlock) -> Self {
+        self.interlock = Some(interlock);
+        self
+    }
+
+    pub fn pin(&self) -> u8 {
+        self.pin
+    }
+
+    pub fn is_on(&self) -> bool {
+        self.on.load(Ordering::Relaxed)
+    }
+
+    /// Switch the load on or off (logical level, before `invert`).
+    /// The interlock may refuse an ON request, in which case the line goes safe.
+    pub fn set(&self, on: bool) {
+        let mut line = self.line();
+        self.want.store(on, Ordering::Relaxed);
+        let on = match &self.interlock {
+            Some(il) => il.admit(on, clock::global().instant(), safety::today()),
+            None => on,
+        };
+        self.write(&mut line, on);
+    }
+
+    /// Drive the line to its safe level.
+    pub fn set_safe(&self) {
+        let mut line = self.line();
+        self.want.store(false, Ordering::Relaxed);
+        if let Some(il) = &self.interlock {
+            il.record_off(clock::global().instant());
+        }
+        self.write(&mut line, false);
+    }
+
+    /// Let the interlock force the line off if a limit ran out while it was ON,
+    /// and switch on a request the cooldown held back once it is over.
+    pub fn check(&self) {
+        let mut line = self.line();
+        if let Some(il) = &self.interlock {
+            let (now, today) = (clock::global().instant(), safety::today());
+            if !il.check(now, today) {
+                if self.is_on() {
+                    self.write(&mut line, false);
+                }
+            } else if self.want.load(Ordering::Relaxed) && !self.is_on() {
+                let on = il.admit(true, now, today);
+                self.write(&mut line, on);
+            }
+        }
+    }
+
+    pub fn fault(&self) -> Option<Fault> {
+        self.interlock.as_ref().and_then(Interlock::fault)
+    }
+
+    pub fn acknowledge(&self) {
+        if let Some(il) = &self.interlock {
+            il.acknowledge();
+        }
+    }
+
+    pub fn set_limits(&self, limits: safety::Limits) {
+        if let Some(il) = &self.interlock {
+            il.set_limits(limits);
+        }
+    }
+
+    fn write(&self, line: &mut Box<dyn OutputPin>, on: bool) {
+        let high = if on { !safe_level(self.invert) } else { safe_level(self.invert) };
+        line.write(high);
+        if self.on.swap(on, Ordering::Relaxed) != on {
+            if let Some(log) = datalog::global() {
+                log.pin(self.pin, on);
+            }
+        }
+    }
+
+    // A writer that panicked mid-write must not stop us from reaching the safe level.
+    fn line(&self) -> MutexGuard<'_, Box<dyn OutputPin>> {
+        self.line.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Drop for Output {
+    fn drop(&mut self) {
+        self.set_safe();
+    }
+}
+
+/// Claim `pin` as an output on the Pi (or a virtual line in `sim` mode), behind
+/// the configured safety limits and registered with the shutdown coordinator.
+/// The line keeps its last (safe) level after the process exits instead of
+/// floating back to an input.
+pub fn open(pin: u8, invert: bool) -> Result<Arc<Output>, Box<dyn Error>> {
+    if crate::sim::global().is_some() {
+        return Ok(register(Output::new(pin, invert, Box::new(VirtualPin))));
+    }
+    #[cfg(feature = "gpio")]
+    {
+        let mut line = rppal::gpio::Gpio::new()?.get(pin)?.into_output();
+        line.set_reset_on_drop(false);
+        Ok(register(Output::new(pin, invert, Box::new(line))))
+    }
+    #[cfg(not(feature = "gpio"))]
+    Err(format!("GPIO {} requires 'gpio' feature (or --sim)", pin).into())
+}
+
+fn register(out: Output) -> Arc<Output> {
+    let limits = safety::global().limits_for(out.pin());
+    let out = Arc::new(out.with_interlock(Interlock::new(limits)));
+    shutdown::global().register_output(&out);
+    safety::global().register(&out);
+    out
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    /// Records every level written, for tests that need to see the physical line.
+    #[derive(Clone, Default)]
+    pub(crate) struct FakePin(pub Arc<Mutex<Vec<bool>>>);
+
+    impl FakePin {
+        pub(crate) fn last(&self) -> Option<bool> {
+            self.0.lock().unwrap().last().copied()
+        }
+    }
+
+    impl OutputPin for FakePin {
+        fn write(&mut self, high: bool) {
+            self.0.lock().unwrap().push(high);
+        }
+    }
+
+    #[test]
+    fn invert_maps_logical_level() {
+        let fake = FakePin::default();
+        let out = Output::new(17, true, Box::new(fake.clone()));
+        assert_eq!(fake.last(), Some(true)); // safe on construction
+        out.set(true);
+        assert_eq!(fake.last(), Some(false));
+    }
+
+    #[test]
+    fn interlock_forces_line_safe() {
+        let fake = FakePin::default();
+        let limits = safety::Limits { max_on: Some(std::time::Duration::ZERO), ..Default::default() };
+        let out = Output::new(17, false, Box::new(fake.clone())).with_interlock(Interlock::new(limits));
+        out.set(true);
+        assert_eq!(fake.last(), Some(false));
+        assert_eq!(out.fault(), Some(Fault::MaxOn));
+    }
+
+    #[test]
+    fn cooldown_holds_on_until_it_is_over() {
+        let fake = FakePin::default();
+        let limits = safety::Limits { min_off: Some(std::time::Duration::from_millis(50)), ..Default::default() };
+        let out = Output::new(22, false, Box::new(fake.clone())).with_interlock(Interlock::new(limits));
+        out.set(true);
+        out.set(false);
+        out.set(true);
+        assert!(!out.is_on());
+        out.check();
+        assert!(!out.is_on());
+        std::thread::sleep(std::time::Duration::from_millis(60));
+        out.check();
+        assert!(out.is_on());
+        assert_eq!(fake.last(), Some(true));
+        assert_eq!(out.fault(), None);
+    }
+
+    #[test]
+    fn new_limits_reach_open_outputs() {
+        let monitor = safety::Monitor::default();
+        let fake = FakePin::default();
+        let pump = Arc::new(Output::new(22, false, Box::new(fake.clone())).with_interlock(Interlock::new(monitor.limits_for(22))));
+        monitor.register(&pump);
+        pump.set(true);
+        pump.check();
+        assert!(pump.is_on());
+        // A reload that adds max_on applies to the pump that is already running
+        let limits = safety::Limits { max_on: Some(std::time::Duration::ZERO), ..Default::default() };
+        monitor.configure(limits, vec![22]);
+        pump.check();
+        assert_eq!(fake.last(), Some(false));
+        assert_eq!(pump.fault(), Some(Fault::MaxOn));
+        // Taking the pin off the pump list lifts them again
+        monitor.configure(limits, vec![]);
+        pump.acknowledge();
+        pump.set(true);
+        assert!(pump.is_on());
+    }
+
+    #[test]
+    fn drop_drives_safe_level() {
+        for invert in [false, true] {
+            let fake = FakePin::default();
+            let out = Output::new(17, invert, Box::new(fake.clone()));
+            out.set(true);
+            drop(out);
+            assert_eq!(fake.last(), Some(safe_level(invert)));
+        }
+    }
+}
@@ -0,0 +1,254 @@
+// Central pin registry. Each GPIO has exactly one owner; other callers may only
+// stack explicit priority layers on top of it (e.g. a manual override).
+
+use super::output::{self, Output};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock, Weak};
+
+/// Who wins when several layers drive one pin. Higher wins.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Priority {
+    Interval,
+    Schedule,
+    Moisture,
+    Manual,
+}
+
+impl fmt::Display for Priority {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Priority::Interval => "interval",
+            Priority::Schedule => "schedule",
+            Priority::Moisture => "moisture",
+            Priority::Manual => "manual",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+#[derive(Debug)]
+pub enum PinError {
+    /// The pin already has an owner
+    InUse { pin: u8, owner: String, requested_by: String },
+    /// A layer can only be stacked on a pin someone owns
+    NotOwned { pin: u8, requested_by: String },
+    /// Two layers with the same priority would be ambiguous
+    DuplicateLayer { pin: u8, priority: Priority, existing: String, requested_by: String },
+    /// The hardware refused the pin
+    Unavailable { pin: u8, reason: String },
+}
+
+impl fmt::Display for PinError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PinError::InUse { pin, owner, requested_by } => write!(
+                f,
+                "GPIO {} is already owned by '{}'; '{}' cannot use it (pick a different pin in config)",
+                pin, owner, requested_by
+            ),
+            PinError::NotOwned { pin, requested_by } => {
+                write!(f, "GPIO {} has no owner to layer '{}' on", pin, requested_by)
+            }
+            PinError::DuplicateLayer { pin, priority, existing, requested_by } => write!(
+                f,
+                "GPIO {} already has a {} layer '{}'; '{}' cannot add another",
+                pin, priority, existing, requested_by
+            ),
+            PinError::Unavailable { pin, reason } => write!(f, "GPIO {} unavailable: {}", pin, reason),
+        }
+    }
+}
+
+impl Error for PinError {}
+
+type Opener = Box<dyn Fn(u8, bool) -> Result<Arc<Output>, Box<dyn Error>> + Send + Sync>;
+
+struct Layer {
+    id: u64,
+    name: String,
+    priority: Priority,
+    request: Option<bool>,
+}
+
+struct Line {
+    owner: String,
+    output: Arc<Output>,
+    layers: Mutex<Vec<Layer>>,
+}
+
+impl Line {
+    // Highest-priority layer with an active request decides; nobody asking means off.
+    fn resolve(&self, layers: &[Layer]) {
+        let on = layers
+            .iter()
+            .filter_map(|l| l.request.map(|r| (l.priority, r)))
+            .max_by_key(|(p, _)| *p)
+            .is_some_and(|(_, r)| r);
+        self.output.set(on);
+    }
+}
+
+/// A handle on one layer of a pin: the owner's own layer, or one stacked on it.
+/// Dropping it removes the layer; when the last handle goes the pin returns to safe.
+pub struct PinClaim {
+    id: u64,
+    line: Arc<Line>,
+}
+
+impl PinClaim {
+    /// Ask for the load on or off at this layer's priority.
+    pub fn set(&self, on: bool) {
+        self.request(Some(on));
+    }
+
+    fn request(&self, req: Option<bool>) {
+        let mut layers = lock(&self.line.layers);
+        if let Some(l) = layers.iter_mut().find(|l| l.id == self.id) {
+            l.request = req;
+        }
+        self.line.resolve(&layers);
+    }
+}
+
+impl Drop for PinClaim {
+    fn drop(&mut self) {
+        let mut layers = lock(&self.line.layers);
+        layers.retain(|l| l.id != self.id);
+        self.line.resolve(&layers);
+    }
+}
+
+pub struct Registry {
+    opener: Opener,
+    lines: Mutex<HashMap<u8, Weak<Line>>>,
+    next_id: AtomicU64,
+}
+
+impl Registry {
+    pub fn new(opener: Opener) -> Self {
+        Self { opener, lines: Mutex::new(HashMap::new()), next_id: AtomicU64::new(0) }
+    }
+
+    /// Take exclusive ownership of `pin`. Fails if anyone else owns it.
+    pub fn claim(&self, pin: u8, invert: bool, owner: &str, priority: Priority) -> Result<PinClaim, PinError> {
+        let mut lines = lock(&self.lines);
+        if let Some(line) = lines.get(&pin).and_then(Weak::upgrade) {
+            return Err(PinError::InUse { pin, owner: line.owner.clone(), requested_by: owner.to_string() });
+        }
+        let output = (self.opener)(pin, invert).map_err(|e| PinError::Unavailable { pin, reason: e.to_string() })?;
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let line = Arc::new(Line {
+            owner: owner.to_string(),
+            output,
+            layers: Mutex::new(vec![Layer { id, name: owner.to_string(), priority, request: None }]),
+        });
+        lines.insert(pin, Arc::downgrade(&line));
+        Ok(PinClaim { id, line })
+    }
+
+    /// Stack a layer on a pin that someone already owns.
+    pub fn layer(&self, pin: u8, name: &str, priority: Priority) -> Result<PinClaim, PinError> {
+        let line = lock(&self.lines)
+            .get(&pin)
+            .and_then(Weak::upgrade)
+            .ok_or_else(|| PinError::NotOwned { pin, requested_by: name.to_string() })?;
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        {
+            let mut layers = lock(&line.layers);
+            if let Some(l) = layers.iter().find(|l| l.priority == priority) {
+                return Err(PinError::DuplicateLayer {
+                    pin,
+                    priority,
+                    existing: l.name.clone(),
+                    requested_by: name.to_string(),
+                });
+            }
+            layers.push(Layer { id, name: name.to_string(), priority, request: None });
+        }
+        Ok(PinClaim { id, line })
+    }
+
+    /// Current level of every owned pin, sorted by pin number.
+    pub fn states(&self) -> Vec<(u8, bool)> {
+        let mut out: Vec<(u8, bool)> = lock(&self.lines)
+            .iter()
+            .filter_map(|(pin, line)| line.upgrade().map(|l| (*pin, l.output.is_on())))
+            .collect();
+        out.sort();
+        out
+    }
+}
+
+/// Process-wide registry backed by the real GPIO lines.
+pub fn global() -> &'static Registry {
+    static GLOBAL: OnceLock<Registry> = OnceLock::new();
+    GLOBAL.get_or_init(|| Registry::new(Box::new(output::open)))
+}
+
+fn lock<T>(m: &Mutex<T>) -> MutexGuard<'_, T> {
+    m.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gpio::output::tests::FakePin;
+
+    fn fake_registry() -> (Registry, FakePin) {
+        let fake = FakePin::default();
+        let f = fake.clone();
+        let reg = Registry::new(Box::new(move |pin, invert| {
+            Ok(Arc::new(Output::new(pin, invert, Box::new(f.clone()))))
+        }));
+        (reg, fake)
+    }
+
+    #[test]
+    fn second_owner_is_refused() {
+        let (reg, _) = fake_registry();
+        let _sched = reg.claim(27, false, "schedule", Priority::Schedule).unwrap();
+        let err = reg.claim(27, false, "interval", Priority::Interval).err().unwrap();
+        assert!(matches!(err, PinError::InUse { pin: 27, .. }));
+        assert!(err.to_string().contains("'schedule'"));
+    }
+
+    #[test]
+    fn higher_layer_wins_and_release_falls_back() {
+        let (reg, fake) = fake_registry();
+        let sched = reg.claim(17, false, "schedule", Priority::Schedule).unwrap();
+        let manual = reg.layer(17, "manual", Priority::Manual).unwrap();
+        assert!(reg.layer(17, "other", Priority::Manual).is_err());
+
+        sched.set(true);
+        assert_eq!(fake.last(), Some(true));
+        manual.set(false);
+        assert_eq!(fake.last(), Some(false));
+        // Dropping the layer hands the pin back to the owner's request
+        drop(manual);
+        assert_eq!(fake.last(), Some(true));
+    }
+
+    #[test]
+    fn dropping_owner_frees_pin() {
+        let (reg, fake) = fake_registry();
+        let claim = reg.claim(17, false, "interval", Priority::Interval).unwrap();
+        claim.set(true);
+        drop(claim);
+        assert_eq!(fake.last(), Some(false));
+        assert!(reg.claim(17, false, "schedule", Priority::Schedule).is_ok());
+    }
+
+    #[test]
+    fn states_lists_owned_pins() {
+        let (reg, _) = fake_registry();
+        let a = reg.claim(22, false, "watering", Priority::Moisture).unwrap();
+        let _b = reg.claim(17, false, "interval", Priority::Interval).unwrap();
+        a.set(true);
+        assert_eq!(reg.states(), vec![(17, false), (22, true)]);
+        drop(a);
+        assert_eq!(reg.states(), vec![(17, false)]);
+    }
+}
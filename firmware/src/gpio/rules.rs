@@ -0,0 +1,461 @@
+// Schedule rules that don't fit the weekday table: cron expressions and
+// "every N hours from T", each firing timed runs of `secs` seconds, and the
+// date spans that switch to another rule set.
+
+use crate::config::{ScheduleOverride, ScheduleRule};
+use chrono::{Datelike, NaiveDate, Weekday};
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Rule {
+    Cron { cron: Cron, secs: u32 },
+    // Every `hours` from `start` (seconds since midnight) until midnight, again each day
+    Every { hours: u32, start: u32, secs: u32 },
+}
+
+impl Rule {
+    /// Check a `[[schedule_rules]]` entry; the error says exactly what is wrong.
+    pub fn from_config(r: &ScheduleRule) -> Result<Rule, String> {
+        if !(1..=86400).contains(&r.secs) {
+            return Err(format!("secs = {} is outside 1..=86400", r.secs));
+        }
+        match (&r.cron, r.every_hours) {
+            (Some(expr), None) => {
+                if r.start.is_some() {
+                    return Err("start only applies to every_hours".to_string());
+                }
+                Ok(Rule::Cron { cron: Cron::parse(expr)?, secs: r.secs })
+            }
+            (None, Some(hours)) => {
+                if !(1..=24).contains(&hours) {
+                    return Err(format!("every_hours = {} is outside 1..=24", hours));
+                }
+                let start = match &r.start {
+                    Some(s) => parse_time_of_day(s)
+                        .ok_or_else(|| format!("start '{}' is not a time of day (HH:MM or HH:MM:SS)", s))?,
+                    None => 0,
+                };
+                Ok(Rule::Every { hours, start, secs: r.secs })
+            }
+            (Some(_), Some(_)) => Err("set either cron or every_hours, not both".to_string()),
+            (None, None) => Err("needs cron or every_hours".to_string()),
+        }
+    }
+
+    /// Runs that start on `day`: (start in seconds since midnight, duration in seconds).
+    pub fn runs(&self, day: NaiveDate) -> Vec<(u32, u32)> {
+        match self {
+            Rule::Cron { cron, secs } => {
+                if !cron.matches_day(day) {
+                    return Vec::new();
+                }
+                let mut out = Vec::new();
+                for h in bits(cron.hour, 0, 23) {
+                    for m in bits(cron.minute, 0, 59) {
+                        out.push((h * 3600 + m * 60, *secs));
+                    }
+                }
+                out
+            }
+            Rule::Every { hours, start, secs } => {
+                (*start..86400).step_by(*hours as usize * 3600).map(|s| (s, *secs)).collect()
+            }
+        }
+    }
+}
+
+/// A standard five-field cron expression: minute hour day-of-month month day-of-week.
+/// Fields take `*`, numbers, `a-b` ranges, `/step` and comma lists; months and
+/// weekdays also take three-letter names, and weekday 7 is Sunday like 0.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cron {
+    minute: u64,
+    hour: u64,
+    dom: u64,
+    month: u64,
+    dow: u64,
+    // A restricted day-of-month and day-of-week match either one, as in cron(8)
+    dom_any: bool,
+    dow_any: bool,
+}
+
+const MONTHS: [&str; 12] = ["jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec"];
+const WEEKDAYS: [&str; 7] = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+
+impl Cron {
+    pub fn parse(expr: &str) -> Result<Cron, String> {
+        let fields: Vec<&str> = expr.split_whitespace().collect();
+        let err = |e: String| format!("cron '{}': {}", expr, e);
+        if fields.len() != 5 {
+            return Err(err(format!(
+                "expected 5 fields (minute hour day-of-month month day-of-week), found {}",
+                fields.len()
+            )));
+        }
+        let minute = field(fields[0], "minute", 0, 59, &[]).map_err(err)?;
+        let hour = field(fields[1], "hour", 0, 23, &[]).map_err(err)?;
+        let dom = field(fields[2], "day-of-month", 1, 31, &[]).map_err(err)?;
+        let month = field(fields[3], "month", 1, 12, &MONTHS).map_err(err)?;
+        let mut dow = field(fields[4], "day-of-week", 0, 7, &WEEKDAYS).map_err(err)?;
+        if dow & (1 << 7) != 0 {
+            dow = (dow | 1) & !(1 << 7);
+        }
+        // Like cron(8), `*/2` still counts as unrestricted here
+        let any = |f: &str| f.starts_with('*');
+        Ok(Cron { minute, hour, dom, month, dow, dom_any: any(fields[2]), dow_any: any(fields[4]) })
+    }
+
+    fn matches_day(&self, day: NaiveDate) -> bool {
+        let dom = self.dom & (1 << day.day()) != 0;
+        let dow = self.dow & (1 << day.weekday().num_days_from_sunday()) != 0;
+        let day_ok = if self.dom_any || self.dow_any { dom && dow } else { dom || dow };
+        self.month & (1 << day.month()) != 0 && day_ok
+    }
+}
+
+// One field as a bit set over min..=max
+fn field(text: &str, name: &str, min: u32, max: u32, names: &[&str]) -> Result<u64, String> {
+    let value = |s: &str| -> Result<u32, String> {
+        let v = match names.iter().position(|n| n.eq_ignore_ascii_case(s)) {
+            Some(i) => i as u32 + min,
+            None => s.parse().map_err(|_| format!("{} '{}' is not a number", name, s))?,
+        };
+        if !(min..=max).contains(&v) {
+            return Err(format!("{} value {} is outside {}-{}", name, v, min, max));
+        }
+        Ok(v)
+    };
+    let mut set = 0u64;
+    for item in text.split(',') {
+        let (range, step) = match item.split_once('/') {
+            Some((r, s)) => match s.parse::<u32>() {
+                Ok(s) if s > 0 => (r, s),
+                _ => return Err(format!("{} step '{}' must be a positive number", name, s)),
+            },
+            None => (item, 1),
+        };
+        let (lo, hi) = if range == "*" {
+            (min, max)
+        } else if let Some((a, b)) = range.split_once('-') {
+            let (lo, hi) = (value(a)?, value(b)?);
+            if lo > hi {
+                return Err(format!("{} range {} runs backwards", name, range));
+            }
+            (lo, hi)
+        } else if range.is_empty() {
+            return Err(format!("{} has an empty item in '{}'", name, text));
+        } else {
+            let v = value(range)?;
+            // `5/15` means from 5 to the end in steps of 15
+            (v, if item.contains('/') { max } else { v })
+        };
+        for v in (lo..=hi).step_by(step as usize) {
+            set |= 1 << v;
+        }
+    }
+    Ok(set)
+}
+
+fn bits(set: u64, min: u32, max: u32) -> impl Iterator<Item = u32> {
+    (min..=max).filter(move |v| set & (1 << v) != 0)
+}
+
+/// Days an override applies to, both ends included: fixed dates, or month-days
+/// that come back every year and may wrap past New Year.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DateSpan {
+    Dates(NaiveDate, NaiveDate),
+    Yearly((u32, u32), (u32, u32)),
+}
+
+impl DateSpan {
+    /// `from`/`to` as "YYYY-MM-DD", or both as "MM-DD" for every year.
+    pub fn parse(from: &str, to: &str) -> Result<DateSpan, String> {
+        let date = |s: &str| NaiveDate::parse_from_str(s.trim(), "%Y-%m-%d").ok();
+        // Any leap year will do to check the day exists
+        let month_day = |s: &str| {
+            let d = NaiveDate::parse_from_str(&format!("2000-{}", s.trim()), "%Y-%m-%d").ok()?;
+            (s.trim().len() == 5).then_some((d.month(), d.day()))
+        };
+        match (date(from), date(to)) {
+            (Some(a), Some(b)) if a <= b => Ok(DateSpan::Dates(a, b)),
+            (Some(_), Some(_)) => Err(format!("to {} is before from {}", to, from)),
+            _ => match (month_day(from), month_day(to)) {
+                (Some(a), Some(b)) => Ok(DateSpan::Yearly(a, b)),
+                _ => Err(format!(
+                    "from '{}' / to '{}' must both be dates (YYYY-MM-DD) or both month-days (MM-DD)",
+                    from, to
+                )),
+            },
+        }
+    }
+
+    pub fn contains(&self, day: NaiveDate) -> bool {
+        match *self {
+            DateSpan::Dates(a, b) => a <= day && day <= b,
+            DateSpan::Yearly(a, b) => {
+                let md = (day.month(), day.day());
+                if a <= b { a <= md && md <= b } else { md >= a || md <= b }
+            }
+        }
+    }
+}
+
+/// Check a `[[schedule_overrides]]` entry (apart from its weekday table) and
+/// return the days it covers.
+pub fn check_override(o: &ScheduleOverride) -> Result<DateSpan, String> {
+    let span = DateSpan::parse(&o.from, &o.to)?;
+    if !(o.scale > 0.0 && o.scale <= 10.0) {
+        return Err(format!("scale = {} is outside 0..=10", o.scale));
+    }
+    if o.skip && (o.schedule.is_some() || !o.rules.is_empty()) {
+        return Err("skip can't be combined with schedule or rules".to_string());
+    }
+    for (i, r) in o.rules.iter().enumerate() {
+        Rule::from_config(r).map_err(|e| format!("rules[{}]: {}", i, e))?;
+    }
+    Ok(span)
+}
+
+impl fmt::Display for DateSpan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DateSpan::Dates(a, b) => write!(f, "{}..{}", a, b),
+            DateSpan::Yearly(a, b) => write!(f, "{:02}-{:02}..{:02}-{:02}", a.0, a.1, b.0, b.1),
+        }
+    }
+}
+
+/// A time of day for a sun-relative entry: fixed, or sunrise/sunset plus an offset.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TimeOfDay {
+    Fixed(u32),
+    // Offsets in seconds, negative = before
+    Sunrise(i64),
+    Sunset(i64),
+}
+
+impl TimeOfDay {
+    /// "sunrise", "sunset-30m", "sunrise+1h30m", or a fixed "HH:MM[:SS]".
+    pub fn parse(s: &str) -> Result<TimeOfDay, String> {
+        let t = s.trim().to_lowercase();
+        let (event, rest): (fn(i64) -> TimeOfDay, &str) = if let Some(rest) = t.strip_prefix("sunrise") {
+            (TimeOfDay::Sunrise, rest)
+        } else if let Some(rest) = t.strip_prefix("sunset") {
+            (TimeOfDay::Sunset, rest)
+        } else {
+            return parse_time_of_day(&t)
+                .map(TimeOfDay::Fixed)
+                .ok_or_else(|| format!("'{}' is not sunrise, sunset or a time of day (HH:MM)", s));
+        };
+        if rest.is_empty() {
+            return Ok(event(0));
+        }
+        let bad = || format!("'{}': offset must look like +30m, -2h or +1h30m", s);
+        let (sign, mut rest) = match (rest.strip_prefix('+'), rest.strip_prefix('-')) {
+            (Some(r), _) if !r.is_empty() => (1, r),
+            (_, Some(r)) if !r.is_empty() => (-1, r),
+            _ => return Err(bad()),
+        };
+        let mut secs = 0i64;
+        while !rest.is_empty() {
+            let digits = rest.find(|c: char| !c.is_ascii_digit()).ok_or_else(bad)?;
+            let n: i64 = rest[..digits].parse().map_err(|_| bad())?;
+            let unit = rest[digits..].chars().next().unwrap_or_default();
+            let unit_secs = match unit {
+                'h' => 3600,
+                'm' => 60,
+                's' => 1,
+                _ => return Err(bad()),
+            };
+            // Checked per part, so a huge offset can't overflow
+            secs = n
+                .checked_mul(unit_secs)
+                .and_then(|d| secs.checked_add(d))
+                .filter(|t| *t <= 12 * 3600)
+                .ok_or_else(|| format!("'{}': offset is more than 12h", s))?;
+            rest = &rest[digits + unit.len_utf8()..];
+        }
+        Ok(event(sign * secs))
+    }
+
+    /// Seconds from local midnight, given that day's sunrise and sunset.
+    pub fn resolve(&self, sun: Option<(i64, i64)>) -> Option<i64> {
+        match *self {
+            TimeOfDay::Fixed(secs) => Some(secs as i64),
+            TimeOfDay::Sunrise(offset) => sun.map(|(rise, _)| rise + offset),
+            TimeOfDay::Sunset(offset) => sun.map(|(_, set)| set + offset),
+        }
+    }
+
+    pub fn is_fixed(&self) -> bool {
+        matches!(self, TimeOfDay::Fixed(_))
+    }
+}
+
+/// A `[schedule]` day name: "Monday", "mon", "Tues", ...
+pub fn parse_weekday(s: &str) -> Option<Weekday> {
+    let t = s.trim().to_lowercase();
+    match t.as_str() {
+        "mon" | "monday" => Some(Weekday::Mon),
+        "tue" | "tues" | "tuesday" => Some(Weekday::Tue),
+        "wed" | "weds" | "wednesday" => Some(Weekday::Wed),
+        "thu" | "thur" | "thurs" | "thursday" => Some(Weekday::Thu),
+        "fri" | "friday" => Some(Weekday::Fri),
+        "sat" | "saturday" => Some(Weekday::Sat),
+        "sun" | "sunday" => Some(Weekday::Sun),
+        _ => None,
+    }
+}
+
+/// "HH:MM:SS" or "HH:MM" -> seconds since midnight
+pub fn parse_time_of_day(s: &str) -> Option<u32> {
+    let parts: Vec<u32> = s.trim().split(':').map(|p| p.parse().ok()).collect::<Option<_>>()?;
+    let (h, m, sec) = match parts[..] {
+        [h, m] => (h, m, 0),
+        [h, m, sec] => (h, m, sec),
+        _ => return None,
+    };
+    (h < 24 && m < 60 && sec < 60).then_some(h * 3600 + m * 60 + sec)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(cron: Option<&str>, every_hours: Option<u32>, start: Option<&str>, secs: u32) -> Result<Rule, String> {
+        Rule::from_config(&ScheduleRule {
+            cron: cron.map(str::to_string),
+            every_hours,
+            start: start.map(str::to_string),
+            secs,
+        })
+    }
+
+    fn day(y: i32, m: u32, d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(y, m, d).unwrap()
+    }
+
+    #[test]
+    fn cron_fires_on_matching_days_and_times() {
+        let r = rule(Some("0 7,19 * * *"), None, None, 45).unwrap();
+        assert_eq!(r.runs(day(2024, 3, 31)), vec![(7 * 3600, 45), (19 * 3600, 45)]);
+
+        // Weekdays every 20 minutes from 06:10 to 07:59, weekday names and ranges
+        let r = rule(Some("10/20 6-7 * * mon-fri"), None, None, 30).unwrap();
+        let mins: Vec<u32> = r.runs(day(2024, 4, 1)).iter().map(|(s, _)| s / 60).collect();
+        assert_eq!(mins, vec![370, 390, 410, 430, 450, 470]);
+        assert!(r.runs(day(2024, 3, 31)).is_empty());
+
+        // Sunday as 7; with a day-of-month too, either one matches
+        let r = rule(Some("30 22 1 jun 7"), None, None, 60).unwrap();
+        assert_eq!(r.runs(day(2024, 6, 1)).len(), 1);
+        assert_eq!(r.runs(day(2024, 6, 2)).len(), 1);
+        assert!(r.runs(day(2024, 6, 3)).is_empty());
+        assert!(r.runs(day(2024, 7, 1)).is_empty());
+
+        // A stepped `*` is unrestricted: odd days that are also Mondays
+        let r = rule(Some("0 6 */2 * 1"), None, None, 60).unwrap();
+        assert_eq!(r.runs(day(2024, 4, 1)).len(), 1);
+        assert!(r.runs(day(2024, 4, 3)).is_empty());
+        assert!(r.runs(day(2024, 4, 8)).is_empty());
+    }
+
+    #[test]
+    fn every_n_hours_restarts_each_day() {
+        let r = rule(None, Some(5), Some("06:00"), 20).unwrap();
+        let hours: Vec<u32> = r.runs(day(2024, 1, 1)).iter().map(|(s, _)| s / 3600).collect();
+        assert_eq!(hours, vec![6, 11, 16, 21]);
+        let r = rule(None, Some(24), None, 20).unwrap();
+        assert_eq!(r.runs(day(2024, 1, 1)), vec![(0, 20)]);
+    }
+
+    #[test]
+    fn errors_say_what_is_wrong() {
+        let err = |cron: &str| rule(Some(cron), None, None, 10).unwrap_err();
+        assert_eq!(err("0 25 * * *"), "cron '0 25 * * *': hour value 25 is outside 0-23");
+        assert_eq!(
+            err("0 7 * *"),
+            "cron '0 7 * *': expected 5 fields (minute hour day-of-month month day-of-week), found 4"
+        );
+        assert_eq!(err("x 7 * * *"), "cron 'x 7 * * *': minute 'x' is not a number");
+        assert_eq!(err("0 7 * * fri-mon"), "cron '0 7 * * fri-mon': day-of-week range fri-mon runs backwards");
+        assert_eq!(err("*/0 * * * *"), "cron '*/0 * * * *': minute step '0' must be a positive number");
+        assert_eq!(err("0 7,,9 * * *"), "cron '0 7,,9 * * *': hour has an empty item in '7,,9'");
+        assert_eq!(err("0 7 0 * *"), "cron '0 7 0 * *': day-of-month value 0 is outside 1-31");
+
+        assert_eq!(rule(None, Some(0), None, 10).unwrap_err(), "every_hours = 0 is outside 1..=24");
+        assert_eq!(
+            rule(None, Some(6), Some("6am"), 10).unwrap_err(),
+            "start '6am' is not a time of day (HH:MM or HH:MM:SS)"
+        );
+        assert_eq!(rule(Some("* * * * *"), Some(1), None, 10).unwrap_err(), "set either cron or every_hours, not both");
+        assert_eq!(rule(None, None, None, 10).unwrap_err(), "needs cron or every_hours");
+        assert_eq!(rule(None, Some(1), None, 0).unwrap_err(), "secs = 0 is outside 1..=86400");
+    }
+
+    #[test]
+    fn date_spans_fixed_and_yearly() {
+        let s = DateSpan::parse("2026-12-20", "2027-01-03").unwrap();
+        assert!(s.contains(day(2026, 12, 20)) && s.contains(day(2027, 1, 3)));
+        assert!(!s.contains(day(2026, 12, 19)) && !s.contains(day(2027, 12, 25)));
+        assert_eq!(s.to_string(), "2026-12-20..2027-01-03");
+
+        let summer = DateSpan::parse("06-01", "08-31").unwrap();
+        assert!(summer.contains(day(2025, 7, 15)) && summer.contains(day(2031, 8, 31)));
+        assert!(!summer.contains(day(2025, 9, 1)));
+        // Wraps past New Year
+        let winter = DateSpan::parse("12-01", "02-29").unwrap();
+        assert!(winter.contains(day(2025, 12, 24)) && winter.contains(day(2026, 2, 28)));
+        assert!(!winter.contains(day(2026, 3, 1)));
+
+        assert_eq!(DateSpan::parse("2027-01-03", "2026-12-20").unwrap_err(), "to 2026-12-20 is before from 2027-01-03");
+        assert!(DateSpan::parse("06-31", "08-31").is_err());
+        assert!(DateSpan::parse("2026-06-01", "08-31").is_err());
+        assert!(DateSpan::parse("6-1", "8-31").is_err());
+    }
+
+    #[test]
+    fn overrides_are_checked() {
+        let o = |from: &str, to: &str| ScheduleOverride { from: from.to_string(), to: to.to_string(), ..Default::default() };
+        assert_eq!(check_override(&o("06-01", "08-31")).unwrap(), DateSpan::Yearly((6, 1), (8, 31)));
+        assert_eq!(
+            check_override(&ScheduleOverride { scale: 0.0, ..o("06-01", "08-31") }).unwrap_err(),
+            "scale = 0 is outside 0..=10"
+        );
+        let rules = vec![ScheduleRule { every_hours: Some(30), secs: 5, ..Default::default() }];
+        assert_eq!(
+            check_override(&ScheduleOverride { rules: rules.clone(), ..o("06-01", "08-31") }).unwrap_err(),
+            "rules[0]: every_hours = 30 is outside 1..=24"
+        );
+        assert_eq!(
+            check_override(&ScheduleOverride { skip: true, rules, ..o("06-01", "08-31") }).unwrap_err(),
+            "skip can't be combined with schedule or rules"
+        );
+    }
+
+    #[test]
+    fn sun_relative_times_parse_and_resolve() {
+        assert_eq!(TimeOfDay::parse("sunrise-30m").unwrap(), TimeOfDay::Sunrise(-1800));
+        assert_eq!(TimeOfDay::parse("Sunset+1h30m").unwrap(), TimeOfDay::Sunset(5400));
+        assert_eq!(TimeOfDay::parse("sunset").unwrap(), TimeOfDay::Sunset(0));
+        assert_eq!(TimeOfDay::parse("06:15").unwrap(), TimeOfDay::Fixed(22500));
+        assert_eq!(TimeOfDay::parse("sunrise+2x").unwrap_err(), "'sunrise+2x': offset must look like +30m, -2h or +1h30m");
+        assert_eq!(TimeOfDay::parse("sunrise 30m").unwrap_err(), "'sunrise 30m': offset must look like +30m, -2h or +1h30m");
+        assert!(TimeOfDay::parse("sunrise-").is_err() && TimeOfDay::parse("sunset+h").is_err());
+        assert!(TimeOfDay::parse("sunset+1é").is_err());
+        assert_eq!(TimeOfDay::parse("sunset+13h").unwrap_err(), "'sunset+13h': offset is more than 12h");
+        assert_eq!(
+            TimeOfDay::parse("sunrise+3000000000000000h").unwrap_err(),
+            "'sunrise+3000000000000000h': offset is more than 12h"
+        );
+        assert!(TimeOfDay::parse("sunrise+9223372036854775807s1s").is_err());
+        assert_eq!(TimeOfDay::parse("dusk").unwrap_err(), "'dusk' is not sunrise, sunset or a time of day (HH:MM)");
+
+        let sun = Some((6 * 3600, 20 * 3600));
+        assert_eq!(TimeOfDay::Sunrise(-1800).resolve(sun), Some(5 * 3600 + 1800));
+        assert_eq!(TimeOfDay::Sunset(7200).resolve(sun), Some(22 * 3600));
+        assert_eq!(TimeOfDay::Sunset(0).resolve(None), None);
+        assert_eq!(TimeOfDay::Fixed(60).resolve(None), Some(60));
+    }
+}
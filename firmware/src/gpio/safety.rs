@@ -0,0 +1,363 @@
+// Run-time interlock that sits between the controllers and the physical pin.
+// Every output opened through `output::open` gets an `Interlock`, limited only
+// if it drives a pump; a watchdog thread re-checks them so a stuck controller
+// cannot keep a pump running.
+
+use super::output::Output;
+use super::shutdown::{self, WorkerHandle};
+use crate::clock;
+use crate::config::SafetyConfig;
+use chrono::NaiveDate;
+use std::fmt;
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock, Weak};
+use std::time::{Duration, Instant};
+
+/// Limits for one output. `None` disables that check.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Limits {
+    pub max_on: Option<Duration>,
+    pub min_off: Option<Duration>,
+    pub daily_on: Option<Duration>,
+}
+
+impl From<&SafetyConfig> for Limits {
+    fn from(c: &SafetyConfig) -> Self {
+        Self {
+            max_on: c.max_on_secs.map(Duration::from_secs),
+            min_off: c.min_off_secs.map(Duration::from_secs),
+            daily_on: c.daily_on_secs.map(Duration::from_secs),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    /// Stayed ON longer than `max_on`
+    MaxOn,
+    /// Used up the daily ON-time budget
+    DailyBudget,
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Fault::MaxOn => write!(f, "max on"),
+            Fault::DailyBudget => write!(f, "daily budget"),
+        }
+    }
+}
+
+#[derive(Default)]
+struct State {
+    on: bool,
+    on_since: Option<Instant>,
+    // Start of the ON time not yet added to `used` (reset at midnight)
+    counted_from: Option<Instant>,
+    off_since: Option<Instant>,
+    day: Option<NaiveDate>,
+    used: Duration,
+    fault: Option<Fault>,
+}
+
+impl State {
+    fn switch(&mut self, on: bool, now: Instant) {
+        if on && !self.on {
+            self.on_since = Some(now);
+            self.counted_from = Some(now);
+        } else if !on && self.on {
+            if let Some(t) = self.counted_from.take() {
+                self.used += now.saturating_duration_since(t);
+            }
+            self.on_since = None;
+            self.off_since = Some(now);
+        }
+        self.on = on;
+    }
+
+    fn latch(&mut self, fault: Fault, now: Instant) {
+        self.switch(false, now);
+        self.fault = Some(fault);
+    }
+
+    fn roll_day(&mut self, now: Instant, today: NaiveDate) {
+        if self.day != Some(today) {
+            if self.day.is_some() {
+                self.used = Duration::ZERO;
+                if self.on {
+                    self.counted_from = Some(now);
+                }
+            }
+            self.day = Some(today);
+        }
+    }
+
+    fn used_today(&self, now: Instant) -> Duration {
+        self.used + self.counted_from.map_or(Duration::ZERO, |t| now.saturating_duration_since(t))
+    }
+}
+
+/// Enforces `Limits` for one output and latches a `Fault` until acknowledged.
+/// An ON request inside `min_off` is held back, not a fault.
+pub struct Interlock {
+    limits: Mutex<Limits>,
+    state: Mutex<State>,
+}
+
+impl Interlock {
+    pub fn new(limits: Limits) -> Self {
+        Self { limits: Mutex::new(limits), state: Mutex::new(State::default()) }
+    }
+
+    /// Replace the limits; the next check measures the current ON time against them.
+    pub fn set_limits(&self, limits: Limits) {
+        *lock(&self.limits) = limits;
+    }
+
+    /// Level the output may actually take when a controller asks for `want_on`.
+    pub fn admit(&self, want_on: bool, now: Instant, today: NaiveDate) -> bool {
+        let mut st = lock(&self.state);
+        st.roll_day(now, today);
+        if st.fault.is_some() {
+            st.switch(false, now);
+            return false;
+        }
+        if want_on && !st.on {
+            let cooling = match (lock(&self.limits).min_off, st.off_since) {
+                (Some(min), Some(t)) => now.saturating_duration_since(t) < min,
+                _ => false,
+            };
+            if cooling {
+                return false;
+            }
+        }
+        st.switch(want_on, now);
+        if let Some(f) = self.violation(&st, now) {
+            st.latch(f, now);
+            return false;
+        }
+        want_on
+    }
+
+    /// Watchdog check. Returns `false` if the output must be forced off now.
+    pub fn check(&self, now: Instant, today: NaiveDate) -> bool {
+        let mut st = lock(&self.state);
+        st.roll_day(now, today);
+        if let Some(f) = self.violation(&st, now) {
+            st.latch(f, now);
+            return false;
+        }
+        true
+    }
+
+    /// Record that the line was driven off outside of `admit` (e.g. shutdown).
+    pub fn record_off(&self, now: Instant) {
+        lock(&self.state).switch(false, now);
+    }
+
+    pub fn fault(&self) -> Option<Fault> {
+        lock(&self.state).fault
+    }
+
+    /// Clear a latched fault. The cooldown still applies from when the output was forced off.
+    pub fn acknowledge(&self) {
+        lock(&self.state).fault = None;
+    }
+
+    fn violation(&self, st: &State, now: Instant) -> Option<Fault> {
+        if !st.on {
+            return None;
+        }
+        let limits = *lock(&self.limits);
+        if let (Some(max), Some(t)) = (limits.max_on, st.on_since) {
+            if now.saturating_duration_since(t) >= max {
+                return Some(Fault::MaxOn);
+            }
+        }
+        if let Some(budget) = limits.daily_on {
+            if st.used_today(now) >= budget {
+                return Some(Fault::DailyBudget);
+            }
+        }
+        None
+    }
+}
+
+/// Holds the configured limits, the pump pins they apply to, and every
+/// interlocked output for the watchdog and the UI.
+#[derive(Default)]
+pub struct Monitor {
+    limits: Mutex<Limits>,
+    pumps: Mutex<Vec<u8>>,
+    outputs: Mutex<Vec<Weak<Output>>>,
+}
+
+impl Monitor {
+    /// Apply `limits` to the outputs on `pumps`, including those already open;
+    /// every other output (blink pin, schedule pin, grow lights) runs without limits.
+    pub fn configure(&self, limits: Limits, pumps: Vec<u8>) {
+        *lock(&self.limits) = limits;
+        *lock(&self.pumps) = pumps;
+        for o in self.live() {
+            o.set_limits(self.limits_for(o.pin()));
+        }
+    }
+
+    /// The limits for the output on `pin`.
+    pub fn limits_for(&self, pin: u8) -> Limits {
+        if lock(&self.pumps).contains(&pin) {
+            *lock(&self.limits)
+        } else {
+            Limits::default()
+        }
+    }
+
+    pub fn register(&self, out: &Arc<Output>) {
+        let mut outputs = lock(&self.outputs);
+        outputs.retain(|o| o.strong_count() > 0);
+        outputs.push(Arc::downgrade(out));
+    }
+
+    /// Latched faults as (pin, fault), for display.
+    pub fn faults(&self) -> Vec<(u8, Fault)> {
+        self.live().iter().filter_map(|o| o.fault().map(|f| (o.pin(), f))).collect()
+    }
+
+    pub fn acknowledge_all(&self) {
+        for o in self.live() {
+            o.acknowledge();
+        }
+    }
+
+    fn check_all(&self) {
+        for o in self.live() {
+            o.check();
+        }
+    }
+
+    fn live(&self) -> Vec<Arc<Output>> {
+        lock(&self.outputs).iter().filter_map(Weak::upgrade).collect()
+    }
+}
+
+pub fn global() -> &'static Monitor {
+    static GLOBAL: OnceLock<Monitor> = OnceLock::new();
+    GLOBAL.get_or_init(Monitor::default)
+}
+
+pub fn today() -> NaiveDate {
+    clock::global().now().date()
+}
+
+/// Start the watchdog thread. With `hardware` set it also feeds `/dev/watchdog`,
+/// so the Pi reboots (and the pins reset) if the whole process hangs or is killed.
+pub fn start_watchdog(hardware: bool) -> WorkerHandle {
+    shutdown::global().spawn("safety-watchdog", move |stop| {
+        let mut dev = if hardware { open_hw_watchdog() } else { None };
+        while stop.wait(Duration::from_millis(250)) {
+            global().check_all();
+            if let Some(f) = dev.as_mut() {
+                use std::io::Write;
+                let _ = f.write_all(b"\0");
+            }
+        }
+        // Magic close: a clean shutdown disarms the hardware timer.
+        if let Some(mut f) = dev {
+            use std::io::Write;
+            let _ = f.write_all(b"V");
+        }
+    })
+}
+
+fn open_hw_watchdog() -> Option<std::fs::File> {
+    match std::fs::OpenOptions::new().write(true).open("/dev/watchdog") {
+        Ok(f) => {
+            println!("[safety] hardware watchdog armed");
+            Some(f)
+        }
+        Err(e) => {
+            println!("[safety] hardware watchdog unavailable: {}", e);
+            None
+        }
+    }
+}
+
+fn lock<T>(m: &Mutex<T>) -> MutexGuard<'_, T> {
+    m.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn day(d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2026, 6, d).unwrap()
+    }
+
+    fn secs(s: u64) -> Duration {
+        Duration::from_secs(s)
+    }
+
+    #[test]
+    fn max_on_latches_until_acknowledged() {
+        let il = Interlock::new(Limits { max_on: Some(secs(60)), ..Default::default() });
+        let t0 = Instant::now();
+        assert!(il.admit(true, t0, day(1)));
+        assert!(il.check(t0 + secs(59), day(1)));
+        assert!(!il.check(t0 + secs(60), day(1)));
+        assert_eq!(il.fault(), Some(Fault::MaxOn));
+        // Stays off while latched, even if the controller keeps asking
+        assert!(!il.admit(true, t0 + secs(120), day(1)));
+        il.acknowledge();
+        assert!(il.admit(true, t0 + secs(121), day(1)));
+    }
+
+    #[test]
+    fn cooldown_delays_early_restart() {
+        let il = Interlock::new(Limits { min_off: Some(secs(30)), ..Default::default() });
+        let t0 = Instant::now();
+        assert!(il.admit(true, t0, day(1)));
+        assert!(!il.admit(false, t0 + secs(5), day(1)));
+        assert!(!il.admit(true, t0 + secs(10), day(1)));
+        assert_eq!(il.fault(), None);
+        assert!(il.admit(true, t0 + secs(35), day(1)));
+    }
+
+    #[test]
+    fn daily_budget_resets_at_midnight() {
+        let il = Interlock::new(Limits { daily_on: Some(secs(100)), ..Default::default() });
+        let t0 = Instant::now();
+        assert!(il.admit(true, t0, day(1)));
+        assert!(!il.admit(false, t0 + secs(60), day(1)));
+        assert!(il.admit(true, t0 + secs(70), day(1)));
+        assert!(!il.check(t0 + secs(110), day(1)));
+        assert_eq!(il.fault(), Some(Fault::DailyBudget));
+        il.acknowledge();
+        assert!(!il.admit(true, t0 + secs(120), day(1)));
+        il.acknowledge();
+        assert!(il.admit(true, t0 + secs(130), day(2)));
+    }
+
+    #[test]
+    fn limits_only_apply_to_pumps() {
+        let m = Monitor::default();
+        // The documented example
+        let limits = Limits { max_on: Some(secs(600)), min_off: Some(secs(60)), daily_on: Some(secs(3600)) };
+        m.configure(limits, vec![22]);
+        assert_eq!(m.limits_for(22), limits);
+
+        // A 1s blink on GPIO 17 runs all day without a fault
+        let blink = Interlock::new(m.limits_for(17));
+        let t0 = Instant::now();
+        for i in 0..86_400 {
+            blink.admit(i % 2 == 0, t0 + secs(i), day(1));
+        }
+        assert!(blink.check(t0 + secs(86_400), day(1)));
+        assert_eq!(blink.fault(), None);
+
+        // The same blink on a pump is only slowed down by the cooldown
+        let pump = Interlock::new(m.limits_for(22));
+        let on: usize = (0..600).filter(|i| pump.admit(i % 2 == 0, t0 + secs(*i), day(1))).count();
+        assert_eq!(on, 10);
+        assert_eq!(pump.fault(), None);
+    }
+}
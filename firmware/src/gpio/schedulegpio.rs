@@ -1,68 +1,185 @@
+use super::registry::{self, PinError, Priority};
+use super::rules::{DateSpan, Rule, TimeOfDay};
+use super::shutdown::{self, WorkerHandle};
 use super::GpioController;
-use chrono::{Local, Timelike, Datelike, Weekday};
-use rppal::gpio::Gpio;
+use crate::clock::Clock;
+use crate::sun::Location;
+use chrono::{NaiveDate, NaiveDateTime, NaiveTime, Datelike, Weekday};
 use std::collections::HashMap;
 use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
-use std::sync::Arc;
-use std::thread;
+use std::sync::{Arc, Mutex, MutexGuard};
 use std::time::Duration;
 
 #[derive(Debug, Clone, Default)]
 pub struct GpioSchedule {
-    // Vec of (start, end) in 24h HHMM (e.g., 930, 1745)
+    // Vec of (start, end) in 24h HHMM (e.g., 930, 1745); end 2400 is midnight.
+    // Overnight ranges are already split into the next day.
     pub schedule: HashMap<Weekday, Vec<(u16, u16)>>,
+    // Timed runs: (start, duration), both in seconds; may run past midnight
+    pub runs: HashMap<Weekday, Vec<(u32, u32)>>,
+    // Ranges relative to sunrise/sunset; an end before the start runs overnight
+    pub solar: HashMap<Weekday, Vec<(TimeOfDay, TimeOfDay)>>,
+    // Needed for `solar`, also in overrides; without it those entries never run
+    pub location: Option<Location>,
+    // Cron and "every N hours" rules, which fire runs too
+    pub rules: Vec<Rule>,
+    // Other rule sets for date ranges, applied in order on the days they cover
+    pub overrides: Vec<Override>,
+}
+
+/// A rule set in force for a date range instead of the regular one.
+#[derive(Debug, Clone)]
+pub struct Override {
+    pub name: String,
+    pub span: DateSpan,
+    // Nothing runs
+    pub skip: bool,
+    // Multiplies the length of every range and run
+    pub scale: f64,
+    // Replaces the regular ranges, runs and rules; None keeps them
+    pub schedule: Option<GpioSchedule>,
+}
+
+impl GpioSchedule {
+    /// Whether local time `now` falls in one of its day's ranges, or in a run
+    /// started today or late yesterday.
+    pub fn is_on(&self, now: NaiveDateTime) -> bool {
+        let today = now.date();
+        [today.pred_opt(), Some(today)]
+            .into_iter()
+            .flatten()
+            .flat_map(|day| self.windows(day))
+            .any(|(start, end)| start <= now && now < end)
+    }
+
+    /// Time from `now` to the next start or end of a range or run, if any.
+    pub fn next_change(&self, now: NaiveDateTime) -> Option<chrono::Duration> {
+        let today = now.date();
+        [today.pred_opt(), Some(today), today.succ_opt()]
+            .into_iter()
+            .flatten()
+            .flat_map(|day| self.windows(day))
+            .flat_map(|(start, end)| [start, end])
+            .filter(|t| *t > now)
+            .min()
+            .map(|t| t - now)
+    }
+
+    /// Overrides in force on `day`, in config order.
+    pub fn active(&self, day: NaiveDate) -> Vec<&Override> {
+        self.overrides.iter().filter(|o| o.span.contains(day)).collect()
+    }
+
+    /// Name of the rule set in force on `day`: "regular", or the active
+    /// overrides joined with '+'.
+    pub fn rule_set(&self, day: NaiveDate) -> String {
+        let names: Vec<&str> = self.active(day).iter().map(|o| o.name.as_str()).collect();
+        if names.is_empty() { "regular".to_string() } else { names.join("+") }
+    }
+
+    // Ranges and runs that start on `day`, as local times. Active overrides
+    // apply in order: a schedule replaces the set, scales multiply, skip wins.
+    fn windows(&self, day: NaiveDate) -> Vec<(NaiveDateTime, NaiveDateTime)> {
+        let (mut set, mut scale) = (self, 1.0);
+        for o in self.active(day) {
+            if o.skip {
+                return Vec::new();
+            }
+            set = o.schedule.as_ref().unwrap_or(set);
+            scale *= o.scale;
+        }
+        let midnight = day.and_time(NaiveTime::MIN);
+        let at = |secs: i64| midnight + chrono::Duration::seconds(secs);
+        set.starts(day, self.location)
+            .into_iter()
+            .map(|(s, d)| (at(s), at(s + (d as f64 * scale).round() as i64)))
+            .collect()
+    }
+
+    // (start, duration) in seconds from midnight of this set's own entries on `day`
+    fn starts(&self, day: NaiveDate, location: Option<Location>) -> Vec<(i64, i64)> {
+        let secs = |v: u16| (v / 100) as i64 * 3600 + (v % 100) as i64 * 60;
+        let ranges = self.schedule.get(&day.weekday()).into_iter().flatten().map(|(s, e)| (secs(*s), secs(*e) - secs(*s)));
+        let runs = self.runs.get(&day.weekday()).into_iter().flatten().map(|(s, d)| (*s as i64, *d as i64));
+        let rules = self.rules.iter().flat_map(|r| r.runs(day)).map(|(s, d)| (s as i64, d as i64));
+        let mut out: Vec<(i64, i64)> = ranges.chain(runs).chain(rules).collect();
+        if let Some(entries) = self.solar.get(&day.weekday()) {
+            let sun = location.and_then(|l| l.local_times(day));
+            for (from, to) in entries {
+                if let (Some(s), Some(e)) = (from.resolve(sun), to.resolve(sun)) {
+                    out.push((s, if e > s { e - s } else { e + 86400 - s }));
+                }
+            }
+        }
+        out
+    }
 }
 
 pub struct ScheduleRppalGpioController {
     enabled: Arc<AtomicBool>,
     interval_ms: Arc<AtomicU64>, // kept for trait compatibility; not used
-    _thread: thread::JoinHandle<()>,
+    rule_set: Arc<Mutex<String>>,
+    sched: Arc<Mutex<GpioSchedule>>,
+    _worker: WorkerHandle,
 }
 
 impl ScheduleRppalGpioController {
-    pub fn new(gpio_pin: u8, invert: bool, sched: GpioSchedule) -> Self {
+    pub fn new(gpio_pin: u8, invert: bool, sched: GpioSchedule, clock: Arc<dyn Clock>) -> Result<Self, PinError> {
+        let claim = registry::global().claim(gpio_pin, invert, "schedule", Priority::Schedule)?;
         let enabled = Arc::new(AtomicBool::new(true));
         let interval_ms = Arc::new(AtomicU64::new(1000));
 
         let enabled_t = Arc::clone(&enabled);
-        let schedule_t = sched.schedule.clone();
+        let rule_set = Arc::new(Mutex::new(String::new()));
+        let rule_set_t = Arc::clone(&rule_set);
+        let sched = Arc::new(Mutex::new(sched));
+        let sched_t = Arc::clone(&sched);
 
-        let handle = thread::spawn(move || {
+        let worker = shutdown::global().spawn("gpio-sched", move |stop| {
             println!("[gpio-sched] pin={} invert={}", gpio_pin, invert);
-            let gpio = match Gpio::new() {
-                Ok(g) => g,
-                Err(_) => return,
-            };
-            let mut pin = match gpio.get(gpio_pin).and_then(|p| Ok(p.into_output())) {
-                Ok(p) => p,
-                Err(_) => return,
-            };
-
             loop {
-                let now = Local::now();
-                let wd = now.weekday();
-                let hhmm: u16 = (now.time().hour() as u16) * 100 + (now.time().minute() as u16);
-
-                let mut on = false;
-                if enabled_t.load(Ordering::Relaxed) {
-                    if let Some(ranges) = schedule_t.get(&wd) {
-                        for (start, end) in ranges {
-                            if *start <= hhmm && hhmm < *end { on = true; break; }
-                        }
-                    }
+                let now = clock.now();
+                let sched = lock(&sched_t);
+                let active = sched.rule_set(now.date());
+                let mut current = lock(&rule_set_t);
+                if *current != active {
+                    let spans: Vec<String> = sched.active(now.date()).iter().map(|o| o.span.to_string()).collect();
+                    println!("[gpio-sched] rule set '{}' active {}", active, spans.join(" "));
+                    *current = active;
                 }
+                drop(current);
+                let on = enabled_t.load(Ordering::Relaxed) && sched.is_on(now);
+                claim.set(on);
 
-                let high = if invert { !on } else { on };
-                if high { pin.set_high(); } else { pin.set_low(); }
-
-                thread::sleep(Duration::from_millis(500));
+                // Sleep until the next edge so timed runs last to the second, but
+                // look again at least every 500ms of real time (enable flag, clock jumps)
+                let next = sched.next_change(now).and_then(|d| d.to_std().ok()).unwrap_or(Duration::from_secs(30));
+                drop(sched);
+                let poll = clock.wall(next).clamp(Duration::from_millis(1), Duration::from_millis(500));
+                if !stop.wait(poll) {
+                    break;
+                }
             }
         });
 
-        Self { enabled, interval_ms, _thread: handle }
+        Ok(Self { enabled, interval_ms, rule_set, sched, _worker: worker })
+    }
+
+    /// Swap in a new schedule; the worker follows it within 500ms.
+    pub fn set_schedule(&self, sched: GpioSchedule) {
+        *lock(&self.sched) = sched;
+    }
+
+    /// Name of the rule set in force now (see `GpioSchedule::rule_set`).
+    pub fn rule_set(&self) -> String {
+        lock(&self.rule_set).clone()
     }
 }
 
+fn lock<T>(m: &Mutex<T>) -> MutexGuard<'_, T> {
+    m.lock().unwrap_or_else(|e| e.into_inner())
+}
+
 impl GpioController for ScheduleRppalGpioController {
     fn set_blink(&self, on: bool) {
         self.enabled.store(on, Ordering::Relaxed);
@@ -81,6 +198,8 @@ impl GpioController for ScheduleRppalGpioController {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::clock::FixedClock;
+    use chrono::NaiveDate;
 
     // Ignored: requires GPIO hardware. Ensures API compiles with `--features gpio`.
     #[test]
@@ -88,8 +207,167 @@ mod tests {
     fn construct_schedule_controller() {
         let mut m: HashMap<Weekday, Vec<(u16, u16)>> = HashMap::new();
         m.insert(Weekday::Mon, vec![(900, 1700)]);
-        let sched = GpioSchedule { schedule: m };
-        let ctl = ScheduleRppalGpioController::new(27, false, sched);
+        let sched = GpioSchedule { schedule: m, ..Default::default() };
+        let ctl = ScheduleRppalGpioController::new(27, false, sched, crate::clock::global()).unwrap();
         let _ = ctl.is_blink();
     }
+
+    fn at(clock: &FixedClock) -> bool {
+        let mut m: HashMap<Weekday, Vec<(u16, u16)>> = HashMap::new();
+        m.insert(Weekday::Sun, vec![(100, 200), (2300, 2359)]);
+        m.insert(Weekday::Mon, vec![(0, 30)]);
+        GpioSchedule { schedule: m, ..Default::default() }.is_on(clock.now())
+    }
+
+    #[test]
+    fn follows_the_clock_across_midnight_and_days() {
+        // Sunday 31 March 2024, 22:59
+        let clock = FixedClock::new(NaiveDate::from_ymd_opt(2024, 3, 31).unwrap().and_hms_opt(22, 59, 30).unwrap());
+        assert!(!at(&clock));
+        clock.advance(Duration::from_secs(30));
+        assert!(at(&clock));
+        // 23:59 is outside [2300, 2359)
+        clock.advance(Duration::from_secs(59 * 60));
+        assert!(!at(&clock));
+        // Monday 00:00 picks up Monday's ranges
+        clock.advance(Duration::from_secs(60));
+        assert!(at(&clock));
+        clock.advance(Duration::from_secs(30 * 60));
+        assert!(!at(&clock));
+    }
+
+    #[test]
+    fn overnight_range_runs_from_sunday_into_monday() {
+        // Sunday 22:00 - Monday 06:00, as split by the config loader
+        let mut m: HashMap<Weekday, Vec<(u16, u16)>> = HashMap::new();
+        m.insert(Weekday::Sun, vec![(2200, 2400)]);
+        m.insert(Weekday::Mon, vec![(0, 600)]);
+        let sched = GpioSchedule { schedule: m, ..Default::default() };
+        let clock = FixedClock::new(NaiveDate::from_ymd_opt(2024, 3, 31).unwrap().and_hms_opt(21, 59, 59).unwrap());
+        assert!(!sched.is_on(clock.now()));
+        clock.advance(Duration::from_secs(1));
+        assert!(sched.is_on(clock.now()));
+        clock.advance(Duration::from_secs(2 * 3600 - 1));
+        assert!(sched.is_on(clock.now()));
+        clock.advance(Duration::from_secs(1));
+        assert!(sched.is_on(clock.now()));
+        clock.advance(Duration::from_secs(6 * 3600 - 1));
+        assert!(sched.is_on(clock.now()));
+        clock.advance(Duration::from_secs(1));
+        assert!(!sched.is_on(clock.now()));
+    }
+
+    #[test]
+    fn timed_runs_have_second_precision() {
+        let mut runs: HashMap<Weekday, Vec<(u32, u32)>> = HashMap::new();
+        // Sunday 07:30:00 for 45s, and 23:59:50 for 30s into Monday
+        runs.insert(Weekday::Sun, vec![(7 * 3600 + 30 * 60, 45), (86390, 30)]);
+        let sched = GpioSchedule { runs, ..Default::default() };
+        let clock = FixedClock::new(NaiveDate::from_ymd_opt(2024, 3, 31).unwrap().and_hms_opt(7, 29, 59).unwrap());
+        assert!(!sched.is_on(clock.now()));
+        assert_eq!(sched.next_change(clock.now()), Some(chrono::Duration::seconds(1)));
+        clock.advance(Duration::from_secs(1));
+        assert!(sched.is_on(clock.now()));
+        assert_eq!(sched.next_change(clock.now()), Some(chrono::Duration::seconds(45)));
+        clock.advance(Duration::from_millis(44_900));
+        assert!(sched.is_on(clock.now()));
+        assert_eq!(sched.next_change(clock.now()), Some(chrono::Duration::milliseconds(100)));
+        clock.advance(Duration::from_millis(100));
+        assert!(!sched.is_on(clock.now()));
+
+        clock.set(NaiveDate::from_ymd_opt(2024, 4, 1).unwrap().and_hms_opt(0, 0, 19).unwrap());
+        assert!(sched.is_on(clock.now()));
+        clock.advance(Duration::from_secs(1));
+        assert!(!sched.is_on(clock.now()));
+        // Next run is Sunday morning
+        assert_eq!(sched.next_change(clock.now()), None);
+    }
+
+    #[test]
+    fn overrides_skip_replace_and_scale_by_date() {
+        let mut base: HashMap<Weekday, Vec<(u16, u16)>> = HashMap::new();
+        for day in [Weekday::Mon, Weekday::Tue, Weekday::Wed, Weekday::Thu, Weekday::Fri, Weekday::Sat, Weekday::Sun] {
+            base.insert(day, vec![(700, 800)]);
+        }
+        let mut summer: HashMap<Weekday, Vec<(u16, u16)>> = HashMap::new();
+        summer.insert(Weekday::Mon, vec![(600, 630)]);
+        let o = |name: &str, from, to, skip, scale, schedule| Override {
+            name: name.to_string(),
+            span: DateSpan::parse(from, to).unwrap(),
+            skip,
+            scale,
+            schedule,
+        };
+        let sched = GpioSchedule {
+            schedule: base,
+            overrides: vec![
+                o("summer", "06-01", "08-31", false, 1.0, Some(GpioSchedule { schedule: summer, ..Default::default() })),
+                o("vacation", "2026-07-12", "2026-07-20", true, 1.0, None),
+            ],
+            ..Default::default()
+        };
+        let at = |m, d, h, min| NaiveDate::from_ymd_opt(2026, m, d).unwrap().and_hms_opt(h, min, 0).unwrap();
+
+        // Regular: every day 07:00-08:00
+        assert_eq!(sched.rule_set(at(5, 31, 0, 0).date()), "regular");
+        assert!(sched.is_on(at(5, 31, 7, 30)));
+        // Summer replaces it: Mondays 06:00-06:30 only
+        assert_eq!(sched.rule_set(at(6, 1, 0, 0).date()), "summer");
+        assert!(sched.is_on(at(6, 1, 6, 15)) && !sched.is_on(at(6, 1, 7, 30)));
+        assert!(!sched.is_on(at(6, 2, 6, 15)));
+        // The heatwave doubles the summer ranges (Monday 6 July 2026)
+        let heatwave = o("heatwave", "2026-07-06", "2026-07-06", false, 2.0, None);
+        let mut hot = sched.clone();
+        hot.overrides.insert(1, heatwave);
+        assert_eq!(hot.rule_set(at(7, 6, 0, 0).date()), "summer+heatwave");
+        assert!(hot.is_on(at(7, 6, 6, 59)) && !hot.is_on(at(7, 6, 7, 0)));
+        assert!(!sched.is_on(at(7, 6, 6, 59)));
+        // Vacation skips everything
+        assert_eq!(sched.rule_set(at(7, 12, 0, 0).date()), "summer+vacation");
+        assert!(!sched.is_on(at(7, 12, 7, 30)));
+        assert!(!sched.is_on(at(7, 20, 7, 30)));
+        assert!(sched.is_on(at(9, 1, 7, 30)));
+    }
+
+    #[test]
+    fn sun_relative_ranges_follow_the_seasons() {
+        let london = Location { latitude: 51.5074, longitude: -0.1278 };
+        let mut solar: HashMap<Weekday, Vec<(TimeOfDay, TimeOfDay)>> = HashMap::new();
+        for day in [Weekday::Fri, Weekday::Sat] {
+            solar.insert(day, vec![(TimeOfDay::Sunrise(-1800), TimeOfDay::Sunset(7200))]);
+        }
+        let sched = GpioSchedule { solar, location: Some(london), ..Default::default() };
+        // Friday 21 June and Saturday 21 December 2024, in whatever zone the test runs in
+        for day in [NaiveDate::from_ymd_opt(2024, 6, 21).unwrap(), NaiveDate::from_ymd_opt(2024, 12, 21).unwrap()] {
+            let (rise, set) = london.local_times(day).unwrap();
+            let at = |secs: i64| day.and_time(NaiveTime::MIN) + chrono::Duration::seconds(secs);
+            assert!(!sched.is_on(at(rise - 1801)));
+            assert!(sched.is_on(at(rise - 1800)));
+            assert!(sched.is_on(at(set + 7199)));
+            assert!(!sched.is_on(at(set + 7200)));
+            assert_eq!(sched.next_change(at(rise - 1801)), Some(chrono::Duration::seconds(1)));
+        }
+        // Summer days are longer
+        let len = |m, d| {
+            let (rise, set) = london.local_times(NaiveDate::from_ymd_opt(2024, m, d).unwrap()).unwrap();
+            set - rise
+        };
+        assert!(len(6, 21) > len(12, 21) + 8 * 3600);
+        // No location: the entries never run
+        let nowhere = GpioSchedule { location: None, ..sched };
+        assert!(!nowhere.is_on(NaiveDate::from_ymd_opt(2024, 6, 21).unwrap().and_hms_opt(12, 0, 0).unwrap()));
+    }
+
+    #[test]
+    fn wall_clock_jumps_are_taken_as_they_come() {
+        let day = NaiveDate::from_ymd_opt(2024, 10, 27).unwrap();
+        let clock = FixedClock::new(day.and_hms_opt(1, 59, 0).unwrap());
+        assert!(at(&clock));
+        // Fall back: 01:00-02:00 happens twice and is on both times
+        clock.set(day.and_hms_opt(1, 0, 0).unwrap());
+        assert!(at(&clock));
+        // Spring forward over the whole range: it is simply skipped
+        clock.set(day.and_hms_opt(2, 0, 0).unwrap());
+        assert!(!at(&clock));
+    }
 }
@@ -0,0 +1,217 @@
+// Shutdown coordinator: every controller thread and output registers here so
+// that exit, panic and SIGINT/SIGTERM/SIGHUP all leave the pins in their safe state.
+
+use super::output::Output;
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock, Weak};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Passed to a worker thread; tells it when to leave its loop.
+#[derive(Clone)]
+pub struct StopFlag(Arc<AtomicBool>);
+
+impl StopFlag {
+    pub fn is_set(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// Sleep for `dur`, waking early if stop is requested.
+    /// Returns `true` if the worker should keep running.
+    pub fn wait(&self, dur: Duration) -> bool {
+        let deadline = Instant::now() + dur;
+        while !self.is_set() {
+            let now = Instant::now();
+            if now >= deadline {
+                return true;
+            }
+            thread::sleep((deadline - now).min(Duration::from_millis(20)));
+        }
+        false
+    }
+}
+
+struct Worker {
+    name: String,
+    stop: Arc<AtomicBool>,
+    handle: Mutex<Option<thread::JoinHandle<()>>>,
+}
+
+impl Worker {
+    fn stop_and_join(&self) {
+        self.stop.store(true, Ordering::Relaxed);
+        let handle = lock(&self.handle).take();
+        if let Some(h) = handle {
+            // A panicking worker may reach here through the panic hook; never join ourselves.
+            if h.thread().id() == thread::current().id() {
+                return;
+            }
+            if h.join().is_err() {
+                note(&format!("[shutdown] worker '{}' panicked", self.name));
+            }
+        }
+    }
+}
+
+/// Owned by a controller. Dropping it stops the worker thread and waits for it to exit.
+pub struct WorkerHandle(Arc<Worker>);
+
+impl Drop for WorkerHandle {
+    fn drop(&mut self) {
+        self.0.stop_and_join();
+    }
+}
+
+#[derive(Default)]
+pub struct Shutdown {
+    requested: AtomicBool,
+    workers: Mutex<Vec<Arc<Worker>>>,
+    outputs: Mutex<Vec<Weak<Output>>>,
+}
+
+impl Shutdown {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Spawn a controller thread that runs until stopped by its handle or by shutdown.
+    pub fn spawn<F>(&self, name: &str, f: F) -> WorkerHandle
+    where
+        F: FnOnce(StopFlag) + Send + 'static,
+    {
+        let stop = Arc::new(AtomicBool::new(self.is_requested()));
+        let flag = StopFlag(Arc::clone(&stop));
+        let handle = thread::Builder::new()
+            .name(name.to_string())
+            .spawn(move || f(flag))
+            .expect("failed to spawn worker thread");
+        let worker = Arc::new(Worker {
+            name: name.to_string(),
+            stop,
+            handle: Mutex::new(Some(handle)),
+        });
+        let mut workers = lock(&self.workers);
+        workers.retain(|w| lock(&w.handle).is_some());
+        workers.push(Arc::clone(&worker));
+        WorkerHandle(worker)
+    }
+
+    /// Track an output so shutdown can force it safe even if its thread is stuck.
+    pub fn register_output(&self, out: &Arc<Output>) {
+        let mut outputs = lock(&self.outputs);
+        outputs.retain(|o| o.strong_count() > 0);
+        outputs.push(Arc::downgrade(out));
+    }
+
+    pub fn is_requested(&self) -> bool {
+        self.requested.load(Ordering::Relaxed)
+    }
+
+    /// Stop every worker, wait for it, then drive every registered output to its safe level.
+    /// Safe to call more than once and from any thread.
+    pub fn shutdown(&self) {
+        self.requested.store(true, Ordering::Relaxed);
+        let workers: Vec<Arc<Worker>> = lock(&self.workers).drain(..).collect();
+        for w in &workers {
+            w.stop.store(true, Ordering::Relaxed);
+        }
+        for w in &workers {
+            w.stop_and_join();
+        }
+        for out in lock(&self.outputs).iter().filter_map(Weak::upgrade) {
+            out.set_safe();
+        }
+    }
+}
+
+/// Process-wide coordinator used by the controllers, the panic hook and the signal handler.
+pub fn global() -> &'static Shutdown {
+    static GLOBAL: OnceLock<Shutdown> = OnceLock::new();
+    GLOBAL.get_or_init(Shutdown::new)
+}
+
+/// Install the panic hook and signal handler. Call once, early in `main`.
+pub fn install() {
+    let prev = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        prev(info);
+        global().shutdown();
+    }));
+
+    #[cfg(unix)]
+    watch_signals();
+}
+
+#[cfg(unix)]
+fn watch_signals() {
+    use signal_hook::consts::{SIGHUP, SIGINT, SIGTERM};
+    use signal_hook::iterator::Signals;
+
+    let mut signals = match Signals::new([SIGINT, SIGTERM, SIGHUP]) {
+        Ok(s) => s,
+        Err(e) => {
+            note(&format!("[shutdown] cannot install signal handler: {}", e));
+            return;
+        }
+    };
+    thread::spawn(move || {
+        if let Some(sig) = signals.forever().next() {
+            note(&format!("[shutdown] signal {}", sig));
+            global().shutdown();
+            // Pins are safe now; give main a moment to restore the terminal, then exit anyway.
+            thread::sleep(Duration::from_secs(2));
+            std::process::exit(128 + sig);
+        }
+    });
+}
+
+// The terminal may already be gone (SIGHUP), so never panic on a failed write.
+fn note(msg: &str) {
+    let _ = writeln!(std::io::stderr(), "{}", msg);
+}
+
+fn lock<T>(m: &Mutex<T>) -> MutexGuard<'_, T> {
+    m.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gpio::output::safe_level;
+    use crate::gpio::output::tests::FakePin;
+
+    #[test]
+    fn shutdown_stops_workers_and_forces_outputs_safe() {
+        let sd = Shutdown::new();
+        let fake = FakePin::default();
+        let out = Arc::new(Output::new(5, true, Box::new(fake.clone())));
+        sd.register_output(&out);
+
+        let out_t = Arc::clone(&out);
+        let _handle = sd.spawn("test-worker", move |stop| {
+            while stop.wait(Duration::from_millis(5)) {
+                out_t.set(true);
+            }
+        });
+        thread::sleep(Duration::from_millis(30));
+        assert_eq!(fake.last(), Some(false)); // inverted: on == low
+
+        sd.shutdown();
+        assert!(sd.is_requested());
+        assert_eq!(fake.last(), Some(safe_level(true)));
+    }
+
+    #[test]
+    fn dropping_handle_joins_worker() {
+        let sd = Shutdown::new();
+        let exited = Arc::new(AtomicBool::new(false));
+        let exited_t = Arc::clone(&exited);
+        let handle = sd.spawn("test-worker", move |stop| {
+            while stop.wait(Duration::from_millis(5)) {}
+            exited_t.store(true, Ordering::Relaxed);
+        });
+        drop(handle);
+        assert!(exited.load(Ordering::Relaxed));
+    }
+}
@@ -4,110 +4,327 @@ mod ui;
 mod config;
 mod gpio;
 mod analog;
+mod datalog;
+mod daemon;
+mod cli;
+mod clock;
+mod sim;
+mod sun;
+mod reload;
+mod migrate;
+mod zone;
+#[cfg(feature = "http")]
+mod api;
+#[cfg(feature = "mqtt")]
+mod mqtt;
 
-use crate::config::load_config;
+use crate::cli::{Cli, Command};
+use crate::config::{data_dir, load_config, AppConfig, ScheduleEntry, ScheduleRule};
+use crate::daemon::DaemonOptions;
 use crate::gpio::new_controller;
-use crate::analog::Ads1115;
-#[cfg(feature = "gpio")]
+use crate::gpio::{safety, shutdown};
+use crate::analog::probe;
 use chrono::Weekday;
-#[cfg(feature = "gpio")]
 use crate::gpio::ScheduleRppalGpioController;
-#[cfg(feature = "gpio")]
-use crate::gpio::GpioSchedule;
-#[cfg(feature = "gpio")]
+use crate::gpio::{GpioSchedule, Override};
+use crate::gpio::rules::{self, Rule, TimeOfDay};
+use crate::sun::Location;
 use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
+use clap::Parser;
+use std::sync::Arc;
+use std::time::Duration;
 
 fn main() {
-    // Load persisted configuration
-    let cfg = load_config();
+    let cli = Cli::parse();
+    if let Some(path) = cli.config {
+        config::set_config_path(path);
+    }
+
+    // Drive every output to its safe level on panic or SIGINT/SIGTERM/SIGHUP
+    shutdown::install();
 
+    // Load persisted configuration; a broken file is reported, never replaced.
+    // The file commands read the file themselves: `config validate` lists every
+    // problem, backups can be restored over a broken file, and neither they nor
+    // `config migrate --dry-run` may migrate it on the way in.
+    let file_command = matches!(
+        cli.command,
+        Some(Command::Config { action: cli::ConfigAction::Validate | cli::ConfigAction::Backups | cli::ConfigAction::Restore { .. } | cli::ConfigAction::Migrate { .. } })
+    );
+    let mut cfg = if file_command { AppConfig::default() } else { load_config().unwrap_or_else(|e| exit_with_error(e)) };
+
+    // Simulated sensors and virtual pins; must be decided before anything opens hardware
+    cfg.sim.enabled |= cli.sim;
+    if cfg.sim.enabled {
+        sim::init(&cfg);
+        // Every controller, limit and the soil model run on simulated time
+        clock::init(Arc::new(clock::AcceleratedClock::new(cfg.sim.speed)));
+    }
+
+    let result = match cli.command {
+        None | Some(Command::Run) => {
+            run_controllers(cfg, None);
+            Ok(())
+        }
+        Some(Command::Daemon { pid_file }) => {
+            run_controllers(cfg, Some(DaemonOptions { pid_file }));
+            Ok(())
+        }
+        Some(Command::ReadSensor { sensor, json }) => cli::read_sensor(&cfg, sensor.as_deref(), json),
+        Some(Command::SetPin { pin, state, hold }) => cli::set_pin(&cfg, pin, state, hold),
+        Some(Command::Config { action }) => cli::config(cfg, action),
+        Some(Command::Calibrate { point, sensor }) => cli::calibrate(cfg, point, sensor.as_deref()),
+    };
+
+    // Stop controller threads and leave every pin in its safe state
+    shutdown::global().shutdown();
+    if let Err(e) = result {
+        eprintln!("error: {}", e);
+        std::process::exit(1);
+    }
+}
+
+/// Start every configured controller, then hand over to the TUI or, with
+/// `daemon` options, the headless loop until quit or a signal.
+fn run_controllers(cfg: AppConfig, daemon: Option<DaemonOptions>) {
+    if let Some((path, source)) = config::config_location() {
+        println!("[startup] config {} ({})", path.display(), source);
+    }
+    let overrides = config::env_overrides();
+    if !overrides.is_empty() {
+        println!("[startup] overridden by {}", overrides.join(", "));
+    }
     // Construct GPIO controller (real or stub depending on features)
     println!(
         "[startup] pin={} invert={} blink={} iv={}ms",
         cfg.gpio_pin, cfg.invert, cfg.blink_on, cfg.interval_ms
     );
-    // If a schedule is provided in config, start a schedule controller on cfg.schedule_pin
-    #[cfg(feature = "gpio")]
-    {
-        if let Some(s) = build_schedule(cfg.schedule.clone()) {
-            let _schedule_ctl = ScheduleRppalGpioController::new(cfg.schedule_pin, cfg.invert, s);
-            let _ = &_schedule_ctl;
-            println!("[startup] schedule active on GPIO {}", cfg.schedule_pin);
+    // History of moisture samples and pin changes, next to the config directory
+    if cfg.logging.enabled {
+        match data_dir().map(|d| datalog::init(&d, &cfg.logging)) {
+            Some(Ok(())) => println!("[startup] data log in {}", data_dir().unwrap_or_default().display()),
+            Some(Err(e)) => println!("[startup] data log disabled: {}", e),
+            None => println!("[startup] data log disabled: no data directory"),
         }
     }
 
+    // Run-time limits apply to every pump opened from here on
+    safety::global().configure(safety::Limits::from(&cfg.safety), cfg.pump_pins());
+    let _watchdog = safety::start_watchdog(cfg.safety.hardware_watchdog);
+    let _sim = sim::start();
+
+    // If a schedule is provided in config, start a schedule controller on cfg.schedule_pin
+    // (kept alive until the end of main; dropping it stops the thread)
+    let schedule = build_schedule(&cfg).unwrap_or_else(|e| exit_with_error(e));
+    let schedule_ctl = schedule.filter(|_| gpio::available()).map(|s| {
+        let ctl = ScheduleRppalGpioController::new(cfg.schedule_pin, cfg.invert, s, clock::global())
+            .unwrap_or_else(|e| exit_with_error(e));
+        println!("[startup] schedule active on GPIO {}", cfg.schedule_pin);
+        Arc::new(ctl)
+    });
+
     // Interval controller on GPIO 17 for the TUI
-    let controller = new_controller(cfg.gpio_pin, cfg.invert, None);
+    // (the pin registry refuses to start if it collides with the schedule pin)
+    let controller = new_controller(cfg.gpio_pin, cfg.invert, None, clock::global()).unwrap_or_else(|e| exit_with_error(e));
     controller.set_blink(cfg.blink_on);
     controller.set_interval_ms(cfg.interval_ms);
 
-    // Initialize ADS1115 moisture sensor
-    let sensor = match Ads1115::new() {
-        Ok(s) => {
-            println!("[startup] ADS1115 initialized on I2C");
-            Some(Arc::new(Mutex::new(s)))
+    // Initialize the ADS1115 and one probe per configured sensor
+    let sensors = match probe::open_all(&cfg.adc, &cfg.sensor_list()) {
+        Ok(list) => {
+            let names: Vec<String> = list.iter().map(|p| format!("{} ({})", p.name(), p.channel())).collect();
+            println!(
+                "[startup] ADS1115 initialized on I2C (±{}V, {} SPS, {}): {}",
+                cfg.adc.gain.full_scale(),
+                cfg.adc.data_rate.sps(),
+                cfg.adc.mode,
+                names.join(", ")
+            );
+            list
         }
         Err(e) => {
             println!("[startup] Failed to initialize ADS1115: {}", e);
-            None
+            Vec::new()
         }
     };
 
-    // Run the terminal UI only
-    if let Err(e) = ui::run(controller, cfg, sensor) {
+    // One pump per zone following its sensor, plus the zone's grow light
+    let zones = zone::start_all(&cfg, &sensors).unwrap_or_else(|e| exit_with_error(e));
+
+    // Keep logging samples whether or not anyone is looking at the UI
+    let _sampler = (!sensors.is_empty() && datalog::global().is_some())
+        .then(|| datalog::start_sampler(sensors.clone(), Duration::from_secs(cfg.logging.sample_secs)));
+
+    // Status/control API for the LAN, if built in and enabled
+    #[cfg(feature = "http")]
+    let _api = if cfg.http.enabled {
+        let api = api::Api::new(
+            Arc::clone(&controller),
+            cfg.clone(),
+            sensors.clone(),
+            zones.clone(),
+            schedule_ctl.clone(),
+        );
+        api::start(api, &cfg.http.bind).unwrap_or_else(|e| exit_with_error(e)).into()
+    } else {
+        None
+    };
+
+    // State publishing and commands over MQTT (Home Assistant), if built in and enabled
+    #[cfg(feature = "mqtt")]
+    let _mqtt = cfg.mqtt.enabled.then(|| {
+        let bridge = mqtt::Bridge::new(
+            &cfg,
+            Arc::clone(&controller),
+            schedule_ctl.clone().map(|c| c as Arc<dyn gpio::GpioController + Send + Sync>),
+            sensors.clone(),
+            zones.clone(),
+        );
+        mqtt::start(&cfg.mqtt, bridge)
+    });
+    // Apply edits of the config file while running
+    let _reload = config::config_path().map(|path| {
+        let live = reload::Live {
+            controller: Arc::clone(&controller),
+            schedule: schedule_ctl.clone(),
+            sensors: sensors.clone(),
+            zones: zones.clone(),
+        };
+        reload::start(path, cfg.clone(), live)
+    });
+
+    // Without a remote front end this is only kept alive
+    let _ = &schedule_ctl;
+
+    if let Some(opts) = daemon {
+        if let Err(e) = daemon::run(controller, cfg, sensors, zones, opts) {
+            eprintln!("[daemon] error: {e}");
+        }
+    } else if let Err(e) = ui::run(controller, cfg, sensors, zones, schedule_ctl) {
         eprintln!("TUI error: {e}");
     }
 }
 
-// Build schedule from config: String day names -> Weekday map
-#[cfg(feature = "gpio")]
-fn build_schedule(src: Option<HashMap<String, Vec<(u16, u16)>>>) -> Option<GpioSchedule> {
+fn exit_with_error(e: impl std::fmt::Display) -> ! {
+    eprintln!("[startup] {}", e);
+    shutdown::global().shutdown();
+    std::process::exit(1)
+}
+
+// The schedule pin's regular rule set plus its date overrides; None if nothing is configured.
+// A rule, override or sun-relative entry that doesn't parse is an error.
+fn build_schedule(cfg: &AppConfig) -> Result<Option<GpioSchedule>, String> {
+    let mut sched = build_set(cfg.schedule.clone(), &cfg.schedule_rules, "schedule", "schedule_rules")?;
+    for (i, o) in cfg.schedule_overrides.iter().enumerate() {
+        let key = format!("schedule_overrides[{}]", i);
+        let span = rules::check_override(o).map_err(|e| format!("{}: {}", key, e))?;
+        let replaces = o.schedule.is_some() || !o.rules.is_empty();
+        let schedule = if replaces {
+            Some(build_set(o.schedule.clone(), &o.rules, &format!("{}.schedule", key), &format!("{}.rules", key))?)
+        } else {
+            None
+        };
+        let name = if o.name.trim().is_empty() { span.to_string() } else { o.name.clone() };
+        sched.overrides.push(Override { name, span, skip: o.skip, scale: o.scale, schedule });
+    }
+    sched.location = location(cfg);
+    Ok((!is_empty(&sched)).then_some(sched))
+}
+
+// The light schedule of zone `i` of `cfg.zone_list()`: its weekday table and
+// rules, without date overrides. None if it has none.
+fn build_light(cfg: &AppConfig, i: usize) -> Result<Option<GpioSchedule>, String> {
+    let Some(z) = cfg.zone_list().into_iter().nth(i) else {
+        return Ok(None);
+    };
+    let key = format!("zones[{}]", i);
+    let mut sched =
+        build_set(z.schedule, &z.schedule_rules, &format!("{}.schedule", key), &format!("{}.schedule_rules", key))?;
+    sched.location = location(cfg);
+    Ok((!is_empty(&sched)).then_some(sched))
+}
+
+fn location(cfg: &AppConfig) -> Option<Location> {
+    cfg.latitude.zip(cfg.longitude).map(|(latitude, longitude)| Location { latitude, longitude })
+}
+
+fn is_empty(sched: &GpioSchedule) -> bool {
+    sched.schedule.is_empty()
+        && sched.runs.is_empty()
+        && sched.solar.is_empty()
+        && sched.rules.is_empty()
+        && sched.overrides.is_empty()
+}
+
+// Build one rule set from config: String day names -> Weekday map, plus the rules
+// (`table_key`/`rules_key` name them in errors). Any bad entry is an error.
+fn build_set(
+    src: Option<HashMap<String, Vec<ScheduleEntry>>>,
+    rule_src: &[ScheduleRule],
+    table_key: &str,
+    rules_key: &str,
+) -> Result<GpioSchedule, String> {
     let mut map: HashMap<Weekday, Vec<(u16, u16)>> = HashMap::new();
-    let Some(srcmap) = src else { return None; };
-    for (k, v) in srcmap.into_iter() {
-        if let Some(day) = parse_weekday(&k) {
-            let normalized = normalize_ranges(&k, v);
-            if !normalized.is_empty() {
-                map.entry(day).or_default().extend(normalized);
+    let mut runs: HashMap<Weekday, Vec<(u32, u32)>> = HashMap::new();
+    let mut solar: HashMap<Weekday, Vec<(TimeOfDay, TimeOfDay)>> = HashMap::new();
+    let rules = rule_src
+        .iter()
+        .enumerate()
+        .map(|(i, r)| Rule::from_config(r).map_err(|e| format!("{}[{}]: {}", rules_key, i, e)))
+        .collect::<Result<Vec<Rule>, String>>()?;
+    for (k, v) in src.unwrap_or_default().into_iter() {
+        if let Some(day) = rules::parse_weekday(&k) {
+            for entry in v {
+                match entry {
+                    // `config::validate` reports these with a line and column; here they just fail
+                    ScheduleEntry::Range(s, e) => {
+                        let (today, next) =
+                            split_range(s, e).ok_or_else(|| format!("{}.{}: [{}, {}] is not a valid HHMM range", table_key, k, s, e))?;
+                        map.entry(day).or_default().push(today);
+                        if let Some(next) = next {
+                            map.entry(day.succ()).or_default().push(next);
+                        }
+                    }
+                    ScheduleEntry::Run { at, secs } => {
+                        let start = rules::parse_time_of_day(&at)
+                            .filter(|_| (1..=86400).contains(&secs))
+                            .ok_or_else(|| format!("{}.{}: invalid run at '{}' for {}s", table_key, k, at, secs))?;
+                        runs.entry(day).or_default().push((start, secs));
+                    }
+                    ScheduleEntry::Sun { from, to } => {
+                        let parse = |t: &str| TimeOfDay::parse(t).map_err(|e| format!("{}.{}: {}", table_key, k, e));
+                        solar.entry(day).or_default().push((parse(&from)?, parse(&to)?));
+                    }
+                }
             }
         }
     }
-    if map.is_empty() { None } else { Some(GpioSchedule { schedule: map }) }
+    let map: HashMap<Weekday, Vec<(u16, u16)>> =
+        map.into_iter().map(|(day, ranges)| (day, merge_ranges(ranges))).collect();
+    Ok(GpioSchedule { schedule: map, runs, solar, rules, ..Default::default() })
 }
 
-#[cfg(feature = "gpio")]
-fn parse_weekday(s: &str) -> Option<Weekday> {
-    let t = s.trim().to_lowercase();
-    match t.as_str() {
-        "mon" | "monday" => Some(Weekday::Mon),
-        "tue" | "tues" | "tuesday" => Some(Weekday::Tue),
-        "wed" | "weds" | "wednesday" => Some(Weekday::Wed),
-        "thu" | "thur" | "thurs" | "thursday" => Some(Weekday::Thu),
-        "fri" | "friday" => Some(Weekday::Fri),
-        "sat" | "saturday" => Some(Weekday::Sat),
-        "sun" | "sunday" => Some(Weekday::Sun),
-        _ => None,
+// (start, end) in HHMM
+type Range = (u16, u16);
+
+// Validate one range. An overnight range (start > end) runs to midnight (2400)
+// and continues on the next day from 0000.
+fn split_range(s: u16, e: u16) -> Option<(Range, Option<Range>)> {
+    if !is_hhmm(s) || !(is_hhmm(e) || e == 2400) || s == e {
+        return None;
+    }
+    if s < e {
+        Some(((s, e), None))
+    } else {
+        Some(((s, 2400), (e > 0).then_some((0, e))))
     }
 }
 
-#[cfg(feature = "gpio")]
-fn normalize_ranges(day_key: &str, ranges: Vec<(u16, u16)>) -> Vec<(u16, u16)> {
-    // Filter invalid HHMM and start/end, then sort and merge overlaps/adjacent.
-    let mut valid: Vec<(u16, u16)> = ranges
-        .into_iter()
-        .filter(|(s, e)| {
-            let ok = is_hhmm(*s) && is_hhmm(*e) && s < e;
-            if !ok {
-                println!("[schedule] drop invalid {:?} for {}", (s, e), day_key);
-            }
-            ok
-        })
-        .collect();
-
-    valid.sort_by_key(|(s, _)| *s);
+fn merge_ranges(mut ranges: Vec<(u16, u16)>) -> Vec<(u16, u16)> {
+    // Sort and merge overlaps/adjacent.
+    ranges.sort_by_key(|(s, _)| *s);
     let mut out: Vec<(u16, u16)> = Vec::new();
-    for (s, e) in valid {
+    for (s, e) in ranges {
         if let Some((_last_s, last_e)) = out.last_mut() {
             if s <= *last_e { // overlap or touch; merge
                 if e > *last_e { *last_e = e; }
@@ -119,9 +336,115 @@ fn normalize_ranges(day_key: &str, ranges: Vec<(u16, u16)>) -> Vec<(u16, u16)> {
     out
 }
 
-#[cfg(feature = "gpio")]
 fn is_hhmm(v: u16) -> bool {
     let hh = v / 100;
     let mm = v % 100;
     hh < 24 && mm < 60
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn build(days: &[(&str, Vec<ScheduleEntry>)]) -> Option<GpioSchedule> {
+        let cfg = AppConfig {
+            schedule: Some(days.iter().map(|(d, r)| (d.to_string(), r.clone())).collect()),
+            ..Default::default()
+        };
+        build_schedule(&cfg).unwrap()
+    }
+
+    fn schedule(days: &[(&str, Vec<(u16, u16)>)]) -> HashMap<Weekday, Vec<(u16, u16)>> {
+        let days: Vec<_> = days
+            .iter()
+            .map(|(d, r)| (*d, r.iter().map(|(s, e)| ScheduleEntry::Range(*s, *e)).collect()))
+            .collect();
+        build(&days).map(|s| s.schedule).unwrap_or_default()
+    }
+
+    #[test]
+    fn overnight_ranges_continue_on_the_next_day() {
+        let m = schedule(&[("Sunday", vec![(2200, 600)]), ("Monday", vec![(500, 900)])]);
+        assert_eq!(m[&Weekday::Sun], vec![(2200, 2400)]);
+        // Sunday night wraps into Monday and merges with Monday's own range
+        assert_eq!(m[&Weekday::Mon], vec![(0, 900)]);
+
+        // Until midnight, and a whole day given as 0000-2400
+        let m = schedule(&[("Fri", vec![(2300, 0)]), ("Sat", vec![(0, 2400)])]);
+        assert_eq!(m[&Weekday::Fri], vec![(2300, 2400)]);
+        assert_eq!(m[&Weekday::Sat], vec![(0, 2400)]);
+        assert!(!m.contains_key(&Weekday::Sun));
+    }
+
+    #[test]
+    fn invalid_ranges_are_errors() {
+        for (s, e) in [(900, 900), (2400, 100), (960, 1000), (1000, 2401)] {
+            let cfg = AppConfig {
+                schedule: Some(HashMap::from([("Mon".to_string(), vec![ScheduleEntry::Range(1200, 1300), ScheduleEntry::Range(s, e)])])),
+                ..Default::default()
+            };
+            assert_eq!(build_schedule(&cfg).unwrap_err(), format!("schedule.Mon: [{}, {}] is not a valid HHMM range", s, e));
+        }
+    }
+
+    #[test]
+    fn timed_runs_parse_to_the_second() {
+        let run = |at: &str, secs| ScheduleEntry::Run { at: at.to_string(), secs };
+        let sched = build(&[("Mon", vec![run("07:30:00", 45), run("7:31", 5)])]).unwrap();
+        assert_eq!(sched.runs[&Weekday::Mon], vec![(27000, 45), (27060, 5)]);
+        assert!(sched.schedule.is_empty());
+        for bad in [run("07:30:60", 5), run("25:00", 5), run("08:00", 0), run("8", 5), run("noon", 5)] {
+            let cfg = AppConfig { schedule: Some(HashMap::from([("Mon".to_string(), vec![bad])])), ..Default::default() };
+            assert!(build_schedule(&cfg).unwrap_err().starts_with("schedule.Mon: invalid run at "));
+        }
+    }
+
+    #[test]
+    fn rules_start_a_schedule_and_bad_rules_are_errors() {
+        let every = ScheduleRule { every_hours: Some(6), secs: 30, ..Default::default() };
+        let mut cfg = AppConfig { schedule_rules: vec![every], ..Default::default() };
+        assert_eq!(build_schedule(&cfg).unwrap().unwrap().rules.len(), 1);
+        let bad = ScheduleRule { cron: Some("0 7 * * funday".to_string()), secs: 30, ..Default::default() };
+        cfg.schedule_rules.push(bad);
+        assert_eq!(
+            build_schedule(&cfg).unwrap_err(),
+            "schedule_rules[1]: cron '0 7 * * funday': day-of-week 'funday' is not a number"
+        );
+    }
+
+    #[test]
+    fn zone_lights_follow_their_own_schedule() {
+        let cfg: AppConfig = toml::from_str(
+            "[schedule]\nMon = [[900, 1000]]\n\
+             [[zones]]\nname = \"basil\"\nsensor = \"moisture\"\npump_pin = 22\nlight_pin = 5\nschedule = { Mon = [[600, 2000]] }\n\
+             [[zones]]\nname = \"mint\"\nsensor = \"mint\"\npump_pin = 23\nschedule_rules = [{ every_hours = 0, secs = 5 }]\n",
+        )
+        .unwrap();
+        assert_eq!(build_light(&cfg, 0).unwrap().unwrap().schedule[&Weekday::Mon], vec![(600, 2000)]);
+        assert!(build_light(&cfg, 1).unwrap_err().starts_with("zones[1].schedule_rules[0]: "));
+        assert!(build_light(&cfg, 2).unwrap().is_none());
+    }
+
+    #[test]
+    fn overrides_alone_make_a_schedule() {
+        let summer: AppConfig = toml::from_str(
+            "[[schedule_overrides]]\nfrom = \"06-01\"\nto = \"08-31\"\nschedule = { Mon = [[600, 630]] }\n\
+             [[schedule_overrides]]\nname = \"vacation\"\nfrom = \"2026-12-20\"\nto = \"2027-01-03\"\nskip = true\n",
+        )
+        .unwrap();
+        let sched = build_schedule(&summer).unwrap().unwrap();
+        assert!(sched.schedule.is_empty());
+        assert_eq!(sched.overrides[0].name, "06-01..08-31");
+        assert_eq!(sched.overrides[0].schedule.as_ref().unwrap().schedule[&Weekday::Mon], vec![(600, 630)]);
+        assert!(sched.overrides[1].skip);
+
+        let bad: AppConfig = toml::from_str(
+            "[[schedule_overrides]]\nfrom = \"06-01\"\nto = \"08-31\"\nrules = [{ cron = \"0 7\", secs = 5 }]\n",
+        )
+        .unwrap();
+        assert_eq!(
+            build_schedule(&bad).unwrap_err(),
+            "schedule_overrides[0]: rules[0]: cron '0 7': expected 5 fields (minute hour day-of-month month day-of-week), found 2"
+        );
+    }
+}
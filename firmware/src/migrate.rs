@@ -0,0 +1,153 @@
+// Upgrades of older config files. Each step edits the TOML document in place,
+// so the comments and layout of a hand-edited file survive.
+
+use toml_edit::{value, ArrayOfTables, DocumentMut, Item, Table};
+
+/// Schema version written by this firmware; files without `version` are 1.
+pub const CURRENT_VERSION: u32 = 2;
+
+type Step = fn(&mut DocumentMut) -> Vec<String>;
+
+// STEPS[i] takes a document from version i + 1 to i + 2
+const STEPS: [Step; CURRENT_VERSION as usize - 1] = [sensor_table];
+
+/// A file brought up to `CURRENT_VERSION`.
+#[derive(Debug)]
+pub struct Migrated {
+    pub from: u32,
+    pub text: String,
+    // What each step changed, for the log
+    pub notes: Vec<String>,
+}
+
+/// Upgrade `text` to the current schema; `None` if it's already current (or
+/// doesn't parse, which `config::check` reports). A version from a newer
+/// firmware is an error.
+pub fn migrate(text: &str) -> Result<Option<Migrated>, String> {
+    let Ok(mut doc) = text.parse::<DocumentMut>() else {
+        return Ok(None);
+    };
+    let from = match doc.get("version") {
+        None => 1,
+        Some(v) => match v.as_integer() {
+            Some(n) if (1..=CURRENT_VERSION as i64).contains(&n) => n as u32,
+            Some(n) if n > CURRENT_VERSION as i64 => {
+                return Err(format!(
+                    "version = {} is from a newer firmware; this one reads up to version {}",
+                    n, CURRENT_VERSION
+                ))
+            }
+            _ => return Err(format!("version must be a whole number from 1 to {}", CURRENT_VERSION)),
+        },
+    };
+    if from == CURRENT_VERSION {
+        return Ok(None);
+    }
+    let mut notes = Vec::new();
+    for step in &STEPS[from as usize - 1..] {
+        notes.extend(step(&mut doc));
+    }
+    let text = match doc.get_mut("version").and_then(|v| v.as_value_mut()) {
+        Some(v) => {
+            let decor = v.decor().clone();
+            *v = (CURRENT_VERSION as i64).into();
+            *v.decor_mut() = decor;
+            doc.to_string()
+        }
+        // Put a new version key first, above any comments
+        None => format!("version = {}\n{}", CURRENT_VERSION, doc),
+    };
+    Ok(Some(Migrated { from, text, notes }))
+}
+
+// 1 -> 2: the flat calibration of the single A3 probe becomes a [[sensors]] entry
+fn sensor_table(doc: &mut DocumentMut) -> Vec<String> {
+    let dry = doc.remove("moisture_dry_value");
+    let wet = doc.remove("moisture_wet_value");
+    if dry.is_none() && wet.is_none() {
+        return Vec::new();
+    }
+    if doc.contains_key("sensors") {
+        return vec!["dropped moisture_dry_value/moisture_wet_value; [[sensors]] has its own calibration".to_string()];
+    }
+    let mut sensor = Table::new();
+    sensor.insert("name", value(crate::config::DEFAULT_SENSOR));
+    sensor.insert("channel", value("a3"));
+    for (key, item) in [("dry", dry), ("wet", wet)] {
+        if let Some(Item::Value(mut v)) = item {
+            v.decor_mut().clear();
+            sensor.insert(key, Item::Value(v));
+        }
+    }
+    let mut sensors = ArrayOfTables::new();
+    sensors.push(sensor);
+    doc.insert("sensors", Item::ArrayOfTables(sensors));
+    vec![format!("moved moisture_dry_value/moisture_wet_value into [[sensors]] '{}' on a3", crate::config::DEFAULT_SENSOR)]
+}
+
+/// Lines only in `old` as `- line` and only in `new` as `+ line`, in file order.
+pub fn diff(old: &str, new: &str) -> Vec<String> {
+    let (a, b): (Vec<&str>, Vec<&str>) = (old.lines().collect(), new.lines().collect());
+    // Longest common subsequence, filled from the end
+    let mut lcs = vec![vec![0usize; b.len() + 1]; a.len() + 1];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lcs[i][j] = if a[i] == b[j] { lcs[i + 1][j + 1] + 1 } else { lcs[i + 1][j].max(lcs[i][j + 1]) };
+        }
+    }
+    let (mut i, mut j, mut out) = (0, 0, Vec::new());
+    while i < a.len() || j < b.len() {
+        if i < a.len() && j < b.len() && a[i] == b[j] {
+            i += 1;
+            j += 1;
+        } else if j < b.len() && (i == a.len() || lcs[i][j + 1] >= lcs[i + 1][j]) {
+            out.push(format!("+ {}", b[j]).trim_end().to_string());
+            j += 1;
+        } else {
+            out.push(format!("- {}", a[i]).trim_end().to_string());
+            i += 1;
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::AppConfig;
+
+    #[test]
+    fn flat_calibration_moves_into_sensors() {
+        let old = "# kitchen pot\ninterval_ms = 1500\nmoisture_dry_value = 22481 # in air\nmoisture_wet_value = 10520\n\n[safety]\nmax_on_secs = 600\n";
+        let m = migrate(old).unwrap().unwrap();
+        assert_eq!(m.from, 1);
+        assert_eq!(m.notes.len(), 1);
+        assert!(m.text.starts_with("version = 2\n# kitchen pot\ninterval_ms = 1500\n\n[safety]\n"), "{}", m.text);
+        assert!(m.text.ends_with("[[sensors]]\nname = \"moisture\"\nchannel = \"a3\"\ndry = 22481\nwet = 10520\n"), "{}", m.text);
+        let cfg: AppConfig = toml::from_str(&m.text).unwrap();
+        assert_eq!(cfg.version, CURRENT_VERSION);
+        assert_eq!(cfg.sensor_list()[0].calibration(), Some((22481, 10520)));
+        // Already current: nothing to do
+        assert!(migrate(&m.text).unwrap().is_none());
+        assert_eq!(diff(old, &m.text).first().map(String::as_str), Some("+ version = 2"));
+    }
+
+    #[test]
+    fn versions_are_checked() {
+        assert!(migrate("version = 1\n").unwrap().unwrap().text.starts_with("version = 2\n"));
+        // Own calibration wins over the flat keys
+        let m = migrate("moisture_dry_value = 1\n[[sensors]]\nname = \"basil\"\nchannel = \"a0\"\n").unwrap().unwrap();
+        assert!(!m.text.contains("moisture_dry_value"));
+        assert!(m.notes[0].starts_with("dropped"));
+        assert!(migrate("version = 9\n").unwrap_err().contains("newer firmware"));
+        assert!(migrate("version = \"two\"\n").is_err());
+        // Parse errors are left to config::check
+        assert!(migrate("version = [\n").unwrap().is_none());
+    }
+
+    #[test]
+    fn diff_marks_changed_lines() {
+        assert_eq!(diff("a\nb\nc\n", "a\nc\nd\n"), ["- b", "+ d"]);
+        assert!(diff("same\n", "same\n").is_empty());
+    }
+}
@@ -0,0 +1,435 @@
+// MQTT bridge (feature `mqtt`): publishes moisture, pin and watering state and
+// accepts commands, announcing everything through Home Assistant discovery.
+//
+// Under <base_topic>/<client_id>/:
+//   availability         online | offline (retained, last will)
+//   moisture/<sensor>    {"raw":..,"voltage":..,"percent":..}
+//   pin/<gpio>           ON | OFF
+//   schedule, interval   current values
+//   schedule/set         ON | OFF
+//   interval/set         blink interval in ms
+//   zone/<zone>/watering     idle | dosing | soaking | fault
+//   zone/<zone>/water/set    PRESS (one max_dose_secs dose) or a number of seconds
+//   zone/<zone>/light        ON | OFF (grow light schedule enabled)
+//   zone/<zone>/light/set    ON | OFF
+
+use crate::analog::MoistureProbe;
+use crate::config::{self, AppConfig, MqttConfig};
+use crate::gpio::manual;
+use crate::gpio::registry;
+use crate::gpio::shutdown::{self, WorkerHandle};
+use crate::gpio::GpioController;
+use crate::zone::Zone;
+use rumqttc::{Client, Event, LastWill, MqttOptions, Packet, QoS};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Where the bridge sends messages; the broker connection or a test fake.
+pub trait Transport {
+    fn publish(&mut self, topic: &str, payload: &str, retain: bool);
+    fn subscribe(&mut self, topic: &str);
+}
+
+impl Transport for Client {
+    // try_* so a dead broker can never block the control loop
+    fn publish(&mut self, topic: &str, payload: &str, retain: bool) {
+        let _ = self.try_publish(topic, QoS::AtLeastOnce, retain, payload.as_bytes().to_vec());
+    }
+    fn subscribe(&mut self, topic: &str) {
+        let _ = self.try_subscribe(topic, QoS::AtLeastOnce);
+    }
+}
+
+pub struct Bridge {
+    node: String,
+    base: String,
+    discovery: String,
+    ctl: Arc<dyn GpioController + Send + Sync>,
+    schedule: Option<Arc<dyn GpioController + Send + Sync>>,
+    sensors: Vec<Arc<MoistureProbe>>,
+    zones: Vec<Arc<Zone>>,
+    // Last payload per state topic, so only changes are published
+    last: HashMap<String, String>,
+}
+
+impl Bridge {
+    pub fn new(
+        cfg: &AppConfig,
+        ctl: Arc<dyn GpioController + Send + Sync>,
+        schedule: Option<Arc<dyn GpioController + Send + Sync>>,
+        sensors: Vec<Arc<MoistureProbe>>,
+        zones: Vec<Arc<Zone>>,
+    ) -> Self {
+        let node = slug(&cfg.mqtt.client_id);
+        Self {
+            base: format!("{}/{}", cfg.mqtt.base_topic.trim_end_matches('/'), node),
+            discovery: cfg.mqtt.discovery_prefix.trim_end_matches('/').to_string(),
+            node,
+            ctl,
+            schedule,
+            sensors,
+            zones,
+            last: HashMap::new(),
+        }
+    }
+
+    pub fn topic(&self, suffix: &str) -> String {
+        format!("{}/{}", self.base, suffix)
+    }
+
+    /// (Re)connected: subscribe, announce to Home Assistant and republish all state.
+    pub fn on_connect(&mut self, t: &mut dyn Transport) {
+        for cmd in ["schedule/set", "interval/set"] {
+            t.subscribe(&self.topic(cmd));
+        }
+        for z in &self.zones {
+            for cmd in ["water/set", "light/set"] {
+                t.subscribe(&self.topic(&format!("zone/{}/{}", slug(&z.name), cmd)));
+            }
+        }
+        for (topic, payload) in self.discovery() {
+            t.publish(&topic, &payload.to_string(), true);
+        }
+        t.publish(&self.topic("availability"), "online", true);
+        self.last.clear();
+        self.publish_state(t);
+        self.publish_moisture(t);
+    }
+
+    /// Publish pin, zone, schedule and interval state that changed since last time.
+    pub fn publish_state(&mut self, t: &mut dyn Transport) {
+        let mut state = Vec::new();
+        for (pin, on) in registry::global().states() {
+            state.push((format!("pin/{}", pin), on_off(on).to_string()));
+        }
+        for z in &self.zones {
+            if let Some(w) = &z.watering {
+                state.push((format!("zone/{}/watering", slug(&z.name)), w.state().to_string()));
+            }
+            if let Some(l) = &z.light {
+                state.push((format!("zone/{}/light", slug(&z.name)), on_off(l.is_blink()).to_string()));
+            }
+        }
+        if let Some(s) = &self.schedule {
+            state.push(("schedule".to_string(), on_off(s.is_blink()).to_string()));
+        }
+        state.push(("interval".to_string(), self.ctl.interval_ms().to_string()));
+
+        for (suffix, payload) in state {
+            let topic = self.topic(&suffix);
+            if self.last.get(&topic) != Some(&payload) {
+                t.publish(&topic, &payload, true);
+                self.last.insert(topic, payload);
+            }
+        }
+    }
+
+    pub fn publish_moisture(&mut self, t: &mut dyn Transport) {
+        for probe in &self.sensors {
+            if let Ok(r) = probe.read() {
+                t.publish(&self.topic(&format!("moisture/{}", slug(probe.name()))), &json!(r).to_string(), false);
+            }
+        }
+    }
+
+    /// Apply a command message. Unknown topics are ignored.
+    pub fn on_message(&mut self, topic: &str, payload: &str) -> Result<(), String> {
+        let Some(cmd) = topic.strip_prefix(&self.base).and_then(|s| s.strip_prefix('/')) else {
+            return Ok(());
+        };
+        let payload = payload.trim();
+        match cmd {
+            "schedule/set" => {
+                let sched = self.schedule.as_ref().ok_or("no schedule configured")?;
+                sched.set_blink(parse_on(payload, "schedule")?);
+                Ok(())
+            }
+            "interval/set" => {
+                let ms: u64 = payload
+                    .parse::<f64>()
+                    .ok()
+                    .filter(|v| (50.0..=10_000.0).contains(v))
+                    .map(|v| v as u64)
+                    .ok_or_else(|| format!("interval must be 50..=10000 ms, got '{}'", payload))?;
+                self.ctl.set_interval_ms(ms);
+                config::update_config(|c| c.interval_ms = ms).map(|_| ()).map_err(|e| e.to_string())
+            }
+            _ => match cmd.strip_prefix("zone/").and_then(|s| s.split_once('/')) {
+                Some((id, action)) => self.zone_command(id, action, payload),
+                None => Ok(()),
+            },
+        }
+    }
+
+    // A command under zone/<zone>/
+    fn zone_command(&self, id: &str, action: &str, payload: &str) -> Result<(), String> {
+        let Some(z) = self.zones.iter().find(|z| slug(&z.name) == id) else {
+            return Ok(());
+        };
+        match action {
+            "water/set" => {
+                let dose = z.dose.as_ref().ok_or_else(|| format!("zone '{}' has no moisture sensor", z.name))?;
+                let secs = if payload.is_empty() || payload.eq_ignore_ascii_case("PRESS") {
+                    z.dose_secs
+                } else {
+                    payload.parse().map_err(|_| format!("bad dose '{}'", payload))?
+                };
+                if !(1..=manual::MAX_SECS).contains(&secs) {
+                    return Err(format!("dose must be 1..={} seconds", manual::MAX_SECS));
+                }
+                dose.start(secs, "mqtt").map_err(|e| e.to_string())
+            }
+            "light/set" => {
+                let light = z.light.as_ref().ok_or_else(|| format!("zone '{}' has no light schedule", z.name))?;
+                light.set_blink(parse_on(payload, "light")?);
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // Home Assistant MQTT discovery: one retained config message per entity.
+    fn discovery(&self) -> Vec<(String, Value)> {
+        let device = json!({
+            "identifiers": [self.node],
+            "name": format!("Plants {}", self.node),
+            "manufacturer": "plants-love-rust",
+            "sw_version": env!("CARGO_PKG_VERSION"),
+        });
+        let avail = self.topic("availability");
+        let entity = |component: &str, id: &str, mut body: Value| {
+            body["unique_id"] = json!(format!("{}_{}", self.node, id));
+            body["availability_topic"] = json!(avail);
+            body["device"] = device.clone();
+            (format!("{}/{}/{}/{}/config", self.discovery, component, self.node, id), body)
+        };
+
+        let mut out = Vec::new();
+        for probe in &self.sensors {
+            let id = slug(probe.name());
+            let state = self.topic(&format!("moisture/{}", id));
+            out.push(entity("sensor", &format!("moisture_{}", id), json!({
+                "name": format!("Moisture {}", probe.name()),
+                "device_class": "moisture",
+                "unit_of_measurement": "%",
+                "state_topic": state,
+                "value_template": "{{ value_json.percent }}",
+            })));
+            out.push(entity("sensor", &format!("moisture_raw_{}", id), json!({
+                "name": format!("Moisture raw {}", probe.name()),
+                "state_topic": state,
+                "value_template": "{{ value_json.raw }}",
+                "entity_category": "diagnostic",
+            })));
+        }
+        for (pin, _) in registry::global().states() {
+            out.push(entity("binary_sensor", &format!("gpio{}", pin), json!({
+                "name": format!("GPIO {}", pin),
+                "state_topic": self.topic(&format!("pin/{}", pin)),
+            })));
+        }
+        for z in &self.zones {
+            let id = slug(&z.name);
+            let topic = |suffix: &str| self.topic(&format!("zone/{}/{}", id, suffix));
+            if z.watering.is_some() {
+                out.push(entity("sensor", &format!("watering_{}", id), json!({
+                    "name": format!("Watering {}", z.name),
+                    "state_topic": topic("watering"),
+                })));
+                out.push(entity("button", &format!("water_{}", id), json!({
+                    "name": format!("Water {} now", z.name),
+                    "command_topic": topic("water/set"),
+                })));
+            }
+            if z.light.is_some() {
+                out.push(entity("switch", &format!("light_{}", id), json!({
+                    "name": format!("Light {}", z.name),
+                    "state_topic": topic("light"),
+                    "command_topic": topic("light/set"),
+                })));
+            }
+        }
+        if self.schedule.is_some() {
+            out.push(entity("switch", "schedule", json!({
+                "name": "Schedule",
+                "state_topic": self.topic("schedule"),
+                "command_topic": self.topic("schedule/set"),
+            })));
+        }
+        out.push(entity("number", "interval", json!({
+            "name": "Blink interval",
+            "unit_of_measurement": "ms",
+            "min": 50,
+            "max": 10000,
+            "step": 50,
+            "state_topic": self.topic("interval"),
+            "command_topic": self.topic("interval/set"),
+        })));
+        out
+    }
+}
+
+// Topic- and id-safe form of a user-chosen name
+fn slug(s: &str) -> String {
+    s.chars().map(|c| if c.is_ascii_alphanumeric() || c == '_' || c == '-' { c } else { '_' }).collect()
+}
+
+fn parse_on(payload: &str, what: &str) -> Result<bool, String> {
+    match payload.to_ascii_uppercase().as_str() {
+        "ON" | "TRUE" | "1" => Ok(true),
+        "OFF" | "FALSE" | "0" => Ok(false),
+        _ => Err(format!("bad {} state '{}'", what, payload)),
+    }
+}
+
+fn on_off(on: bool) -> &'static str {
+    if on { "ON" } else { "OFF" }
+}
+
+/// Connect to the broker and run the bridge until shutdown. The client keeps
+/// reconnecting on its own; state is republished after every (re)connect.
+pub fn start(cfg: &MqttConfig, mut bridge: Bridge) -> WorkerHandle {
+    let mut opts = MqttOptions::new(cfg.client_id.clone(), cfg.host.clone(), cfg.port);
+    opts.set_keep_alive(Duration::from_secs(30));
+    opts.set_last_will(LastWill::new(bridge.topic("availability"), "offline", QoS::AtLeastOnce, true));
+    if let Some(user) = &cfg.username {
+        opts.set_credentials(user.clone(), cfg.password.clone().unwrap_or_default());
+    }
+    let publish_every = Duration::from_secs(cfg.publish_secs.max(1));
+    println!("[mqtt] connecting to {}:{} as {}", cfg.host, cfg.port, cfg.client_id);
+
+    shutdown::global().spawn("mqtt", move |stop| {
+        let (mut client, mut connection) = Client::new(opts, 64);
+        let mut last_moisture = Instant::now();
+        let mut connected = false;
+        while !stop.is_set() {
+            match connection.recv_timeout(Duration::from_millis(250)) {
+                Ok(Ok(Event::Incoming(Packet::ConnAck(_)))) => {
+                    println!("[mqtt] connected");
+                    connected = true;
+                    bridge.on_connect(&mut client);
+                    last_moisture = Instant::now();
+                }
+                Ok(Ok(Event::Incoming(Packet::Publish(p)))) => {
+                    let payload = String::from_utf8_lossy(&p.payload);
+                    if let Err(e) = bridge.on_message(&p.topic, &payload) {
+                        println!("[mqtt] {}: {}", p.topic, e);
+                    }
+                }
+                Ok(Ok(_)) => {}
+                Ok(Err(e)) => {
+                    if connected {
+                        println!("[mqtt] connection lost: {}", e);
+                        connected = false;
+                    }
+                    // Back off before the next reconnect attempt
+                    if !stop.wait(Duration::from_secs(5)) {
+                        break;
+                    }
+                }
+                Err(_) => {} // timeout: nothing arrived
+            }
+            if connected {
+                bridge.publish_state(&mut client);
+                if last_moisture.elapsed() >= publish_every {
+                    bridge.publish_moisture(&mut client);
+                    last_moisture = Instant::now();
+                }
+            }
+        }
+        if connected {
+            Transport::publish(&mut client, &bridge.topic("availability"), "offline", true);
+            let _ = client.disconnect();
+            // Give the event loop a moment to flush the goodbye
+            let _ = connection.recv_timeout(Duration::from_millis(200));
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gpio::fake::FakeCtl;
+
+    #[derive(Default)]
+    struct FakeTransport {
+        published: Vec<(String, String, bool)>,
+        subscribed: Vec<String>,
+    }
+
+    impl Transport for FakeTransport {
+        fn publish(&mut self, topic: &str, payload: &str, retain: bool) {
+            self.published.push((topic.to_string(), payload.to_string(), retain));
+        }
+        fn subscribe(&mut self, topic: &str) {
+            self.subscribed.push(topic.to_string());
+        }
+    }
+
+    fn bridge() -> (Bridge, Arc<FakeCtl>) {
+        let mut cfg = AppConfig::default();
+        cfg.mqtt.client_id = "pi one".to_string();
+        let sched = Arc::new(FakeCtl::default());
+        // A zone whose sensor didn't open: no pump controller, no doses
+        let zone = Zone {
+            name: "basil pot".to_string(),
+            sensor: "basil".to_string(),
+            pump_pin: 22,
+            light_pin: None,
+            dose_secs: 10,
+            watering: None,
+            dose: None,
+            light: None,
+        };
+        let b = Bridge::new(&cfg, Arc::new(FakeCtl::default()), Some(sched.clone()), Vec::new(), vec![Arc::new(zone)]);
+        (b, sched)
+    }
+
+    #[test]
+    fn connect_announces_and_subscribes() {
+        let (mut b, _) = bridge();
+        let mut t = FakeTransport::default();
+        b.on_connect(&mut t);
+        assert!(t.subscribed.contains(&"plants/pi_one/schedule/set".to_string()));
+        assert!(t.subscribed.contains(&"plants/pi_one/zone/basil_pot/water/set".to_string()));
+        let (_, cfg, retain) = t
+            .published
+            .iter()
+            .find(|(topic, _, _)| topic == "homeassistant/switch/pi_one/schedule/config")
+            .unwrap();
+        assert!(retain);
+        let cfg: Value = serde_json::from_str(cfg).unwrap();
+        assert_eq!(cfg["unique_id"], "pi_one_schedule");
+        assert_eq!(cfg["command_topic"], "plants/pi_one/schedule/set");
+        assert!(t.published.iter().any(|(topic, p, _)| topic == "plants/pi_one/availability" && p == "online"));
+        assert!(t.published.iter().any(|(topic, p, _)| topic == "plants/pi_one/schedule" && p == "OFF"));
+    }
+
+    #[test]
+    fn state_is_only_republished_on_change() {
+        let (mut b, sched) = bridge();
+        let mut t = FakeTransport::default();
+        b.on_connect(&mut t);
+        t.published.clear();
+        b.publish_state(&mut t);
+        assert!(t.published.is_empty());
+        b.on_message("plants/pi_one/schedule/set", "ON").unwrap();
+        assert!(sched.is_blink());
+        b.publish_state(&mut t);
+        assert_eq!(t.published, vec![("plants/pi_one/schedule".to_string(), "ON".to_string(), true)]);
+    }
+
+    #[test]
+    fn bad_commands_are_rejected() {
+        let (mut b, _) = bridge();
+        assert!(b.on_message("plants/pi_one/schedule/set", "maybe").is_err());
+        assert!(b.on_message("plants/pi_one/interval/set", "5").is_err());
+        assert!(b.on_message("plants/other/schedule/set", "maybe").is_ok());
+        assert!(b.on_message("plants/pi_one/zone/basil_pot/water/set", "PRESS").is_err());
+        assert!(b.on_message("plants/pi_one/zone/basil_pot/light/set", "ON").is_err());
+        // Zones that don't exist are ignored like any unknown topic
+        assert!(b.on_message("plants/pi_one/zone/mint/water/set", "PRESS").is_ok());
+    }
+}
@@ -0,0 +1,211 @@
+// Watch the config file and apply edits to the running controllers. An edit
+// that doesn't pass `config::check` is logged and the running config is kept.
+
+use crate::analog::MoistureProbe;
+use crate::{build_light, build_schedule};
+use crate::config::{self, AppConfig, SensorConfig};
+use crate::gpio::{safety, shutdown, GpioController, ScheduleRppalGpioController};
+use crate::gpio::shutdown::WorkerHandle;
+use crate::zone::Zone;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+// Top-level keys a reload applies; changes to any other key wait for a restart
+const LIVE_KEYS: &[&str] = &[
+    "blink_on",
+    "interval_ms",
+    "schedule",
+    "schedule_rules",
+    "schedule_overrides",
+    "latitude",
+    "longitude",
+    "safety",
+    "sensor calibration",
+    "zone schedules",
+];
+
+/// The running parts a reload can change.
+pub struct Live {
+    pub controller: Arc<dyn GpioController + Send + Sync>,
+    pub schedule: Option<Arc<ScheduleRppalGpioController>>,
+    pub sensors: Vec<Arc<MoistureProbe>>,
+    pub zones: Vec<Arc<Zone>>,
+}
+
+/// Check `path` once a second and apply changes on top of `cfg`, the config
+/// the controllers were started with.
+pub fn start(path: PathBuf, cfg: AppConfig, live: Live) -> WorkerHandle {
+    shutdown::global().spawn("config-reload", move |stop| {
+        let mut running = cfg;
+        let mut seen = stamp(&path);
+        while stop.wait(Duration::from_secs(1)) {
+            let now = stamp(&path);
+            if now == seen {
+                continue;
+            }
+            seen = now;
+            match reload(&path, &running, &live) {
+                Ok(cfg) => running = cfg,
+                Err(e) => println!("[config] change not applied, keeping the running config:\n{}", e),
+            }
+        }
+    })
+}
+
+// Modification time and size, to notice edits without reading the file
+fn stamp(path: &Path) -> Option<(SystemTime, u64)> {
+    let meta = fs::metadata(path).ok()?;
+    Some((meta.modified().ok()?, meta.len()))
+}
+
+fn reload(path: &Path, running: &AppConfig, live: &Live) -> Result<AppConfig, String> {
+    let mut cfg = config::with_env(config::read_config(path)?)?;
+    // `--sim` isn't in the file
+    cfg.sim.enabled |= running.sim.enabled;
+    let changed = changed_keys(running, &cfg);
+    if changed.is_empty() {
+        return Ok(cfg);
+    }
+    let schedule = build_schedule(&cfg)?;
+    let zones = cfg.zone_list();
+    let lights = (0..zones.len()).map(|i| build_light(&cfg, i)).collect::<Result<Vec<_>, String>>()?;
+
+    let mut applied = Vec::new();
+    // The TUI saves what it just set; don't report those as changes
+    if live.controller.is_blink() != cfg.blink_on {
+        live.controller.set_blink(cfg.blink_on);
+        applied.push("blink_on".to_string());
+    }
+    if live.controller.interval_ms() != cfg.interval_ms {
+        live.controller.set_interval_ms(cfg.interval_ms);
+        applied.push("interval_ms".to_string());
+    }
+    // Only calibrations the file changed (sim probes get a default one)
+    let (before, after) = (running.sensor_list(), cfg.sensor_list());
+    for p in &live.sensors {
+        let cal = |list: &[SensorConfig]| list.iter().find(|s| s.name == p.name()).and_then(|s| s.calibration());
+        if cal(&before) != cal(&after) {
+            p.set_calibration(cal(&after));
+            applied.push(format!("calibration of '{}'", p.name()));
+        }
+    }
+    if changed.iter().any(|k| k == "safety") {
+        safety::global().configure(safety::Limits::from(&cfg.safety), cfg.pump_pins());
+        applied.push("safety".to_string());
+    }
+    let mut restart: Vec<String> = changed.iter().filter(|k| !LIVE_KEYS.contains(&k.as_str())).cloned().collect();
+    let schedule_keys = ["schedule", "schedule_rules", "schedule_overrides", "latitude", "longitude"];
+    if changed.iter().any(|k| schedule_keys.contains(&k.as_str())) {
+        match (&live.schedule, schedule) {
+            (Some(ctl), s) => {
+                ctl.set_schedule(s.unwrap_or_default());
+                applied.push("schedule".to_string());
+            }
+            // No scheduler was started, so there's no pin to drive yet
+            (None, Some(_)) => restart.push("schedule".to_string()),
+            (None, None) => {}
+        }
+    }
+    if changed.iter().any(|k| k == "zone schedules") {
+        for (z, light) in zones.iter().zip(lights) {
+            let live = live.zones.iter().find(|l| l.name == z.name).and_then(|l| l.light.as_ref());
+            match (live, light) {
+                (Some(ctl), s) => {
+                    ctl.set_schedule(s.unwrap_or_default());
+                    applied.push(format!("light schedule of '{}'", z.name));
+                }
+                (None, Some(_)) => restart.push(format!("light schedule of '{}'", z.name)),
+                (None, None) => {}
+            }
+        }
+    }
+
+    if !applied.is_empty() {
+        println!("[config] applied {}", applied.join(", "));
+    }
+    if !restart.is_empty() {
+        println!("[config] restart to apply {}", restart.join(", "));
+    }
+    Ok(cfg)
+}
+
+// Top-level keys whose values differ; sensor calibration and zone light
+// schedules count as keys of their own
+fn changed_keys(old: &AppConfig, new: &AppConfig) -> Vec<String> {
+    // The implicit sensor counts as listed, so calibrating it isn't a new sensor
+    let table = |cfg: &AppConfig| {
+        let cfg = AppConfig { sensors: cfg.sensor_list(), ..cfg.clone() };
+        match toml::Value::try_from(&cfg) {
+            Ok(toml::Value::Table(t)) => t,
+            _ => toml::Table::new(),
+        }
+    };
+    let (mut old, mut new) = (table(old), table(new));
+    let mut changed = Vec::new();
+    // Move dry/wet out of [[sensors]] so recalibrating isn't a sensor change
+    let calibration = |t: &mut toml::Table| -> Vec<(Option<toml::Value>, Option<toml::Value>)> {
+        let Some(toml::Value::Array(list)) = t.get_mut("sensors") else { return Vec::new() };
+        list.iter_mut()
+            .filter_map(|s| s.as_table_mut())
+            .map(|s| (s.remove("dry"), s.remove("wet")))
+            .collect()
+    };
+    if calibration(&mut old) != calibration(&mut new) {
+        changed.push("sensor calibration".to_string());
+    }
+    // Same for the light schedules in [[zones]]
+    let schedules = |t: &mut toml::Table| -> Vec<(Option<toml::Value>, Option<toml::Value>)> {
+        let Some(toml::Value::Array(list)) = t.get_mut("zones") else { return Vec::new() };
+        list.iter_mut()
+            .filter_map(|z| z.as_table_mut())
+            .map(|z| (z.remove("schedule"), z.remove("schedule_rules")))
+            .collect()
+    };
+    if schedules(&mut old) != schedules(&mut new) {
+        changed.push("zone schedules".to_string());
+    }
+    let mut keys: Vec<&String> = old.keys().chain(new.keys()).collect();
+    keys.sort();
+    keys.dedup();
+    changed.extend(keys.into_iter().filter(|k| old.get(*k) != new.get(*k)).cloned());
+    changed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn changes_are_split_into_live_and_restart_keys() {
+        let old = AppConfig::default();
+        let mut new = old.clone();
+        assert!(changed_keys(&old, &new).is_empty());
+        new.interval_ms = 250;
+        new.gpio_pin = 5;
+        new.schedule = Some(Default::default());
+        assert_eq!(changed_keys(&old, &new), ["gpio_pin", "interval_ms", "schedule"]);
+        assert!(!LIVE_KEYS.contains(&"gpio_pin"));
+
+        let old: AppConfig = toml::from_str("[[sensors]]\nname = \"basil\"\nchannel = \"a0\"\ndry = 26000\n").unwrap();
+        let mut new = old.clone();
+        new.set_calibration("basil", None, Some(12000));
+        assert_eq!(changed_keys(&old, &new), ["sensor calibration"]);
+        new.sensors[0].channel = crate::analog::ads1115::Channel::A1;
+        assert_eq!(changed_keys(&old, &new), ["sensor calibration", "sensors"]);
+
+        let old = AppConfig::default();
+        let mut new = old.clone();
+        new.set_calibration(crate::config::DEFAULT_SENSOR, Some(26000), None);
+        assert_eq!(changed_keys(&old, &new), ["sensor calibration"]);
+
+        let old: AppConfig =
+            toml::from_str("[[zones]]\nname = \"basil\"\nsensor = \"moisture\"\npump_pin = 22\nlight_pin = 5\n").unwrap();
+        let mut new = old.clone();
+        new.zones[0].schedule_rules.push(crate::config::ScheduleRule { every_hours: Some(6), secs: 30, ..Default::default() });
+        assert_eq!(changed_keys(&old, &new), ["zone schedules"]);
+        new.zones[0].pump_pin = 23;
+        assert_eq!(changed_keys(&old, &new), ["zone schedules", "zones"]);
+    }
+}
@@ -0,0 +1,238 @@
+// Simulated plants for development without a Pi. Every moisture sensor reads a
+// soil model through an in-memory ADS1115; the soil dries out over time and
+// gets wetter while one of its pump outputs is on. Outputs become virtual lines, so the
+// real controllers, schedules and safety limits run unchanged.
+
+use crate::analog::ads1115::Address;
+use crate::analog::fake::FakeBus;
+use crate::clock;
+use crate::config::{AppConfig, SensorConfig, SimConfig};
+use crate::gpio::registry;
+use crate::gpio::shutdown::{self, WorkerHandle};
+use std::collections::HashMap;
+use std::sync::{Mutex, MutexGuard, OnceLock};
+use std::time::Duration;
+
+/// Calibration for sensors that have none, so the sim reports percentages
+/// out of the box; same direction as the real probes (wetter = higher raw).
+pub const DEFAULT_CALIBRATION: (i16, i16) = (15000, 27000);
+
+const TICK: Duration = Duration::from_millis(100);
+
+/// Moisture of one simulated pot, in percent.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Soil {
+    pub percent: f32,
+}
+
+impl Soil {
+    /// Advance by `dt` of simulated time: evaporation always, water while pumping.
+    pub fn step(&mut self, dt: Duration, pumping: bool, cfg: &SimConfig) {
+        let secs = dt.as_secs_f32();
+        let mut delta = -cfg.dry_per_hour * secs / 3600.0;
+        if pumping {
+            delta += cfg.wet_per_minute * secs / 60.0;
+        }
+        self.percent = (self.percent + delta).clamp(0.0, 100.0);
+    }
+
+    /// Raw ADC value a probe with this calibration would read.
+    pub fn raw(&self, (dry, wet): (i16, i16)) -> i16 {
+        (dry as f32 + (wet as f32 - dry as f32) * self.percent / 100.0).round() as i16
+    }
+}
+
+struct Pot {
+    sensor: SensorConfig,
+    // Outputs that water this pot
+    pumps: Vec<u8>,
+    address: Address,
+    soil: Soil,
+}
+
+impl Pot {
+    fn calibration(&self) -> (i16, i16) {
+        self.sensor.calibration().filter(|(d, w)| d != w).unwrap_or(DEFAULT_CALIBRATION)
+    }
+}
+
+pub struct Sim {
+    cfg: SimConfig,
+    // Every pump output, for the start-up line
+    pumps: Vec<u8>,
+    pots: Mutex<Vec<Pot>>,
+    buses: Mutex<HashMap<Address, FakeBus>>,
+    // Simulated time since start-up
+    elapsed: Mutex<Duration>,
+}
+
+impl Sim {
+    fn new(app: &AppConfig) -> Self {
+        let cfg = app.sim.clone();
+        let zones = app.zone_list();
+        // Without `pump_pins` a zone's pump waters its own pot; schedule and light pins never water
+        let pumps_of = |sensor: &str| -> Vec<u8> {
+            if !cfg.pump_pins.is_empty() {
+                return cfg.pump_pins.clone();
+            }
+            zones.iter().filter(|z| z.sensor == sensor).map(|z| z.pump_pin).collect()
+        };
+        let pots: Vec<Pot> = app
+            .sensor_list()
+            .into_iter()
+            .map(|s| Pot {
+                address: s.address.unwrap_or(app.adc.address),
+                pumps: pumps_of(&s.name),
+                sensor: s,
+                soil: Soil { percent: cfg.start_percent },
+            })
+            .collect();
+        let mut pumps: Vec<u8> = pots.iter().flat_map(|p| p.pumps.iter().copied()).collect();
+        pumps.sort_unstable();
+        pumps.dedup();
+        Self { cfg, pumps, pots: Mutex::new(pots), buses: Mutex::new(HashMap::new()), elapsed: Mutex::new(Duration::ZERO) }
+    }
+
+    /// The simulated ADS1115 at `address`, with its inputs set from the model.
+    pub fn bus(&self, address: Address) -> FakeBus {
+        let mut buses = lock(&self.buses);
+        let bus = buses.entry(address).or_insert_with(|| FakeBus::new(address.value())).clone();
+        drop(buses);
+        self.update_inputs();
+        bus
+    }
+
+    pub fn speed(&self) -> f64 {
+        self.cfg.speed
+    }
+
+    /// Simulated time since start-up.
+    pub fn elapsed(&self) -> Duration {
+        *lock(&self.elapsed)
+    }
+
+    fn tick(&self, dt: Duration) {
+        let on: Vec<u8> = registry::global().states().into_iter().filter(|(_, on)| *on).map(|(pin, _)| pin).collect();
+        self.step(dt, &on);
+    }
+
+    // Advance the model by `dt` with the outputs in `on` lit
+    fn step(&self, dt: Duration, on: &[u8]) {
+        *lock(&self.elapsed) += dt;
+        for pot in lock(&self.pots).iter_mut() {
+            let pumping = pot.pumps.iter().any(|p| on.contains(p));
+            pot.soil.step(dt, pumping, &self.cfg);
+        }
+        self.update_inputs();
+    }
+
+    fn update_inputs(&self) {
+        let buses = lock(&self.buses);
+        for pot in lock(&self.pots).iter() {
+            if let Some(bus) = buses.get(&pot.address) {
+                bus.set_input(pot.sensor.channel.mux(), pot.soil.raw(pot.calibration()));
+            }
+        }
+    }
+}
+
+static GLOBAL: OnceLock<Sim> = OnceLock::new();
+
+/// Switch the process to simulated hardware. Call before any pin or sensor is opened.
+pub fn init(cfg: &AppConfig) {
+    let _ = GLOBAL.set(Sim::new(cfg));
+}
+
+/// The simulation, if running in sim mode.
+pub fn global() -> Option<&'static Sim> {
+    GLOBAL.get()
+}
+
+/// Advance the soil model in the background until shutdown, on the process clock
+/// (accelerated by `speed` in sim mode).
+pub fn start() -> Option<WorkerHandle> {
+    let sim = global()?;
+    println!(
+        "[sim] speed x{} pumps={:?} dry {}%/h wet {}%/min",
+        sim.cfg.speed, sim.pumps, sim.cfg.dry_per_hour, sim.cfg.wet_per_minute
+    );
+    Some(shutdown::global().spawn("sim", move |stop| {
+        let clock = clock::global();
+        // One simulated second per step, so a short dose isn't rounded up to a whole tick
+        let tick = clock.wall(Duration::from_secs(1)).clamp(Duration::from_millis(1), TICK);
+        let mut last = clock.instant();
+        while stop.wait(tick) {
+            let now = clock.instant();
+            sim.tick(now - last);
+            last = now;
+        }
+    }))
+}
+
+fn lock<T>(m: &Mutex<T>) -> MutexGuard<'_, T> {
+    m.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn soil_dries_and_pump_wets() {
+        let cfg = SimConfig::default();
+        let mut soil = Soil { percent: 50.0 };
+        soil.step(Duration::from_secs(3600), false, &cfg);
+        assert!((soil.percent - 48.0).abs() < 1e-3);
+        soil.step(Duration::from_secs(60), true, &cfg);
+        assert!(soil.percent > 57.9 && soil.percent < 58.0);
+        soil.step(Duration::from_secs(3600), true, &cfg);
+        assert_eq!(soil.percent, 100.0);
+        assert_eq!(soil.raw(DEFAULT_CALIBRATION), 27000);
+        assert_eq!(Soil { percent: 25.0 }.raw((26000, 12000)), 22500);
+    }
+
+    #[test]
+    fn sensors_read_the_model() {
+        let mut app = AppConfig::default();
+        app.set_calibration(crate::config::DEFAULT_SENSOR, Some(10000), Some(20000));
+        let sim = Sim::new(&app);
+        let bus = sim.bus(Address::Gnd);
+        let mut adc = crate::analog::Ads1115::with_bus(bus, Address::Gnd, Default::default(), Default::default());
+        assert_eq!(adc.read_channel(crate::analog::Channel::A3).unwrap(), 15000);
+        sim.tick(Duration::from_secs(1800));
+        assert_eq!(adc.read_channel(crate::analog::Channel::A3).unwrap(), 14900);
+        assert_eq!(sim.elapsed(), Duration::from_secs(1800));
+    }
+
+    #[test]
+    fn zone_pumps_water_their_own_pot() {
+        let app: AppConfig = toml::from_str(
+            "[[sensors]]\nname = \"basil\"\nchannel = \"a0\"\n[[sensors]]\nname = \"mint\"\nchannel = \"a1\"\n\
+             [[zones]]\nname = \"basil\"\nsensor = \"basil\"\npump_pin = 22\n\
+             [[zones]]\nname = \"mint\"\nsensor = \"mint\"\npump_pin = 23\n",
+        )
+        .unwrap();
+        let sim = Sim::new(&app);
+        assert_eq!(sim.pumps, [22, 23]);
+        let pots = lock(&sim.pots);
+        assert_eq!(pots[0].pumps, [22]);
+        assert_eq!(pots[1].pumps, [23]);
+    }
+
+    #[test]
+    fn lights_and_schedules_do_not_water() {
+        let mut app: AppConfig = toml::from_str(
+            "schedule_pin = 27\nschedule = { Mon = [[600, 2000]] }\n\
+             [[sensors]]\nname = \"basil\"\nchannel = \"a0\"\n\
+             [[zones]]\nname = \"basil\"\nsensor = \"basil\"\npump_pin = 22\nlight_pin = 5\nschedule = { Mon = [[600, 2000]] }\n",
+        )
+        .unwrap();
+        app.sim.dry_per_hour = 0.0;
+        let sim = Sim::new(&app);
+        assert_eq!(sim.pumps, [22]);
+        sim.step(Duration::from_secs(600), &[5, 27]);
+        assert_eq!(lock(&sim.pots)[0].soil.percent, app.sim.start_percent);
+        sim.step(Duration::from_secs(60), &[22]);
+        assert!(lock(&sim.pots)[0].soil.percent > app.sim.start_percent);
+    }
+}
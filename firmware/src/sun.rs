@@ -0,0 +1,93 @@
+// Sunrise and sunset from latitude/longitude, computed locally (no network).
+// Uses the sunrise equation with the usual -0.833° for refraction and the
+// sun's radius; good to a minute or two away from the poles.
+
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Utc};
+use std::f64::consts::PI;
+
+// Julian day of 1970-01-01 00:00 UTC, and of the J2000 epoch
+const JD_UNIX: f64 = 2_440_587.5;
+const J2000: f64 = 2_451_545.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Location {
+    // Degrees, north positive
+    pub latitude: f64,
+    // Degrees, east positive
+    pub longitude: f64,
+}
+
+impl Location {
+    /// Sunrise and sunset on local date `day`, as seconds from local midnight
+    /// (may fall outside 0..86400 far from the time zone's meridian).
+    /// None during polar day or night.
+    pub fn local_times(&self, day: NaiveDate) -> Option<(i64, i64)> {
+        let (rise, set) = times_utc(day, self.latitude, self.longitude)?;
+        let midnight = day.and_time(NaiveTime::MIN);
+        let local = |t: NaiveDateTime| (Utc.from_utc_datetime(&t).with_timezone(&Local).naive_local() - midnight).num_seconds();
+        Some((local(rise), local(set)))
+    }
+}
+
+/// Sunrise and sunset in UTC for the solar day of `date` at this longitude.
+pub fn times_utc(date: NaiveDate, latitude: f64, longitude: f64) -> Option<(NaiveDateTime, NaiveDateTime)> {
+    let rad = PI / 180.0;
+    let jd_midnight = date.and_time(NaiveTime::MIN).and_utc().timestamp() as f64 / 86400.0 + JD_UNIX;
+    // Days since J2000 at mean solar noon here
+    let n = (jd_midnight - J2000 + 0.0008).ceil();
+    let j_star = n - longitude / 360.0;
+    let m = (357.5291 + 0.985_600_28 * j_star).rem_euclid(360.0);
+    let c = 1.9148 * (m * rad).sin() + 0.02 * (2.0 * m * rad).sin() + 0.0003 * (3.0 * m * rad).sin();
+    let lambda = (m + c + 180.0 + 102.9372).rem_euclid(360.0);
+    let transit = J2000 + j_star + 0.0053 * (m * rad).sin() - 0.0069 * (2.0 * lambda * rad).sin();
+    let sin_decl = (lambda * rad).sin() * (23.4397 * rad).sin();
+    let cos_decl = sin_decl.asin().cos();
+    let cos_hour = ((-0.833 * rad).sin() - (latitude * rad).sin() * sin_decl) / ((latitude * rad).cos() * cos_decl);
+    if !(-1.0..=1.0).contains(&cos_hour) {
+        return None;
+    }
+    let half_day = cos_hour.acos() / rad / 360.0;
+    let utc = |jd: f64| DateTime::from_timestamp(((jd - JD_UNIX) * 86400.0).round() as i64, 0).map(|t| t.naive_utc());
+    Some((utc(transit - half_day)?, utc(transit + half_day)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hm(t: NaiveDateTime) -> String {
+        t.format("%Y-%m-%d %H:%M").to_string()
+    }
+
+    // Reference times in UTC: London's published solstice times, and equinox
+    // days worked out from the equation of time and a ~12h08m day
+    #[test]
+    fn known_dates_match_published_times() {
+        let cases = [
+            // London, summer and winter solstice
+            (2024, 6, 21, 51.5074, -0.1278, "2024-06-21 03:43", "2024-06-21 20:21"),
+            (2024, 12, 21, 51.5074, -0.1278, "2024-12-21 08:04", "2024-12-21 15:53"),
+            // Sydney: sunrise is still the previous day in UTC
+            (2024, 3, 20, -33.8688, 151.2093, "2024-03-19 19:59", "2024-03-20 08:07"),
+            // New York, equinox
+            (2024, 9, 22, 40.7128, -74.0060, "2024-09-22 10:44", "2024-09-22 22:53"),
+        ];
+        for (y, m, d, lat, lon, rise, set) in cases {
+            let (r, s) = times_utc(NaiveDate::from_ymd_opt(y, m, d).unwrap(), lat, lon).unwrap();
+            let close = |got: NaiveDateTime, want: &str| {
+                let want = NaiveDateTime::parse_from_str(want, "%Y-%m-%d %H:%M").unwrap();
+                (got - want).num_seconds().abs() <= 120
+            };
+            assert!(close(r, rise), "sunrise {} at {},{}: got {}, want {}", d, lat, lon, hm(r), rise);
+            assert!(close(s, set), "sunset {} at {},{}: got {}, want {}", d, lat, lon, hm(s), set);
+        }
+    }
+
+    #[test]
+    fn polar_day_and_night_have_no_sunrise() {
+        // Tromsø
+        assert!(times_utc(NaiveDate::from_ymd_opt(2024, 6, 21).unwrap(), 69.65, 18.96).is_none());
+        assert!(times_utc(NaiveDate::from_ymd_opt(2024, 12, 21).unwrap(), 69.65, 18.96).is_none());
+        assert!(times_utc(NaiveDate::from_ymd_opt(2024, 3, 21).unwrap(), 69.65, 18.96).is_some());
+    }
+}
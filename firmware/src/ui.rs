@@ -1,12 +1,14 @@
-use crate::config::{save_config, AppConfig};
-use crate::gpio::GpioController;
-use crate::analog::Ads1115;
-use crossterm::event::{self, Event, KeyCode};
+use crate::config::{update_config, AppConfig};
+use crate::gpio::{registry, safety, shutdown, GpioController, ScheduleRppalGpioController};
+use crate::zone::Zone;
+use crate::{clock, sim};
+use crate::analog::MoistureProbe;
+use crossterm::event::{self, Event, KeyCode, KeyModifiers};
 use crossterm::terminal;
 use crossterm::{cursor, execute, style, terminal::ClearType};
 use std::error::Error;
 use std::io::{stdout, Write};
-use std::sync::{Arc, Mutex};
+use std::sync::Arc;
 use std::time::Duration;
 
 // Compact TUI: fixed-position lines and short text
@@ -43,7 +45,7 @@ fn toggle_blink(ctl: &dyn GpioController, cfg: &mut AppConfig) {
     let new = !ctl.is_blink();
     ctl.set_blink(new);
     cfg.blink_on = new;
-    let _ = save_config(cfg);
+    let _ = update_config(|c| c.blink_on = new);
 }
 
 fn adjust_interval(ctl: &dyn GpioController, cfg: &mut AppConfig, delta: i64) {
@@ -52,37 +54,45 @@ fn adjust_interval(ctl: &dyn GpioController, cfg: &mut AppConfig, delta: i64) {
     let next = clamp_interval(next);
     ctl.set_interval_ms(next);
     cfg.interval_ms = next;
-    let _ = save_config(cfg);
+    let _ = update_config(|c| c.interval_ms = next);
 }
 
 pub fn run(
     ctl: Arc<dyn GpioController + Send + Sync>,
     mut cfg: AppConfig,
-    sensor: Option<Arc<Mutex<Ads1115>>>,
+    sensors: Vec<Arc<MoistureProbe>>,
+    zones: Vec<Arc<Zone>>,
+    schedule: Option<Arc<ScheduleRppalGpioController>>,
 ) -> Result<(), Box<dyn Error>> {
     let mut out = stdout();
     terminal::enable_raw_mode()?;
     execute!(out, terminal::EnterAlternateScreen, cursor::Hide)?;
 
     let mut running = true;
+    // Sensor that d/w calibrate; Tab moves to the next one
+    let mut selected = 0;
     loop {
-        draw_ui(&ctl, cfg.gpio_pin, cfg.invert, &sensor, &cfg)?;
+        draw_ui(&ctl, &cfg, &sensors, selected, &zones, &schedule)?;
 
         if event::poll(Duration::from_millis(200))? {
             if let Event::Key(k) = event::read()? {
                 match k.code {
                     KeyCode::Char('q') | KeyCode::Esc => running = false,
+                    // Raw mode swallows SIGINT, so handle Ctrl-C as a key
+                    KeyCode::Char('c') if k.modifiers.contains(KeyModifiers::CONTROL) => running = false,
                     KeyCode::Char('b') => toggle_blink(ctl.as_ref(), &mut cfg),
                     KeyCode::Char('+') => adjust_interval(ctl.as_ref(), &mut cfg, -100),
                     KeyCode::Char('-') => adjust_interval(ctl.as_ref(), &mut cfg, 100),
-                    KeyCode::Char('d') => calibrate_dry(&sensor, &mut cfg),
-                    KeyCode::Char('w') => calibrate_wet(&sensor, &mut cfg),
+                    KeyCode::Tab if !sensors.is_empty() => selected = (selected + 1) % sensors.len(),
+                    KeyCode::Char('d') => calibrate(sensors.get(selected), &mut cfg, true),
+                    KeyCode::Char('w') => calibrate(sensors.get(selected), &mut cfg, false),
+                    KeyCode::Char('a') => safety::global().acknowledge_all(),
                     _ => {}
                 }
             }
         }
 
-        if !running { break; }
+        if !running || shutdown::global().is_requested() { break; }
     }
 
     execute!(out, terminal::LeaveAlternateScreen, cursor::Show)?;
@@ -90,29 +100,24 @@ pub fn run(
     Ok(())
 }
 
-fn calibrate_dry(sensor: &Option<Arc<Mutex<Ads1115>>>, cfg: &mut AppConfig) {
-    if let Some(sensor_arc) = sensor {
-        if let Ok(mut sensor_lock) = sensor_arc.try_lock() {
-            if let Ok(raw) = sensor_lock.read_moisture_sensor() {
-                cfg.moisture_dry_value = Some(raw);
-                let _ = save_config(cfg);
-            }
-        }
-    }
-}
-
-fn calibrate_wet(sensor: &Option<Arc<Mutex<Ads1115>>>, cfg: &mut AppConfig) {
-    if let Some(sensor_arc) = sensor {
-        if let Ok(mut sensor_lock) = sensor_arc.try_lock() {
-            if let Ok(raw) = sensor_lock.read_moisture_sensor() {
-                cfg.moisture_wet_value = Some(raw);
-                let _ = save_config(cfg);
-            }
+// Store the current raw reading as the dry (or wet) point of one sensor
+fn calibrate(sensor: Option<&Arc<MoistureProbe>>, cfg: &mut AppConfig, dry: bool) {
+    if let Some(probe) = sensor {
+        if let Some(Ok(r)) = probe.try_read() {
+            let (d, w) = if dry { (Some(r.raw), None) } else { (None, Some(r.raw)) };
+            cfg.set_calibration(probe.name(), d, w);
+            let cal = cfg.sensor_list().into_iter().find(|s| s.name == probe.name()).and_then(|s| s.calibration());
+            probe.set_calibration(cal);
+            let _ = update_config(|c| {
+                c.set_calibration(probe.name(), d, w);
+            });
         }
     }
 }
 
+// Kept next to the helpers it covers, ahead of the drawing code
 #[cfg(test)]
+#[allow(clippy::items_after_test_module)]
 mod tests {
     use super::*;
 
@@ -125,15 +130,23 @@ mod tests {
         assert_eq!(clamp_interval(10_000), 10_000);
         assert_eq!(clamp_interval(20_000), 10_000);
     }
+
+    #[test]
+    fn sim_time_shows_days_and_clock() {
+        assert_eq!(sim_time(Duration::from_secs(59)), "+0d 00:00");
+        assert_eq!(sim_time(Duration::from_secs(3 * 86400 + 5 * 3600 + 7 * 60)), "+3d 05:07");
+    }
 }
 
 fn draw_ui(
     ctl: &Arc<dyn GpioController + Send + Sync>,
-    pin: u8,
-    invert: bool,
-    sensor: &Option<Arc<Mutex<Ads1115>>>,
     cfg: &AppConfig,
+    sensors: &[Arc<MoistureProbe>],
+    selected: usize,
+    zones: &[Arc<Zone>],
+    schedule: &Option<Arc<ScheduleRppalGpioController>>,
 ) -> Result<(), Box<dyn Error>> {
+    let (pin, invert) = (cfg.gpio_pin, cfg.invert);
     let mut out = stdout();
 
     execute!(out, terminal::Clear(ClearType::All), cursor::MoveTo(0, 0))?;
@@ -151,32 +164,73 @@ fn draw_ui(
         "".to_string(),
     ];
 
-    // Moisture sensor readings
-    if let Some(sensor_arc) = sensor {
-        if let Ok(mut sensor_lock) = sensor_arc.try_lock() {
-            match sensor_lock.read_moisture_sensor() {
-                Ok(raw) => {
-                    let voltage = Ads1115::raw_to_voltage(raw);
-                    lines.push("Moisture Sensor (A3):".to_string());
-                    lines.push(format!("  Raw: {}", raw));
-                    lines.push(format!("  Voltage: {:.3}V", voltage));
-                    
-                    if let (Some(dry), Some(wet)) = (cfg.moisture_dry_value, cfg.moisture_wet_value) {
-                        let pct = Ads1115::raw_to_moisture_percent(raw, dry, wet);
-                        lines.push(format!("  Moisture: {:.1}%", pct));
-                        lines.push(format!("  Cal: D={} W={}", dry, wet));
-                    } else {
-                        lines.push("  [Not calibrated]".to_string());
-                    }
-                }
-                Err(e) => {
-                    lines.push(format!("Sensor err: {}", e));
+    if let Some(s) = sim::global() {
+        let now = clock::global().now().format("%a %H:%M");
+        lines.insert(1, format!("SIM x{} {} {}", s.speed(), sim_time(s.elapsed()), now));
+    }
+
+    // What each owned output is doing right now (virtual lines in sim mode)
+    let pins = registry::global().states();
+    if !pins.is_empty() {
+        let states: Vec<String> = pins.iter().map(|(p, on)| format!("{}:{}", p, if *on { "on" } else { "off" })).collect();
+        lines.push(format!("GPIO {}", states.join(" ")));
+        lines.push("".to_string());
+    }
+
+    // Which rule set the schedule follows today (overrides by date)
+    if let Some(s) = schedule {
+        let paused = if s.is_blink() { "" } else { " (off)" };
+        lines.push(format!("Sched GPIO {}: {}{}", cfg.schedule_pin, s.rule_set(), paused));
+        lines.push("".to_string());
+    }
+
+    // Each zone's pump and grow light
+    for z in zones {
+        match &z.watering {
+            Some(w) => lines.push(format!("{}: GPIO {} {}", z.name, z.pump_pin, w.state())),
+            None => lines.push(format!("{}: GPIO {} no sensor", z.name, z.pump_pin)),
+        }
+        if let (Some(l), Some(on)) = (&z.light, z.light_on()) {
+            let paused = if l.is_blink() { "" } else { " (disabled)" };
+            lines.push(format!("  light {}: {}{}", z.light_pin.unwrap_or_default(), if on { "on" } else { "off" }, paused));
+        }
+    }
+    if !zones.is_empty() {
+        lines.push("".to_string());
+    }
+
+    // Latched safety faults stay on screen until acknowledged with 'a'
+    let faults = safety::global().faults();
+    for (fpin, fault) in &faults {
+        lines.push(format!("FAULT GPIO {}: {}", fpin, fault));
+    }
+    if !faults.is_empty() {
+        lines.push("".to_string());
+    }
+
+    // Moisture sensor readings; '>' marks the one d/w calibrate
+    for (i, probe) in sensors.iter().enumerate() {
+        let mark = if i == selected && sensors.len() > 1 { '>' } else { ' ' };
+        lines.push(format!("{}{} ({}):", mark, probe.name(), probe.channel()));
+        match probe.try_read() {
+            Some(Ok(r)) => {
+                lines.push(format!("  Raw: {} {:.3}V", r.raw, r.voltage));
+                if let (Some(pct), Some((dry, wet))) = (r.percent, probe.calibration()) {
+                    lines.push(format!("  Moisture: {:.1}%", pct));
+                    lines.push(format!("  Cal: D={} W={}", dry, wet));
+                } else {
+                    lines.push("  [Not calibrated]".to_string());
                 }
             }
-        } else {
-            lines.push("Sensor: reading...".to_string());
+            Some(Err(e)) => {
+                lines.push(format!("  err: {}", e));
+            }
+            None => {
+                lines.push("  reading...".to_string());
+            }
         }
-    } else {
+    }
+    if sensors.is_empty() {
         lines.push("Sensor: N/A".to_string());
     }
 
@@ -187,8 +241,18 @@ fn draw_ui(
     lines.push("  +/-    - Interval ms".to_string());
     lines.push("  d      - Cal dry".to_string());
     lines.push("  w      - Cal wet".to_string());
+    if sensors.len() > 1 {
+        lines.push("  Tab    - Next sensor".to_string());
+    }
+    lines.push("  a      - Ack fault".to_string());
 
     draw_lines(&mut out, &lines)?;
     out.flush()?;
     Ok(())
-}
\ No newline at end of file
+}
+
+// Simulated time since start as "+<days>d hh:mm"
+fn sim_time(elapsed: Duration) -> String {
+    let m = elapsed.as_secs() / 60;
+    format!("+{}d {:02}:{:02}", m / 1440, m / 60 % 24, m % 60)
+}
@@ -0,0 +1,86 @@
+// The running parts of each zone: the pump that follows the zone's sensor,
+// timed manual doses on that pump, and the grow light on the zone's schedule.
+
+use crate::analog::probe::{self, MoistureProbe};
+use crate::build_light;
+use crate::clock;
+use crate::config::AppConfig;
+use crate::gpio::manual::{self, ManualDose};
+use crate::gpio::{self, registry, MoistureGpioController, ScheduleRppalGpioController};
+use std::sync::Arc;
+
+pub struct Zone {
+    pub name: String,
+    // For the http status
+    #[cfg_attr(not(feature = "http"), allow(dead_code))]
+    pub sensor: String,
+    pub pump_pin: u8,
+    pub light_pin: Option<u8>,
+    // One max_dose_secs, for the mqtt "water now" button
+    #[cfg_attr(not(feature = "mqtt"), allow(dead_code))]
+    pub dose_secs: u64,
+    // Unset when the zone's sensor didn't open; the pump then stays off
+    pub watering: Option<Arc<MoistureGpioController>>,
+    // Manual doses come from the remote front ends
+    #[cfg_attr(not(any(feature = "http", feature = "mqtt")), allow(dead_code))]
+    pub dose: Option<Arc<ManualDose>>,
+    pub light: Option<Arc<ScheduleRppalGpioController>>,
+}
+
+impl Zone {
+    /// Whether the grow light is lit right now; None without a running light.
+    pub fn light_on(&self) -> Option<bool> {
+        let pin = self.light_pin.filter(|_| self.light.is_some())?;
+        registry::global().states().into_iter().find(|(p, _)| *p == pin).map(|(_, on)| on)
+    }
+}
+
+/// Start every zone of `cfg` (see `AppConfig::zone_list`). A zone without its
+/// sensor still runs its light; a pin another controller owns is an error.
+pub fn start_all(cfg: &AppConfig, sensors: &[Arc<MoistureProbe>]) -> Result<Vec<Arc<Zone>>, String> {
+    let mut zones = Vec::new();
+    for (i, z) in cfg.zone_list().iter().enumerate() {
+        let watering = match probe::find(sensors, &z.sensor) {
+            Some(source) => {
+                let ctl = MoistureGpioController::new(cfg.invert, &z.watering(), Arc::clone(source), clock::global())
+                    .map_err(|e| format!("zone '{}': {}", z.name, e))?;
+                println!("[startup] zone '{}' watering on GPIO {} from sensor '{}'", z.name, z.pump_pin, z.sensor);
+                Some(Arc::new(ctl))
+            }
+            None => {
+                println!("[startup] zone '{}' watering disabled: no moisture sensor '{}'", z.name, z.sensor);
+                None
+            }
+        };
+        let dose = watering.as_ref().map(|w| Arc::new(ManualDose::new(w.pin(), clock::global())));
+        let light = match (z.light_pin, build_light(cfg, i)?) {
+            (Some(pin), Some(s)) if gpio::available() => {
+                let ctl = ScheduleRppalGpioController::new(pin, cfg.invert, s, clock::global())
+                    .map_err(|e| format!("zone '{}': {}", z.name, e))?;
+                println!("[startup] zone '{}' light on GPIO {}", z.name, pin);
+                Some(Arc::new(ctl))
+            }
+            _ => None,
+        };
+        zones.push(Arc::new(Zone {
+            name: z.name.clone(),
+            sensor: z.sensor.clone(),
+            pump_pin: z.pump_pin,
+            light_pin: z.light_pin,
+            dose_secs: z.max_dose_secs.clamp(1, manual::MAX_SECS),
+            watering,
+            dose,
+            light,
+        }));
+    }
+    Ok(zones)
+}
+
+/// The zone called `name`, or the first one when no name is given.
+#[cfg_attr(not(feature = "http"), allow(dead_code))]
+pub fn find<'a>(zones: &'a [Arc<Zone>], name: Option<&str>) -> Option<&'a Arc<Zone>> {
+    match name {
+        Some(name) => zones.iter().find(|z| z.name == name),
+        None => zones.first(),
+    }
+}
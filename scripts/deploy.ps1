@@ -10,6 +10,8 @@ Quick usage (build locally with cross + Docker, then upload & run):
 Defaults:
   -PiHost plants-love-rust, -PiUser user, -KeyPath scripts/id_rsa_plants
   Upload path on Pi: ~/plants-love-rust/firmware/target/release
+  The firmware's config lives in ~/.config/plants-love-rust/config.toml (or
+  /etc/plants-love-rust/), outside every path this script uploads to.
 
 Requires:
   - PowerShell 7+ (pwsh)